@@ -1,32 +1,75 @@
-use protobuf::{pandorica_auth, pandorica_user};
+use protobuf::pandorica_file::{download_response, upload_request};
+use protobuf::{pandorica_admin, pandorica_auth, pandorica_common, pandorica_file, pandorica_user};
 use shared::error::{EmptyResult, OperationResult};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Channel;
 use tonic::Request;
 
+use crate::e2e::{self, Decryptor, Encryptor, Keyring};
+use crate::opaque::PasswordProof;
+
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Tells the server which client and device a new session is for, so that it can be told apart
+/// from the user's other sessions.
+fn add_client_metadata<T>(request: &mut Request<T>, device_name: Option<&str>) {
+    let metadata = request.metadata_mut();
+    metadata.insert(
+        "x-client-version",
+        env!("CARGO_PKG_VERSION").parse().unwrap(),
+    );
+    if let Some(device_name) = device_name.and_then(|d| d.parse().ok()) {
+        metadata.insert("x-device-name", device_name);
+    }
+}
+
 pub async fn login(
     url: String,
     username: String,
     password: String,
+    device_name: Option<&str>,
 ) -> OperationResult<pandorica_auth::AuthResponse> {
     let channel = Channel::from_shared(url)?.connect().await?;
 
     let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
 
-    let request = Request::new(pandorica_auth::LoginRequest { username, password });
+    let mut request = Request::new(pandorica_auth::LoginRequest { username, password });
+    add_client_metadata(&mut request, device_name);
 
     let response = client.login(request).await?;
 
     Ok(response.into_inner())
 }
 
-pub async fn logout(url: String, session_id: &str) -> EmptyResult {
+pub async fn refresh(
+    url: String,
+    refresh_token: String,
+    device_name: Option<&str>,
+) -> OperationResult<pandorica_auth::AuthResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let mut request = Request::new(pandorica_auth::RefreshRequest { refresh_token });
+    add_client_metadata(&mut request, device_name);
+
+    let response = client.refresh(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn logout(url: String, session_token: &str) -> EmptyResult {
     let channel = Channel::from_shared(url)?.connect().await?;
 
     let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
         channel,
         move |mut req: Request<()>| {
-            req.metadata_mut()
-                .insert("session_id", session_id.parse().unwrap());
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
             Ok(req)
         },
     );
@@ -38,14 +81,422 @@ pub async fn logout(url: String, session_id: &str) -> EmptyResult {
     Ok(())
 }
 
-pub async fn me(url: String, session_id: &str) -> OperationResult<pandorica_user::MeResponse> {
+pub async fn verify_mfa(
+    url: String,
+    session_token: &str,
+    code: String,
+    device_name: Option<&str>,
+) -> OperationResult<pandorica_auth::AuthResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let mut request = Request::new(pandorica_auth::VerifyMfaRequest { code });
+    add_client_metadata(&mut request, device_name);
+
+    let response = client.verify_mfa(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn enroll_totp(
+    url: String,
+    session_token: &str,
+    password: PasswordProof,
+) -> OperationResult<pandorica_auth::EnrollTotpResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let (password, opaque_proof) = password.into_fields();
+    let request = Request::new(pandorica_auth::EnrollTotpRequest {
+        password,
+        opaque_proof,
+    });
+
+    let response = client.enroll_totp(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn confirm_totp(url: String, session_token: &str, code: String) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_auth::ConfirmTotpRequest { code });
+
+    client.confirm_totp(request).await?;
+
+    Ok(())
+}
+
+pub async fn disable_totp(
+    url: String,
+    session_token: &str,
+    password: PasswordProof,
+    code: String,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let (password, opaque_proof) = password.into_fields();
+    let request = Request::new(pandorica_auth::DisableTotpRequest {
+        password,
+        code,
+        opaque_proof,
+    });
+
+    client.disable_totp(request).await?;
+
+    Ok(())
+}
+
+pub async fn opaque_registration_start(
+    url: String,
+    username: String,
+    registration_request: Vec<u8>,
+) -> OperationResult<Vec<u8>> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let request = Request::new(pandorica_auth::OpaqueRegistrationStartRequest {
+        username,
+        registration_request,
+    });
+
+    let response = client.opaque_registration_start(request).await?;
+
+    Ok(response.into_inner().registration_response)
+}
+
+pub async fn opaque_register(
+    url: String,
+    username: String,
+    email: Option<String>,
+    registration_upload: Vec<u8>,
+    keyring: Option<pandorica_common::Keyring>,
+    recovery_key: Option<pandorica_common::RecoveryKey>,
+    device_name: Option<&str>,
+) -> OperationResult<pandorica_auth::AuthResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let mut request = Request::new(pandorica_auth::OpaqueRegisterRequest {
+        username,
+        email,
+        registration_upload,
+        keyring,
+        recovery_key,
+    });
+    add_client_metadata(&mut request, device_name);
+
+    let response = client.opaque_register(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn opaque_login_start(
+    url: String,
+    username: String,
+    credential_request: Vec<u8>,
+) -> OperationResult<pandorica_auth::OpaqueLoginStartResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let request = Request::new(pandorica_auth::OpaqueLoginStartRequest {
+        username,
+        credential_request,
+    });
+
+    let response = client.opaque_login_start(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn opaque_login_finish(
+    url: String,
+    login_id: String,
+    credential_finalization: Vec<u8>,
+    device_name: Option<&str>,
+) -> OperationResult<pandorica_auth::AuthResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let mut request = Request::new(pandorica_auth::OpaqueLoginFinishRequest {
+        login_id,
+        credential_finalization,
+    });
+    add_client_metadata(&mut request, device_name);
+
+    let response = client.opaque_login_finish(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn opaque_password_start(
+    url: String,
+    session_token: &str,
+    registration_request: Vec<u8>,
+) -> OperationResult<pandorica_auth::OpaqueRegistrationStartResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_auth::OpaquePasswordStartRequest {
+        registration_request,
+    });
+
+    let response = client.opaque_password_start(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn opaque_set_password(
+    url: String,
+    session_token: &str,
+    current_password: PasswordProof,
+    registration_upload: Vec<u8>,
+    keyring: Option<pandorica_common::Keyring>,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let (current_password, opaque_proof) = current_password.into_fields();
+    let request = Request::new(pandorica_auth::OpaqueSetPasswordRequest {
+        current_password,
+        registration_upload,
+        keyring,
+        opaque_proof,
+    });
+
+    client.opaque_set_password(request).await?;
+
+    Ok(())
+}
+
+pub async fn recover_account_start(
+    url: String,
+    username: String,
+    registration_request: Vec<u8>,
+) -> OperationResult<pandorica_auth::RecoverAccountStartResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let request = Request::new(pandorica_auth::RecoverAccountStartRequest {
+        username,
+        registration_request,
+    });
+
+    let response = client.recover_account_start(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn recover_account(
+    url: String,
+    request: pandorica_auth::RecoverAccountRequest,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    client.recover_account(Request::new(request)).await?;
+
+    Ok(())
+}
+
+pub async fn verify_email(url: String, token: String) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let request = Request::new(pandorica_auth::VerifyEmailRequest { token });
+
+    client.verify_email(request).await?;
+
+    Ok(())
+}
+
+pub async fn request_password_reset(url: String, username: String) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let request = Request::new(pandorica_auth::RequestPasswordResetRequest { username });
+
+    client.request_password_reset(request).await?;
+
+    Ok(())
+}
+
+pub async fn reset_password_start(
+    url: String,
+    token: String,
+    registration_request: Vec<u8>,
+) -> OperationResult<Vec<u8>> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let request = Request::new(pandorica_auth::ResetPasswordStartRequest {
+        token,
+        registration_request,
+    });
+
+    let response = client.reset_password_start(request).await?;
+
+    Ok(response.into_inner().registration_response)
+}
+
+pub async fn reset_password(
+    url: String,
+    token: String,
+    registration_upload: Vec<u8>,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::new(channel);
+
+    let request = Request::new(pandorica_auth::ResetPasswordRequest {
+        token,
+        registration_upload,
+    });
+
+    client.reset_password(request).await?;
+
+    Ok(())
+}
+
+/// Returns `None` for an account that is still on a password the server checks itself.
+pub async fn opaque_reauthenticate_start(
+    url: String,
+    session_token: &str,
+    credential_request: Vec<u8>,
+) -> OperationResult<Option<pandorica_auth::OpaqueLoginStartResponse>> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request =
+        Request::new(pandorica_auth::OpaqueReauthenticateStartRequest { credential_request });
+
+    match client.opaque_reauthenticate_start(request).await {
+        Ok(response) => Ok(Some(response.into_inner())),
+        Err(status) if status.message() == "opaque_not_registered" => Ok(None),
+        Err(status) => Err(status.into()),
+    }
+}
+
+pub async fn opaque_reauthenticate_finish(
+    url: String,
+    session_token: &str,
+    login_id: String,
+    credential_finalization: Vec<u8>,
+) -> OperationResult<String> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_auth::auth_service_client::AuthServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_auth::OpaqueLoginFinishRequest {
+        login_id,
+        credential_finalization,
+    });
+
+    let response = client.opaque_reauthenticate_finish(request).await?;
+
+    Ok(response.into_inner().password_proof)
+}
+
+pub async fn me(url: String, session_token: &str) -> OperationResult<pandorica_user::MeResponse> {
     let channel = Channel::from_shared(url)?.connect().await?;
 
     let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
         channel,
         move |mut req: Request<()>| {
-            req.metadata_mut()
-                .insert("session_id", session_id.parse().unwrap());
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
             Ok(req)
         },
     );
@@ -56,3 +507,586 @@ pub async fn me(url: String, session_id: &str) -> OperationResult<pandorica_user
 
     Ok(response.into_inner())
 }
+
+pub async fn delete_account(
+    url: String,
+    session_token: &str,
+    password: PasswordProof,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let (password, opaque_proof) = password.into_fields();
+    let request = Request::new(pandorica_user::DeleteRequest {
+        password,
+        opaque_proof,
+    });
+
+    client.delete(request).await?;
+
+    Ok(())
+}
+
+pub async fn get_keyring(
+    url: String,
+    session_token: &str,
+) -> OperationResult<Option<pandorica_common::Keyring>> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_user::GetKeyringRequest {});
+
+    let response = client.get_keyring(request).await?;
+
+    Ok(response.into_inner().keyring)
+}
+
+pub async fn set_keyring(
+    url: String,
+    session_token: &str,
+    password: PasswordProof,
+    keyring: pandorica_common::Keyring,
+    recovery_key: pandorica_common::RecoveryKey,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let (password, opaque_proof) = password.into_fields();
+    let request = Request::new(pandorica_user::SetKeyringRequest {
+        password,
+        opaque_proof,
+        keyring: Some(keyring),
+        recovery_key: Some(recovery_key),
+    });
+
+    client.set_keyring(request).await?;
+
+    Ok(())
+}
+
+pub async fn set_recovery_key(
+    url: String,
+    session_token: &str,
+    password: PasswordProof,
+    recovery_key: pandorica_common::RecoveryKey,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let (password, opaque_proof) = password.into_fields();
+    let request = Request::new(pandorica_user::SetRecoveryKeyRequest {
+        password,
+        opaque_proof,
+        recovery_key: Some(recovery_key),
+    });
+
+    client.set_recovery_key(request).await?;
+
+    Ok(())
+}
+
+pub async fn send_email_verification(url: String, session_token: &str) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_user::SendEmailVerificationRequest {});
+
+    client.send_email_verification(request).await?;
+
+    Ok(())
+}
+
+pub async fn revoke_session(url: String, session_token: &str, id: String) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_user::RevokeSessionRequest { id });
+
+    client.revoke_session(request).await?;
+
+    Ok(())
+}
+
+pub async fn revoke_other_sessions(url: String, session_token: &str) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_user::RevokeOtherSessionsRequest {});
+
+    client.revoke_other_sessions(request).await?;
+
+    Ok(())
+}
+
+pub async fn create_access_token(
+    url: String,
+    session_token: &str,
+    name: String,
+    scopes: Vec<String>,
+    lifetime_days: Option<u32>,
+) -> OperationResult<pandorica_user::CreateAccessTokenResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_user::CreateAccessTokenRequest {
+        name,
+        scopes,
+        lifetime_days,
+    });
+
+    let response = client.create_access_token(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn list_access_tokens(
+    url: String,
+    session_token: &str,
+) -> OperationResult<pandorica_user::ListAccessTokensResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_user::ListAccessTokensRequest {});
+
+    let response = client.list_access_tokens(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn revoke_access_token(url: String, session_token: &str, id: String) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_user::user_service_client::UserServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_user::RevokeAccessTokenRequest { id });
+
+    client.revoke_access_token(request).await?;
+
+    Ok(())
+}
+
+pub async fn list_users(
+    url: String,
+    session_token: &str,
+    query: Option<String>,
+    cursor: Option<String>,
+) -> OperationResult<pandorica_admin::ListUsersResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_admin::admin_service_client::AdminServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_admin::ListUsersRequest {
+        query,
+        cursor,
+        limit: 0,
+    });
+
+    let response = client.list_users(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub async fn set_user_active(
+    url: String,
+    session_token: &str,
+    id: String,
+    is_active: bool,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_admin::admin_service_client::AdminServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_admin::SetUserActiveRequest { id, is_active });
+
+    client.set_user_active(request).await?;
+
+    Ok(())
+}
+
+pub async fn set_user_role(
+    url: String,
+    session_token: &str,
+    id: String,
+    role: String,
+) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_admin::admin_service_client::AdminServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_admin::SetUserRoleRequest { id, role });
+
+    client.set_user_role(request).await?;
+
+    Ok(())
+}
+
+pub async fn force_password_reset(url: String, session_token: &str, id: String) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_admin::admin_service_client::AdminServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_admin::ForcePasswordResetRequest { id });
+
+    client.force_password_reset(request).await?;
+
+    Ok(())
+}
+
+pub async fn revoke_user_sessions(url: String, session_token: &str, id: String) -> EmptyResult {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_admin::admin_service_client::AdminServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_admin::RevokeUserSessionsRequest { id });
+
+    client.revoke_user_sessions(request).await?;
+
+    Ok(())
+}
+
+pub async fn master_key_status(
+    url: String,
+    session_token: &str,
+) -> OperationResult<pandorica_admin::GetMasterKeyStatusResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_admin::admin_service_client::AdminServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_admin::GetMasterKeyStatusRequest {});
+
+    let response = client.get_master_key_status(request).await?;
+
+    Ok(response.into_inner())
+}
+
+/// Encrypts the file end to end first if there is a keyring.
+pub async fn upload(
+    url: String,
+    session_token: &str,
+    path: &str,
+    keyring: Option<&Keyring>,
+) -> OperationResult<pandorica_file::UploadResponse> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_file::file_service_client::FileServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let name = std::path::Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut file = tokio::fs::File::open(path).await?;
+    let length = file.metadata().await?.len();
+
+    let (tx, rx) = mpsc::channel(4);
+    tx.send(pandorica_file::UploadRequest {
+        data: Some(upload_request::Data::Name(name)),
+    })
+    .await?;
+
+    let length = match keyring {
+        Some(keyring) => {
+            let dek = e2e::generate_dek();
+            tx.send(pandorica_file::UploadRequest {
+                data: Some(upload_request::Data::WrappedDek(keyring.wrap_dek(&dek)?)),
+            })
+            .await?;
+            tokio::spawn(send_encrypted_chunks(file, tx, Encryptor::new(&dek)));
+
+            e2e::encrypted_size(length)
+        }
+        None => {
+            tokio::spawn(async move {
+                let mut buffer = vec![0_u8; UPLOAD_CHUNK_SIZE];
+                while let Ok(read) = file.read(&mut buffer).await {
+                    if read == 0 {
+                        break;
+                    }
+
+                    let message = pandorica_file::UploadRequest {
+                        data: Some(upload_request::Data::Chunk(buffer[..read].to_vec())),
+                    };
+                    if tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            length
+        }
+    };
+
+    let response = client.upload(ReceiverStream::new(rx)).await?.into_inner();
+
+    let size = response.file.as_ref().map(|f| f.size).unwrap_or_default();
+    if size != length {
+        return Err(anyhow::format_err!(
+            "Uploaded {} bytes, but the file is {} bytes long",
+            size,
+            length
+        )
+        .into());
+    }
+
+    Ok(response)
+}
+
+/// Sends the file sealed chunk by chunk, reading one chunk ahead to know which is the last.
+async fn send_encrypted_chunks(
+    mut file: tokio::fs::File,
+    tx: mpsc::Sender<pandorica_file::UploadRequest>,
+    mut encryptor: Encryptor,
+) {
+    let mut chunk = match read_chunk(&mut file).await {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    loop {
+        let next = match read_chunk(&mut file).await {
+            Ok(n) => n,
+            Err(_) => return,
+        };
+        let is_last = next.is_empty();
+
+        let message = match encryptor.seal(&chunk, is_last) {
+            Ok(c) => pandorica_file::UploadRequest {
+                data: Some(upload_request::Data::Chunk(c)),
+            },
+            Err(_) => return,
+        };
+        if tx.send(message).await.is_err() || is_last {
+            return;
+        }
+
+        chunk = next;
+    }
+}
+
+/// Reads up to `e2e::CHUNK_SIZE` bytes, which is less only at the end of the file.
+async fn read_chunk(file: &mut tokio::fs::File) -> std::io::Result<Vec<u8>> {
+    let mut chunk = vec![0_u8; e2e::CHUNK_SIZE];
+    let mut read = 0;
+    while read < chunk.len() {
+        let n = file.read(&mut chunk[read..]).await?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    chunk.truncate(read);
+
+    Ok(chunk)
+}
+
+/// Decrypts a file that was encrypted end to end with `keyring`.
+pub async fn download(
+    url: String,
+    session_token: &str,
+    id: String,
+    path: &str,
+    keyring: Option<&Keyring>,
+) -> OperationResult<pandorica_file::File> {
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    let mut client = pandorica_file::file_service_client::FileServiceClient::with_interceptor(
+        channel,
+        move |mut req: Request<()>| {
+            req.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", session_token).parse().unwrap(),
+            );
+            Ok(req)
+        },
+    );
+
+    let request = Request::new(pandorica_file::DownloadRequest { id });
+
+    let mut stream = client.download(request).await?.into_inner();
+
+    let mut file: Option<pandorica_file::File> = None;
+    let mut decryptor: Option<Decryptor> = None;
+    let mut output = tokio::fs::File::create(path).await?;
+    while let Some(message) = stream.message().await? {
+        match message.data {
+            Some(download_response::Data::File(f)) => {
+                if let Some(wrapped_dek) = f.wrapped_dek.as_ref() {
+                    let keyring = keyring.ok_or_else(|| anyhow::Error::msg("keyring_not_found"))?;
+                    decryptor = Some(Decryptor::new(&keyring.unwrap_dek(wrapped_dek)?));
+                }
+                file = Some(f);
+            }
+            Some(download_response::Data::Chunk(c)) => match decryptor.as_mut() {
+                Some(d) => output.write_all(&d.update(&c)?).await?,
+                None => output.write_all(&c).await?,
+            },
+            None => {}
+        }
+    }
+    if let Some(decryptor) = decryptor {
+        output.write_all(&decryptor.finish()?).await?;
+    }
+    output.flush().await?;
+
+    file.ok_or_else(|| anyhow::Error::msg("file_not_found").into())
+}
@@ -1,15 +1,7 @@
+use crate::e2e::{Keyring, RecoveryKey};
 use crate::helper::CliHelper;
-use crate::models::{Session, User};
-
-pub fn not_implemented() {
-    eprintln!(
-        "{}",
-        crate::colorize::stderr(
-            "ERROR: This command is not implemented yet.",
-            &crate::styles::BOLD_RED
-        )
-    );
-}
+use crate::models::{AccessToken, File, MasterKey, Session, User};
+use crate::opaque::PasswordProof;
 
 pub fn help(helper: &CliHelper) {
     use unicode_width::UnicodeWidthStr;
@@ -19,29 +11,230 @@ pub fn help(helper: &CliHelper) {
         println!(
             "  {}{}{}",
             command.name,
-            " ".repeat(16 - command.name.width()),
+            " ".repeat(24 - command.name.width()),
             command.description
         );
     });
 }
 
-pub async fn login(url: String, username: String, password: String) -> String {
+/// Returns the session token and the refresh token, which are empty if the login failed. An
+/// account from before OPAQUE is moved over right away, unless it still has to pass the second
+/// factor, in which case the password to do so with is returned as well.
+pub async fn login(
+    url: String,
+    username: String,
+    password: String,
+    device_name: Option<&str>,
+) -> (String, String, Option<String>) {
     println!(
         "Logging in to {} with user {}...",
         crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN),
         crate::colorize::stdout(&username, &crate::styles::BOLD_GREEN)
     );
 
-    let result = crate::client::login(url, username, password).await;
+    let result = crate::opaque::login(url.clone(), username.clone(), &password, device_name).await;
+
+    let (result, is_legacy) = match result {
+        Ok(crate::opaque::Login::LoggedIn(response)) => (Ok(response), false),
+        // Whether the password is wrong or the account is from before OPAQUE can't be told
+        // apart from here. The old way sends the password itself, so it is only tried if the
+        // user asked for it, never because the server says it would take it.
+        Ok(crate::opaque::Login::Rejected {
+            is_plaintext_allowed: true,
+        }) if crate::opaque::is_legacy_password_allowed() => (
+            crate::client::login(url.clone(), username, password.clone(), device_name).await,
+            true,
+        ),
+        Ok(crate::opaque::Login::Rejected {
+            is_plaintext_allowed,
+        }) => {
+            if is_plaintext_allowed && !crate::opaque::is_legacy_password_allowed() {
+                println!(
+                    "An account from before OPAQUE has to log in once with {}, which sends \
+                    the password to the server for the last time.",
+                    crate::colorize::stdout("--legacy-password", &crate::styles::BOLD_WHITE)
+                );
+            }
+
+            (Err(anyhow::Error::msg("invalid_credentials").into()), false)
+        }
+        Err(err) => (Err(err), false),
+    };
 
     match result {
         Ok(response) => {
+            if response.is_mfa_pending {
+                println!(
+                    "Two-factor authentication is enabled. Enter a code with {}.",
+                    crate::colorize::stdout("mfa [code]", &crate::styles::BOLD_WHITE)
+                );
+
+                return (response.token, String::new(), is_legacy.then_some(password));
+            }
+
             println!(
                 "{}",
                 crate::colorize::stdout("Logged in successfully.", &crate::styles::BOLD_GREEN)
             );
+            print_password_reset_hint(&response);
+
+            if is_legacy {
+                migrate_password(url, &response.token, password).await;
+            }
+
+            (response.token, response.refresh_token, None)
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            (String::new(), String::new(), None)
+        }
+    }
+}
+
+/// Moves an account from before OPAQUE over, keeping its password.
+pub async fn migrate_password(url: String, session_token: &str, password: String) {
+    let result = crate::opaque::set_password(url, session_token, password.clone(), &password).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Your password will no longer be sent to the server.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+/// Returns the session token and the refresh token of the new account, which are empty if the
+/// registration failed.
+pub async fn register(
+    url: String,
+    username: String,
+    email: Option<String>,
+    password: String,
+    device_name: Option<&str>,
+) -> (String, String) {
+    println!(
+        "Registering user {} on {}...",
+        crate::colorize::stdout(&username, &crate::styles::BOLD_GREEN),
+        crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
+    );
+
+    let result = crate::opaque::register(url, username, email, &password, device_name).await;
+
+    match result {
+        Ok((response, recovery_key)) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Registered successfully.", &crate::styles::BOLD_GREEN)
+            );
+            print_recovery_key(&recovery_key);
+
+            (response.token, response.refresh_token)
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            (String::new(), String::new())
+        }
+    }
+}
+
+/// Turns the password into what the server takes in its place, printing the error if that
+/// fails.
+async fn password_proof(
+    url: String,
+    session_token: &str,
+    password: String,
+) -> Option<PasswordProof> {
+    match crate::opaque::password_proof(url, session_token, password).await {
+        Ok(proof) => Some(proof),
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            None
+        }
+    }
+}
+
+fn print_recovery_key(recovery_key: &RecoveryKey) {
+    println!(
+        "Your files are encrypted end to end, so nobody can get them back if you forget your \
+        password. Write down this recovery key and keep it somewhere safe. It is shown only \
+        once:\n\n  {}\n",
+        crate::colorize::stdout(&recovery_key.mnemonic(), &crate::styles::BOLD_WHITE)
+    );
+}
+
+fn print_password_reset_hint(response: &protobuf::pandorica_auth::AuthResponse) {
+    if response
+        .user
+        .as_ref()
+        .map_or(false, |u| u.is_password_reset_required)
+    {
+        println!(
+            "An admin requires you to change your password before doing anything else. Use {}.",
+            crate::colorize::stdout("change-password", &crate::styles::BOLD_WHITE)
+        );
+    }
+}
+
+/// Returns a new session token and refresh token, which are empty if the refresh failed.
+pub async fn refresh(
+    url: String,
+    refresh_token: &str,
+    device_name: Option<&str>,
+) -> (String, String) {
+    if refresh_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return (String::new(), String::new());
+    }
+
+    println!(
+        "Refreshing the session on {}...",
+        crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
+    );
+
+    let result = crate::client::refresh(url, refresh_token.to_string(), device_name).await;
+
+    match result {
+        Ok(response) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Refreshed successfully.", &crate::styles::BOLD_GREEN)
+            );
 
-            response.session.unwrap().id
+            (response.token, response.refresh_token)
         }
         Err(err) => {
             eprintln!(
@@ -50,18 +243,18 @@ pub async fn login(url: String, username: String, password: String) -> String {
                 err
             );
 
-            String::new()
+            (String::new(), String::new())
         }
     }
 }
 
-pub async fn logout(url: String, session_id: &str) {
+pub async fn logout(url: String, session_token: &str) {
     println!(
         "Logging out from {}...",
         crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
     );
 
-    let result = crate::client::logout(url, session_id).await;
+    let result = crate::client::logout(url, session_token).await;
 
     match result {
         Ok(_) => {
@@ -80,8 +273,8 @@ pub async fn logout(url: String, session_id: &str) {
     }
 }
 
-pub async fn me(url: String, session_id: &str) {
-    if session_id.is_empty() {
+pub async fn me(url: String, session_token: &str) {
+    if session_token.is_empty() {
         eprintln!(
             "{}",
             crate::colorize::stderr(
@@ -98,7 +291,7 @@ pub async fn me(url: String, session_id: &str) {
         crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
     );
 
-    let result = crate::client::me(url, session_id).await;
+    let result = crate::client::me(url, session_token).await;
 
     match result {
         Ok(response) => {
@@ -130,3 +323,1051 @@ pub async fn me(url: String, session_id: &str) {
         }
     }
 }
+
+pub async fn upload(url: String, session_token: &str, path: &str, keyring: Option<&Keyring>) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    println!(
+        "Uploading {} to {}...",
+        crate::colorize::stdout(path, &crate::styles::BOLD_GREEN),
+        crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
+    );
+
+    let result = crate::client::upload(url, session_token, path, keyring).await;
+
+    match result {
+        Ok(response) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Uploaded successfully.", &crate::styles::BOLD_GREEN)
+            );
+
+            let file: File = response.file.unwrap().into();
+            println!("{}\n", file);
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn download(
+    url: String,
+    session_token: &str,
+    id: String,
+    path: &str,
+    keyring: Option<&Keyring>,
+) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    println!(
+        "Downloading {} from {}...",
+        crate::colorize::stdout(&id, &crate::styles::BOLD_GREEN),
+        crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
+    );
+
+    let result = crate::client::download(url, session_token, id, path, keyring).await;
+
+    match result {
+        Ok(file) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Downloaded successfully.", &crate::styles::BOLD_GREEN)
+            );
+
+            let file: File = file.into();
+            println!("{}\n", file);
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+/// Returns the sealed keyring of the logged in user, which is `None` if they have none. Returns
+/// `None` if it couldn't be fetched.
+pub async fn get_keyring(
+    url: String,
+    session_token: &str,
+) -> Option<Option<protobuf::pandorica_common::Keyring>> {
+    if session_token.is_empty() {
+        return Some(None);
+    }
+
+    match crate::client::get_keyring(url, session_token).await {
+        Ok(keyring) => Some(keyring),
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            None
+        }
+    }
+}
+
+pub fn unlock_keyring(
+    keyring: &protobuf::pandorica_common::Keyring,
+    password: &str,
+) -> Option<Keyring> {
+    match Keyring::unlock(keyring, password) {
+        Ok(keyring) => Some(keyring),
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            None
+        }
+    }
+}
+
+/// Gives an account from before end-to-end encryption a keyring, and returns it unlocked.
+pub async fn enable_e2e(url: String, session_token: &str, password: String) -> Option<Keyring> {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return None;
+    }
+
+    let keyring = Keyring::generate();
+    let recovery_key = RecoveryKey::generate();
+    let sealed = keyring
+        .seal(&password)
+        .and_then(|k| keyring.seal_for_recovery(&recovery_key).map(|r| (k, r)));
+    let (sealed_keyring, sealed_recovery_key) = match sealed {
+        Ok(s) => s,
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            return None;
+        }
+    };
+    // The proof also makes sure that the keyring isn't sealed with a mistyped password
+    let password = password_proof(url.clone(), session_token, password).await?;
+    let result = crate::client::set_keyring(
+        url,
+        session_token,
+        password,
+        sealed_keyring,
+        sealed_recovery_key,
+    )
+    .await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Files you upload from now on are encrypted end to end.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+            print_recovery_key(&recovery_key);
+
+            Some(keyring)
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            None
+        }
+    }
+}
+
+/// Replaces the recovery key of the logged in user, for one that was lost or seen by someone else.
+pub async fn regenerate_recovery_key(url: String, session_token: &str, password: String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let sealed = match get_keyring(url.clone(), session_token).await {
+        Some(Some(k)) => k,
+        Some(None) => {
+            eprintln!(
+                "{}",
+                crate::colorize::stderr(
+                    "ERROR: Your files aren't encrypted end to end. Use enable-e2e first.",
+                    &crate::styles::BOLD_RED
+                )
+            );
+
+            return;
+        }
+        None => return,
+    };
+    let keyring = match unlock_keyring(&sealed, &password) {
+        Some(k) => k,
+        None => return,
+    };
+
+    let recovery_key = RecoveryKey::generate();
+    let sealed = match keyring.seal_for_recovery(&recovery_key) {
+        Ok(s) => s,
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            return;
+        }
+    };
+    let password = match password_proof(url.clone(), session_token, password).await {
+        Some(p) => p,
+        None => return,
+    };
+    let result = crate::client::set_recovery_key(url, session_token, password, sealed).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Recovery key replaced. The old one no longer works.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+            print_recovery_key(&recovery_key);
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+/// Sets a new password with the recovery key, for a user who forgot theirs.
+pub async fn recover_account(
+    url: String,
+    username: String,
+    recovery_key: String,
+    new_password: String,
+) {
+    println!(
+        "Recovering user {} on {}...",
+        crate::colorize::stdout(&username, &crate::styles::BOLD_GREEN),
+        crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
+    );
+
+    let result = match RecoveryKey::parse(&recovery_key) {
+        Ok(recovery_key) => {
+            crate::opaque::recover_account(url, username, &recovery_key, &new_password).await
+        }
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(recovery_key) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Password changed. All of your sessions have been logged out, and the \
+                    recovery key you used no longer works.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+            print_recovery_key(&recovery_key);
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn send_email_verification(url: String, session_token: &str) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::send_email_verification(url, session_token).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Verification mail sent. Enter the token in it with verify-email.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn verify_email(url: String, token: String) {
+    let result = crate::client::verify_email(url, token.trim().to_string()).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Email address verified.", &crate::styles::BOLD_GREEN)
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn forgot_password(url: String, username: String) {
+    let result = crate::client::request_password_reset(url, username).await;
+
+    match result {
+        // The server doesn't say whether a mail was sent, so that usernames can't be probed
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "If the account has a verified email address, a password reset token is on \
+                    its way to it. Enter it with reset-password.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+            println!(
+                "Accounts whose files are encrypted end to end don't get one, since a reset \
+                would lose their files. Use recover with the recovery key instead."
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn reset_password(url: String, token: String, new_password: String) {
+    let result = crate::opaque::reset_password(url, token.trim().to_string(), &new_password).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Password reset. All of your sessions have been logged out.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn delete_account(url: String, session_token: &str, password: String) -> bool {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return false;
+    }
+
+    println!(
+        "Deleting your account on {}...",
+        crate::colorize::stdout(&url, &crate::styles::BOLD_GREEN)
+    );
+
+    let password = match password_proof(url.clone(), session_token, password).await {
+        Some(p) => p,
+        None => return false,
+    };
+    let result = crate::client::delete_account(url, session_token, password).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Account deleted successfully.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+
+            true
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            false
+        }
+    }
+}
+
+/// Returns the session token and the refresh token that replace the pending login, which are
+/// empty if the code was rejected.
+pub async fn verify_mfa(
+    url: String,
+    session_token: &str,
+    code: String,
+    device_name: Option<&str>,
+) -> (String, String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return (String::new(), String::new());
+    }
+
+    let result = crate::client::verify_mfa(url, session_token, code, device_name).await;
+
+    match result {
+        Ok(response) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Logged in successfully.", &crate::styles::BOLD_GREEN)
+            );
+            print_password_reset_hint(&response);
+
+            (response.token, response.refresh_token)
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            (String::new(), String::new())
+        }
+    }
+}
+
+pub async fn enroll_totp(url: String, session_token: &str, password: String) -> bool {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return false;
+    }
+
+    let password = match password_proof(url.clone(), session_token, password).await {
+        Some(p) => p,
+        None => return false,
+    };
+    let result = crate::client::enroll_totp(url, session_token, password).await;
+
+    match result {
+        Ok(response) => {
+            println!(
+                "Add this account to your authenticator app with the secret {} or the URI:\n{}\n",
+                crate::colorize::stdout(&response.secret, &crate::styles::BOLD_WHITE),
+                response.uri
+            );
+            println!("Store these recovery codes somewhere safe. Each of them works once:");
+            response.recovery_codes.iter().for_each(|code| {
+                println!("  {}", code);
+            });
+            println!();
+
+            true
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+
+            false
+        }
+    }
+}
+
+pub async fn confirm_totp(url: String, session_token: &str, code: String) {
+    let result = crate::client::confirm_totp(url, session_token, code).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Two-factor authentication enabled.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn disable_totp(url: String, session_token: &str, password: String, code: String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let password = match password_proof(url.clone(), session_token, password).await {
+        Some(p) => p,
+        None => return,
+    };
+    let result = crate::client::disable_totp(url, session_token, password, code).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Two-factor authentication disabled.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn change_password(
+    url: String,
+    session_token: &str,
+    current_password: String,
+    new_password: String,
+) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result =
+        crate::opaque::set_password(url, session_token, current_password, &new_password).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Password changed. Your other sessions have been logged out.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn revoke_session(url: String, session_token: &str, id: String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::revoke_session(url, session_token, id).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Session revoked.", &crate::styles::BOLD_GREEN)
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn revoke_other_sessions(url: String, session_token: &str) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::revoke_other_sessions(url, session_token).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "Your other sessions have been logged out.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn create_access_token(
+    url: String,
+    session_token: &str,
+    name: String,
+    scopes: Vec<String>,
+    lifetime_days: Option<u32>,
+) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result =
+        crate::client::create_access_token(url, session_token, name, scopes, lifetime_days).await;
+
+    match result {
+        Ok(response) => {
+            let access_token: AccessToken = response.access_token.unwrap().into();
+
+            println!("{}\n", access_token);
+            println!(
+                "Token: {}\n{}",
+                crate::colorize::stdout(&response.token, &crate::styles::BOLD_WHITE),
+                crate::colorize::stdout(
+                    "Store it now, it will not be shown again.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn list_access_tokens(url: String, session_token: &str) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::list_access_tokens(url, session_token).await;
+
+    match result {
+        Ok(response) => {
+            if response.access_tokens.is_empty() {
+                println!("You have no personal access tokens.");
+            }
+
+            response.access_tokens.into_iter().for_each(|access_token| {
+                println!("{}\n", AccessToken::from(access_token));
+            });
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn revoke_access_token(url: String, session_token: &str, id: String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::revoke_access_token(url, session_token, id).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Access token revoked.", &crate::styles::BOLD_GREEN)
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn list_users(url: String, session_token: &str, query: Option<String>) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let mut cursor: Option<String> = None;
+    loop {
+        let result =
+            crate::client::list_users(url.clone(), session_token, query.clone(), cursor).await;
+
+        match result {
+            Ok(response) => {
+                response.users.into_iter().for_each(|user| {
+                    println!("{}\n", User::from(user));
+                });
+
+                cursor = response.next_cursor;
+                if cursor.is_none() {
+                    return;
+                }
+            }
+            Err(err) => {
+                eprintln!(
+                    "{} {:#?}",
+                    crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                    err
+                );
+
+                return;
+            }
+        }
+    }
+}
+
+pub async fn set_user_active(url: String, session_token: &str, id: String, is_active: bool) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::set_user_active(url, session_token, id, is_active).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("User updated.", &crate::styles::BOLD_GREEN)
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn set_user_role(url: String, session_token: &str, id: String, role: String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::set_user_role(url, session_token, id, role).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout("Role updated.", &crate::styles::BOLD_GREEN)
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn force_password_reset(url: String, session_token: &str, id: String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::force_password_reset(url, session_token, id).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "The user has to change their password at their next login.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn revoke_user_sessions(url: String, session_token: &str, id: String) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::revoke_user_sessions(url, session_token, id).await;
+
+    match result {
+        Ok(_) => {
+            println!(
+                "{}",
+                crate::colorize::stdout(
+                    "The user has been logged out everywhere.",
+                    &crate::styles::BOLD_GREEN
+                )
+            );
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
+
+pub async fn master_key_status(url: String, session_token: &str) {
+    if session_token.is_empty() {
+        eprintln!(
+            "{}",
+            crate::colorize::stderr(
+                "ERROR: You are not logged in. Please log in first.",
+                &crate::styles::BOLD_RED
+            )
+        );
+
+        return;
+    }
+
+    let result = crate::client::master_key_status(url, session_token).await;
+
+    match result {
+        Ok(response) => {
+            response.master_keys.into_iter().for_each(|master_key| {
+                println!("{}\n", MasterKey::from(master_key));
+            });
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {:#?}",
+                crate::colorize::stderr("ERROR:", &crate::styles::BOLD_RED),
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,550 @@
+// End-to-end encryption. The user's keyring holds an X25519 key pair that file DEKs are wrapped
+// to, and an Ed25519 one that signs them. Its private keys are sealed with a key derived from the
+// password with scrypt, so the server stores them without being able to use them.
+//
+// Wrapped DEK, version 1
+//
+// | version: u8 | ephemeral public key | nonce | sealed DEK | signature |
+//
+// The DEK is sealed with XChaCha20-Poly1305 under a key derived with HKDF-SHA256 from an X25519
+// exchange between a fresh ephemeral key and the user's public key. The signature covers
+// everything before it, so that a DEK wrapped to the public key by anyone else is rejected.
+//
+// File, version 1
+//
+// | magic "PDE2" | version: u8 | chunk size: u32 BE | nonce prefix |
+//
+// The header is followed by the chunks, which are sealed like the server seals its own: every
+// chunk except the last one holds exactly `chunk size` bytes of plaintext, and the nonce is the
+// prefix, a 32-bit BE chunk counter and a final-chunk flag. The header is authenticated as
+// associated data of every chunk.
+//
+// Recovery key
+//
+// 128 random bits, shown to the user once as a BIP39 mnemonic. HKDF-SHA256 derives from it a key
+// that seals a second copy of the keyring private keys, and an Ed25519 key that the user proves
+// they have the recovery key with. The server only gets the public half of the latter.
+
+use bip39::Mnemonic;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use ed25519_dalek::{Signature, Signer, SigningKey};
+use hkdf::Hkdf;
+use protobuf::pandorica_common;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use shared::error::OperationResult;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const MAGIC: &[u8; 4] = b"PDE2";
+const VERSION: u8 = 1;
+const WRAPPED_DEK_VERSION: u8 = 1;
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 24;
+const NONCE_PREFIX_SIZE: usize = 19;
+const TAG_SIZE: usize = 16;
+const SIGNATURE_SIZE: usize = 64;
+const HEADER_SIZE: usize = 4 + 1 + 4 + NONCE_PREFIX_SIZE;
+const SALT_SIZE: usize = 16;
+const WRAPPING_INFO: &[u8] = b"pandorica e2e dek v1";
+const RECOVERY_KEY_SIZE: usize = 16;
+const RECOVERY_SEALING_INFO: &[u8] = b"pandorica recovery sealing v1";
+const RECOVERY_SIGNING_INFO: &[u8] = b"pandorica recovery signing v1";
+/// Has to match the server's.
+const RECOVERY_CONTEXT: &[u8] = b"pandorica account recovery v1";
+
+// About 128 MiB of memory, which is what unlocking the keyring costs, and guessing its password
+const SCRYPT_LOG_N: u8 = 17;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// An unlocked keyring.
+pub struct Keyring {
+    secret: StaticSecret,
+    signing_key: SigningKey,
+}
+
+impl Keyring {
+    pub fn generate() -> Self {
+        Self {
+            secret: StaticSecret::random_from_rng(OsRng),
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Seals the private keys with `password`, to be stored by the server.
+    pub fn seal(&self, password: &str) -> OperationResult<pandorica_common::Keyring> {
+        let mut salt = vec![0_u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = vec![0_u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut keyring = pandorica_common::Keyring {
+            public_key: PublicKey::from(&self.secret).as_bytes().to_vec(),
+            signing_public_key: self.signing_key.verifying_key().as_bytes().to_vec(),
+            salt,
+            scrypt_log_n: SCRYPT_LOG_N.into(),
+            scrypt_r: SCRYPT_R,
+            scrypt_p: SCRYPT_P,
+            nonce,
+            encrypted_private_keys: Vec::new(),
+        };
+
+        keyring.encrypted_private_keys = derive_cipher(&keyring, password)?
+            .encrypt(
+                XNonce::from_slice(&keyring.nonce),
+                Payload {
+                    msg: &self.private_keys(),
+                    aad: &self.public_keys(),
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("keyring_seal_failed"))?;
+
+        Ok(keyring)
+    }
+
+    /// Seals a second copy of the private keys with `recovery_key`.
+    pub fn seal_for_recovery(
+        &self,
+        recovery_key: &RecoveryKey,
+    ) -> OperationResult<pandorica_common::RecoveryKey> {
+        let mut nonce = vec![0_u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let encrypted_private_keys = recovery_key
+            .cipher()
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.private_keys(),
+                    aad: &self.public_keys(),
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("keyring_seal_failed"))?;
+
+        Ok(pandorica_common::RecoveryKey {
+            verifying_key: recovery_key
+                .signing_key()
+                .verifying_key()
+                .as_bytes()
+                .to_vec(),
+            nonce,
+            encrypted_private_keys,
+        })
+    }
+
+    /// Opens a keyring from the server. The public keys it comes with are only trusted if they
+    /// belong to the private keys.
+    pub fn unlock(keyring: &pandorica_common::Keyring, password: &str) -> OperationResult<Self> {
+        if keyring.nonce.len() != NONCE_SIZE {
+            return Err(anyhow::Error::msg("invalid_keyring").into());
+        }
+
+        let private_keys = derive_cipher(keyring, password)?
+            .decrypt(
+                XNonce::from_slice(&keyring.nonce),
+                Payload {
+                    msg: &keyring.encrypted_private_keys,
+                    aad: &associated_data(&keyring.public_key, &keyring.signing_public_key),
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("invalid_password"))?;
+
+        Self::from_private_keys(
+            &private_keys,
+            &keyring.public_key,
+            &keyring.signing_public_key,
+        )
+    }
+
+    /// Opens the copy of the private keys sealed with `recovery_key`. The public keys come from
+    /// the server, and are checked the same way.
+    pub fn unlock_with_recovery(
+        sealed: &pandorica_common::RecoveryKey,
+        public_key: &[u8],
+        signing_public_key: &[u8],
+        recovery_key: &RecoveryKey,
+    ) -> OperationResult<Self> {
+        if sealed.nonce.len() != NONCE_SIZE {
+            return Err(anyhow::Error::msg("invalid_keyring").into());
+        }
+
+        let private_keys = recovery_key
+            .cipher()
+            .decrypt(
+                XNonce::from_slice(&sealed.nonce),
+                Payload {
+                    msg: &sealed.encrypted_private_keys,
+                    aad: &associated_data(public_key, signing_public_key),
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("invalid_recovery_key"))?;
+
+        Self::from_private_keys(&private_keys, public_key, signing_public_key)
+    }
+
+    fn from_private_keys(
+        private_keys: &[u8],
+        public_key: &[u8],
+        signing_public_key: &[u8],
+    ) -> OperationResult<Self> {
+        if private_keys.len() != 2 * KEY_SIZE {
+            return Err(anyhow::Error::msg("invalid_keyring").into());
+        }
+
+        let mut secret = [0_u8; KEY_SIZE];
+        secret.copy_from_slice(&private_keys[..KEY_SIZE]);
+        let mut signing_key = [0_u8; KEY_SIZE];
+        signing_key.copy_from_slice(&private_keys[KEY_SIZE..]);
+        let unlocked = Self {
+            secret: StaticSecret::from(secret),
+            signing_key: SigningKey::from_bytes(&signing_key),
+        };
+
+        if PublicKey::from(&unlocked.secret).as_bytes()[..] != public_key[..]
+            || unlocked.signing_key.verifying_key().as_bytes()[..] != signing_public_key[..]
+        {
+            return Err(anyhow::Error::msg("invalid_keyring").into());
+        }
+
+        Ok(unlocked)
+    }
+
+    fn private_keys(&self) -> Vec<u8> {
+        let mut private_keys = self.secret.to_bytes().to_vec();
+        private_keys.extend_from_slice(self.signing_key.as_bytes());
+        private_keys
+    }
+
+    fn public_keys(&self) -> Vec<u8> {
+        associated_data(
+            PublicKey::from(&self.secret).as_bytes(),
+            self.signing_key.verifying_key().as_bytes(),
+        )
+    }
+
+    /// Wraps a file DEK to the public key, and signs it.
+    pub fn wrap_dek(&self, dek: &[u8; KEY_SIZE]) -> OperationResult<Vec<u8>> {
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let public_key = PublicKey::from(&self.secret);
+        let cipher = wrapping_cipher(&ephemeral.diffie_hellman(&public_key), &ephemeral_public)?;
+
+        let mut nonce = [0_u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        let sealed_dek = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: dek,
+                    aad: &[WRAPPED_DEK_VERSION],
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("dek_wrap_failed"))?;
+
+        let mut wrapped = vec![WRAPPED_DEK_VERSION];
+        wrapped.extend_from_slice(ephemeral_public.as_bytes());
+        wrapped.extend_from_slice(&nonce);
+        wrapped.extend_from_slice(&sealed_dek);
+        let signature = self.signing_key.sign(&wrapped);
+        wrapped.extend_from_slice(&signature.to_bytes());
+
+        Ok(wrapped)
+    }
+
+    /// Checks the signature of a wrapped DEK and unwraps it.
+    pub fn unwrap_dek(&self, wrapped: &[u8]) -> OperationResult<[u8; KEY_SIZE]> {
+        let size = 1 + KEY_SIZE + NONCE_SIZE + KEY_SIZE + TAG_SIZE + SIGNATURE_SIZE;
+        if wrapped.len() != size || wrapped[0] != WRAPPED_DEK_VERSION {
+            return Err(anyhow::Error::msg("invalid_wrapped_dek").into());
+        }
+
+        let (signed, signature) = wrapped.split_at(size - SIGNATURE_SIZE);
+        let signature = Signature::from_slice(signature)
+            .map_err(|_| anyhow::Error::msg("invalid_wrapped_dek"))?;
+        self.signing_key
+            .verifying_key()
+            .verify_strict(signed, &signature)
+            .map_err(|_| anyhow::Error::msg("invalid_wrapped_dek__signature"))?;
+
+        let mut ephemeral_public = [0_u8; KEY_SIZE];
+        ephemeral_public.copy_from_slice(&signed[1..1 + KEY_SIZE]);
+        let ephemeral_public = PublicKey::from(ephemeral_public);
+        let nonce = &signed[1 + KEY_SIZE..1 + KEY_SIZE + NONCE_SIZE];
+        let sealed_dek = &signed[1 + KEY_SIZE + NONCE_SIZE..];
+
+        let cipher = wrapping_cipher(
+            &self.secret.diffie_hellman(&ephemeral_public),
+            &ephemeral_public,
+        )?;
+        let dek = cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed_dek,
+                    aad: &[WRAPPED_DEK_VERSION],
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("invalid_wrapped_dek"))?;
+
+        let mut unwrapped = [0_u8; KEY_SIZE];
+        unwrapped.copy_from_slice(&dek);
+        Ok(unwrapped)
+    }
+}
+
+/// A key the user writes down, for when the password is lost. It never leaves the client.
+pub struct RecoveryKey {
+    entropy: [u8; RECOVERY_KEY_SIZE],
+}
+
+impl RecoveryKey {
+    pub fn generate() -> Self {
+        let mut entropy = [0_u8; RECOVERY_KEY_SIZE];
+        OsRng.fill_bytes(&mut entropy);
+
+        Self { entropy }
+    }
+
+    pub fn parse(mnemonic: &str) -> OperationResult<Self> {
+        let words: Vec<&str> = mnemonic.split_whitespace().collect();
+        let mnemonic = Mnemonic::parse(words.join(" ").to_lowercase())
+            .map_err(|_| anyhow::Error::msg("invalid_recovery_key__mnemonic"))?;
+        let entropy = <[u8; RECOVERY_KEY_SIZE]>::try_from(mnemonic.to_entropy().as_slice())
+            .map_err(|_| anyhow::Error::msg("invalid_recovery_key__mnemonic"))?;
+
+        Ok(Self { entropy })
+    }
+
+    pub fn mnemonic(&self) -> String {
+        Mnemonic::from_entropy(&self.entropy)
+            .expect("128 bits are a valid mnemonic length")
+            .to_string()
+    }
+
+    /// Signs what an account recovery is about to set, to prove that the user has this key.
+    pub fn sign(
+        &self,
+        recovery_id: &str,
+        registration_upload: &[u8],
+        new_verifying_key: &[u8],
+    ) -> Vec<u8> {
+        let mut message = RECOVERY_CONTEXT.to_vec();
+        for part in [
+            recovery_id.as_bytes(),
+            registration_upload,
+            new_verifying_key,
+        ] {
+            message.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            message.extend_from_slice(part);
+        }
+
+        self.signing_key().sign(&message).to_bytes().to_vec()
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.derive(RECOVERY_SEALING_INFO).into())
+    }
+
+    fn signing_key(&self) -> SigningKey {
+        SigningKey::from_bytes(&self.derive(RECOVERY_SIGNING_INFO))
+    }
+
+    fn derive(&self, info: &[u8]) -> [u8; KEY_SIZE] {
+        let mut key = [0_u8; KEY_SIZE];
+        Hkdf::<Sha256>::new(None, &self.entropy)
+            .expand(info, &mut key)
+            .expect("32 bytes are a valid HKDF-SHA256 output length");
+        key
+    }
+}
+
+pub fn generate_dek() -> [u8; KEY_SIZE] {
+    let mut dek = [0_u8; KEY_SIZE];
+    OsRng.fill_bytes(&mut dek);
+    dek
+}
+
+/// What a file of `length` bytes takes up once encrypted.
+pub fn encrypted_size(length: u64) -> u64 {
+    let chunks = std::cmp::max(1, (length + CHUNK_SIZE as u64 - 1) / CHUNK_SIZE as u64);
+    HEADER_SIZE as u64 + length + chunks * TAG_SIZE as u64
+}
+
+/// Seals a file chunk by chunk. The header comes out with the first chunk.
+pub struct Encryptor {
+    cipher: XChaCha20Poly1305,
+    header: Vec<u8>,
+    counter: u32,
+    is_header_written: bool,
+}
+
+impl Encryptor {
+    pub fn new(dek: &[u8; KEY_SIZE]) -> Self {
+        let mut nonce_prefix = [0_u8; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        header.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+        header.extend_from_slice(&nonce_prefix);
+
+        Self {
+            cipher: XChaCha20Poly1305::new(dek.into()),
+            header,
+            counter: 0,
+            is_header_written: false,
+        }
+    }
+
+    /// Seals the next `CHUNK_SIZE` bytes, or fewer if it's the last chunk.
+    pub fn seal(&mut self, chunk: &[u8], is_last: bool) -> OperationResult<Vec<u8>> {
+        let mut sealed = Vec::with_capacity(HEADER_SIZE + chunk.len() + TAG_SIZE);
+        if !self.is_header_written {
+            sealed.extend_from_slice(&self.header);
+            self.is_header_written = true;
+        }
+
+        let nonce = chunk_nonce(&self.header, self.counter, is_last)?;
+        self.counter += 1;
+        let chunk = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: &self.header,
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("file_encryption_failed"))?;
+        sealed.extend_from_slice(&chunk);
+
+        Ok(sealed)
+    }
+}
+
+/// Opens a file as it comes in, in pieces of any size.
+pub struct Decryptor {
+    cipher: XChaCha20Poly1305,
+    header: Option<Vec<u8>>,
+    buffer: Vec<u8>,
+    counter: u32,
+}
+
+impl Decryptor {
+    pub fn new(dek: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(dek.into()),
+            header: None,
+            buffer: Vec::new(),
+            counter: 0,
+        }
+    }
+
+    /// Returns the plaintext of every chunk that is known not to be the last one.
+    pub fn update(&mut self, data: &[u8]) -> OperationResult<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        if self.header.is_none() {
+            if self.buffer.len() < HEADER_SIZE {
+                return Ok(Vec::new());
+            }
+
+            let header: Vec<u8> = self.buffer.drain(..HEADER_SIZE).collect();
+            if &header[..4] != MAGIC || header[4] != VERSION {
+                return Err(anyhow::Error::msg("invalid_file__e2e_header").into());
+            }
+            if header[5..9] != (CHUNK_SIZE as u32).to_be_bytes() {
+                return Err(anyhow::Error::msg("invalid_file__e2e_chunk_size").into());
+            }
+            self.header = Some(header);
+        }
+
+        let mut plaintext = Vec::new();
+        while self.buffer.len() > CHUNK_SIZE + TAG_SIZE {
+            let chunk: Vec<u8> = self.buffer.drain(..CHUNK_SIZE + TAG_SIZE).collect();
+            plaintext.extend_from_slice(&self.open(&chunk, false)?);
+        }
+
+        Ok(plaintext)
+    }
+
+    /// Opens the last chunk. A file cut short fails here.
+    pub fn finish(mut self) -> OperationResult<Vec<u8>> {
+        if self.header.is_none() {
+            return Err(anyhow::Error::msg("invalid_file__e2e_header").into());
+        }
+
+        let chunk = std::mem::take(&mut self.buffer);
+        self.open(&chunk, true)
+    }
+
+    fn open(&mut self, chunk: &[u8], is_last: bool) -> OperationResult<Vec<u8>> {
+        let header = self.header.as_ref().unwrap();
+        let nonce = chunk_nonce(header, self.counter, is_last)?;
+        self.counter += 1;
+
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: header,
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("file_decryption_failed").into())
+    }
+}
+
+fn chunk_nonce(header: &[u8], counter: u32, is_last: bool) -> OperationResult<Vec<u8>> {
+    if counter == u32::MAX {
+        return Err(anyhow::Error::msg("invalid_file__too_many_chunks").into());
+    }
+
+    let mut nonce = header[HEADER_SIZE - NONCE_PREFIX_SIZE..].to_vec();
+    nonce.extend_from_slice(&counter.to_be_bytes());
+    nonce.push(is_last.into());
+    Ok(nonce)
+}
+
+/// What the sealed private keys are bound to.
+fn associated_data(public_key: &[u8], signing_public_key: &[u8]) -> Vec<u8> {
+    let mut keys = public_key.to_vec();
+    keys.extend_from_slice(signing_public_key);
+    keys
+}
+
+fn derive_cipher(
+    keyring: &pandorica_common::Keyring,
+    password: &str,
+) -> OperationResult<XChaCha20Poly1305> {
+    let log_n = u8::try_from(keyring.scrypt_log_n)
+        .map_err(|_| anyhow::Error::msg("invalid_keyring__scrypt_params"))?;
+    let params = scrypt::Params::new(log_n, keyring.scrypt_r, keyring.scrypt_p)
+        .map_err(|_| anyhow::Error::msg("invalid_keyring__scrypt_params"))?;
+
+    let mut key = [0_u8; KEY_SIZE];
+    scrypt::scrypt(password.as_bytes(), &keyring.salt, &params, &mut key)
+        .map_err(|_| anyhow::Error::msg("invalid_keyring__scrypt_params"))?;
+
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+fn wrapping_cipher(
+    shared_secret: &x25519_dalek::SharedSecret,
+    ephemeral_public: &PublicKey,
+) -> OperationResult<XChaCha20Poly1305> {
+    let mut info = WRAPPING_INFO.to_vec();
+    info.extend_from_slice(ephemeral_public.as_bytes());
+
+    let mut key = [0_u8; KEY_SIZE];
+    Hkdf::<Sha256>::new(None, shared_secret.as_bytes())
+        .expand(&info, &mut key)
+        .map_err(|_| anyhow::Error::msg("dek_wrap_failed"))?;
+
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
@@ -35,6 +35,18 @@ impl CliHelper {
             "logout",
             "Logout from the server",
         ));
+        commands.insert(Command::new(
+            "mfa",
+            "mfa [code]",
+            "mfa ",
+            "Finish logging in with a two-factor code",
+        ));
+        commands.insert(Command::new(
+            "refresh",
+            "refresh",
+            "refresh",
+            "Trade the refresh token for a new session",
+        ));
         commands.insert(Command::new(
             "register",
             "register",
@@ -42,6 +54,156 @@ impl CliHelper {
             "Register a new account",
         ));
         commands.insert(Command::new("me", "me", "me", "Show your profile"));
+        commands.insert(Command::new(
+            "upload",
+            "upload [path]",
+            "upload ",
+            "Upload a file",
+        ));
+        commands.insert(Command::new(
+            "download",
+            "download [id] [path]",
+            "download ",
+            "Download a file",
+        ));
+        commands.insert(Command::new(
+            "delete-account",
+            "delete-account",
+            "delete-account",
+            "Permanently delete your account and files",
+        ));
+        commands.insert(Command::new(
+            "change-password",
+            "change-password",
+            "change-password",
+            "Change your password",
+        ));
+        commands.insert(Command::new(
+            "enable-e2e",
+            "enable-e2e",
+            "enable-e2e",
+            "Encrypt the files you upload end to end",
+        ));
+        commands.insert(Command::new(
+            "recovery-key",
+            "recovery-key",
+            "recovery-key",
+            "Replace your recovery key with a new one",
+        ));
+        commands.insert(Command::new(
+            "recover",
+            "recover",
+            "recover",
+            "Regain access to your account with a recovery key",
+        ));
+        commands.insert(Command::new(
+            "send-verification",
+            "send-verification",
+            "send-verification",
+            "Mail a token that verifies your email address",
+        ));
+        commands.insert(Command::new(
+            "verify-email",
+            "verify-email [token]",
+            "verify-email ",
+            "Verify your email address with the mailed token",
+        ));
+        commands.insert(Command::new(
+            "forgot-password",
+            "forgot-password",
+            "forgot-password",
+            "Mail a password reset token to your verified address",
+        ));
+        commands.insert(Command::new(
+            "reset-password",
+            "reset-password [token]",
+            "reset-password ",
+            "Set a new password with the mailed token",
+        ));
+        commands.insert(Command::new(
+            "enable-totp",
+            "enable-totp",
+            "enable-totp",
+            "Enable two-factor authentication",
+        ));
+        commands.insert(Command::new(
+            "disable-totp",
+            "disable-totp",
+            "disable-totp",
+            "Disable two-factor authentication",
+        ));
+        commands.insert(Command::new(
+            "revoke-session",
+            "revoke-session [id]",
+            "revoke-session ",
+            "Log out one of your sessions",
+        ));
+        commands.insert(Command::new(
+            "revoke-other-sessions",
+            "revoke-other-sessions",
+            "revoke-other-sessions",
+            "Log out all of your sessions but this one",
+        ));
+        commands.insert(Command::new(
+            "create-access-token",
+            "create-access-token",
+            "create-access-token",
+            "Create a personal access token for automation",
+        ));
+        commands.insert(Command::new(
+            "access-tokens",
+            "access-tokens",
+            "access-tokens",
+            "List your personal access tokens",
+        ));
+        commands.insert(Command::new(
+            "revoke-access-token",
+            "revoke-access-token [id]",
+            "revoke-access-token ",
+            "Revoke a personal access token",
+        ));
+        commands.insert(Command::new(
+            "users",
+            "users [query]",
+            "users ",
+            "List or search users (auditors and admins)",
+        ));
+        commands.insert(Command::new(
+            "activate-user",
+            "activate-user [id]",
+            "activate-user ",
+            "Reactivate a user (admins)",
+        ));
+        commands.insert(Command::new(
+            "deactivate-user",
+            "deactivate-user [id]",
+            "deactivate-user ",
+            "Deactivate a user and log them out (admins)",
+        ));
+        commands.insert(Command::new(
+            "set-role",
+            "set-role [id] [role]",
+            "set-role ",
+            "Make a user a user, auditor or admin (admins)",
+        ));
+        commands.insert(Command::new(
+            "force-password-reset",
+            "force-password-reset [id]",
+            "force-password-reset ",
+            "Make a user change their password (admins)",
+        ));
+        commands.insert(Command::new(
+            "revoke-user-sessions",
+            "revoke-user-sessions [id]",
+            "revoke-user-sessions ",
+            "Log a user out everywhere (admins)",
+        ));
+        commands.insert(Command::new(
+            "master-keys",
+            "master-keys",
+            "master-keys",
+            "Show the master keys (auditors and admins)",
+        ));
         commands.insert(Command::new("exit", "exit", "exit", "Exit the CLI"));
 
         Self {
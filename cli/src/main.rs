@@ -1,12 +1,15 @@
 mod client;
 mod colorize;
 mod commands;
+mod e2e;
 mod helper;
 mod models;
+mod opaque;
 mod styles;
 
 use clap::{Parser, ValueEnum};
 use rustyline::error::ReadlineError;
+use rustyline::history::History;
 use rustyline::{Editor, Result};
 
 /// The Pandorica CLI is a command line interface for the Pandorica server.
@@ -23,6 +26,16 @@ struct Args {
     /// The password to use for authentication [Optional]
     #[arg(long)]
     password: Option<String>,
+    /// A personal access token to use instead of logging in [Optional]
+    #[arg(long)]
+    token: Option<String>,
+    /// A name for this device, shown in your list of sessions [Optional]
+    #[arg(long)]
+    device_name: Option<String>,
+    /// Send the password to the server if the account is from before OPAQUE. Only needed for
+    /// its first login from this CLI, which moves it over
+    #[arg(long)]
+    legacy_password: bool,
     #[arg(long, value_enum, global = true, default_value_t = Color::Auto)]
     color: Color,
 }
@@ -38,17 +51,36 @@ enum Color {
 async fn main() -> Result<()> {
     let args = Args::parse();
 
+    if args.legacy_password {
+        opaque::allow_legacy_password();
+    }
+
     if args.color == Color::Always {
         owo_colors::set_override(true);
     } else if args.color == Color::Never {
         owo_colors::set_override(false);
     }
 
-    let mut session_id: String = String::new();
+    let mut session_token: String = String::new();
+    let mut refresh_token: String = String::new();
+    // The password of an account from before OPAQUE, kept until its second factor is verified
+    let mut legacy_password: Option<String> = None;
+    // Unlocked the first time a file needs it
+    let mut keyring: Option<e2e::Keyring> = None;
+
+    if let Some(token) = args.token {
+        session_token = token;
+    }
 
     if let Some(username) = args.username {
         if let Some(password) = args.password {
-            session_id = commands::login(args.url.clone(), username, password).await;
+            (session_token, refresh_token, legacy_password) = commands::login(
+                args.url.clone(),
+                username,
+                password,
+                args.device_name.as_deref(),
+            )
+            .await;
         }
     }
 
@@ -96,22 +128,358 @@ async fn main() -> Result<()> {
                             helper::CliHelper::end_masking(&mut readline);
                             (username, password)
                         };
-                        session_id = String::from(
-                            commands::login(args.url.clone(), username, password)
-                                .await
-                                .split(':')
-                                .last()
-                                .unwrap(),
-                        );
+                        (session_token, refresh_token, legacy_password) = commands::login(
+                            args.url.clone(),
+                            username,
+                            password,
+                            args.device_name.as_deref(),
+                        )
+                        .await;
+                        keyring = None;
+                    }
+                    "mfa" => {
+                        let code = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("Code: ")?
+                        };
+                        (session_token, refresh_token) = commands::verify_mfa(
+                            args.url.clone(),
+                            &session_token,
+                            code,
+                            args.device_name.as_deref(),
+                        )
+                        .await;
+                        if !session_token.is_empty() {
+                            if let Some(password) = legacy_password.take() {
+                                commands::migrate_password(
+                                    args.url.clone(),
+                                    &session_token,
+                                    password,
+                                )
+                                .await;
+                            }
+                        }
+                    }
+                    "refresh" => {
+                        (session_token, refresh_token) = commands::refresh(
+                            args.url.clone(),
+                            &refresh_token,
+                            args.device_name.as_deref(),
+                        )
+                        .await;
                     }
                     "logout" => {
-                        commands::logout(args.url.clone(), &session_id).await;
-                        session_id = String::new();
+                        commands::logout(args.url.clone(), &session_token).await;
+                        session_token = String::new();
+                        refresh_token = String::new();
+                        legacy_password = None;
+                        keyring = None;
+                    }
+                    "register" => {
+                        let username = readline.readline("Username: ")?;
+                        let email = readline.readline("Email [Optional]: ")?;
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let password = readline.readline("Password: ")?;
+                        let confirmation = readline.readline("Confirm password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        if password == confirmation {
+                            (session_token, refresh_token) = commands::register(
+                                args.url.clone(),
+                                username,
+                                Some(email).filter(|e| !e.is_empty()),
+                                password,
+                                args.device_name.as_deref(),
+                            )
+                            .await;
+                            legacy_password = None;
+                            keyring = None;
+                        } else {
+                            eprintln!(
+                                "{}",
+                                colorize::stderr(
+                                    "ERROR: The passwords don't match.",
+                                    &styles::BOLD_RED
+                                )
+                            );
+                        }
                     }
-                    "register" => commands::not_implemented(),
                     "exit" => break,
                     "me" => {
-                        commands::me(args.url.clone(), &session_id).await;
+                        commands::me(args.url.clone(), &session_token).await;
+                    }
+                    "upload" => {
+                        let path = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("Path: ")?
+                        };
+                        if unlock_keyring(
+                            args.url.clone(),
+                            &session_token,
+                            &mut readline,
+                            &mut keyring,
+                        )
+                        .await?
+                        {
+                            commands::upload(
+                                args.url.clone(),
+                                &session_token,
+                                &path,
+                                keyring.as_ref(),
+                            )
+                            .await;
+                        }
+                    }
+                    "download" => {
+                        let (id, path) = if line.split(' ').count() == 3 {
+                            (
+                                line.split(' ').nth(1).unwrap().to_string(),
+                                line.split(' ').nth(2).unwrap().to_string(),
+                            )
+                        } else {
+                            let id = readline.readline("File ID: ")?;
+                            let path = readline.readline("Path: ")?;
+                            (id, path)
+                        };
+                        // Files from before end-to-end encryption can be downloaded without it
+                        unlock_keyring(
+                            args.url.clone(),
+                            &session_token,
+                            &mut readline,
+                            &mut keyring,
+                        )
+                        .await?;
+                        commands::download(
+                            args.url.clone(),
+                            &session_token,
+                            id,
+                            &path,
+                            keyring.as_ref(),
+                        )
+                        .await;
+                    }
+                    "delete-account" => {
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let password = readline.readline("Password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        if commands::delete_account(args.url.clone(), &session_token, password)
+                            .await
+                        {
+                            session_token = String::new();
+                            refresh_token = String::new();
+                            keyring = None;
+                        }
+                    }
+                    "change-password" => {
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let current_password = readline.readline("Current password: ")?;
+                        let new_password = readline.readline("New password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        commands::change_password(
+                            args.url.clone(),
+                            &session_token,
+                            current_password,
+                            new_password,
+                        )
+                        .await;
+                    }
+                    "enable-e2e" => {
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let password = readline.readline("Password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        if let Some(k) =
+                            commands::enable_e2e(args.url.clone(), &session_token, password).await
+                        {
+                            keyring = Some(k);
+                        }
+                    }
+                    "recovery-key" => {
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let password = readline.readline("Password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        commands::regenerate_recovery_key(
+                            args.url.clone(),
+                            &session_token,
+                            password,
+                        )
+                        .await;
+                    }
+                    "recover" => {
+                        let username = readline.readline("Username: ")?;
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let recovery_key = readline.readline("Recovery key: ")?;
+                        let password = readline.readline("New password: ")?;
+                        let confirmation = readline.readline("Confirm password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        if password == confirmation {
+                            commands::recover_account(
+                                args.url.clone(),
+                                username,
+                                recovery_key,
+                                password,
+                            )
+                            .await;
+                        } else {
+                            eprintln!(
+                                "{}",
+                                colorize::stderr(
+                                    "ERROR: The passwords don't match.",
+                                    &styles::BOLD_RED
+                                )
+                            );
+                        }
+                    }
+                    "send-verification" => {
+                        commands::send_email_verification(args.url.clone(), &session_token).await;
+                    }
+                    "verify-email" => {
+                        let token = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("Token: ")?
+                        };
+                        commands::verify_email(args.url.clone(), token).await;
+                    }
+                    "forgot-password" => {
+                        let username = readline.readline("Username: ")?;
+                        commands::forgot_password(args.url.clone(), username).await;
+                    }
+                    "reset-password" => {
+                        let token = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("Token: ")?
+                        };
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let password = readline.readline("New password: ")?;
+                        let confirmation = readline.readline("Confirm password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        if password == confirmation {
+                            commands::reset_password(args.url.clone(), token, password).await;
+                        } else {
+                            eprintln!(
+                                "{}",
+                                colorize::stderr(
+                                    "ERROR: The passwords don't match.",
+                                    &styles::BOLD_RED
+                                )
+                            );
+                        }
+                    }
+                    "enable-totp" => {
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let password = readline.readline("Password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        if commands::enroll_totp(args.url.clone(), &session_token, password).await {
+                            let code = readline.readline("Code: ")?;
+                            commands::confirm_totp(args.url.clone(), &session_token, code).await;
+                        }
+                    }
+                    "disable-totp" => {
+                        helper::CliHelper::begin_masking(&mut readline);
+                        let password = readline.readline("Password: ")?;
+                        helper::CliHelper::end_masking(&mut readline);
+                        let code = readline.readline("Code or recovery code: ")?;
+                        commands::disable_totp(args.url.clone(), &session_token, password, code)
+                            .await;
+                    }
+                    "revoke-session" => {
+                        let id = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("Session ID: ")?
+                        };
+                        commands::revoke_session(args.url.clone(), &session_token, id).await;
+                    }
+                    "revoke-other-sessions" => {
+                        commands::revoke_other_sessions(args.url.clone(), &session_token).await;
+                    }
+                    "create-access-token" => {
+                        let name = readline.readline("Name: ")?;
+                        let scopes = readline
+                            .readline("Scopes (files:read, files:write, account:read): ")?
+                            .split([',', ' '])
+                            .filter(|s| !s.is_empty())
+                            .map(|s| s.to_string())
+                            .collect();
+                        let lifetime_days = readline
+                            .readline("Lifetime in days [Default: server setting]: ")?
+                            .trim()
+                            .parse()
+                            .ok();
+                        commands::create_access_token(
+                            args.url.clone(),
+                            &session_token,
+                            name,
+                            scopes,
+                            lifetime_days,
+                        )
+                        .await;
+                    }
+                    "access-tokens" => {
+                        commands::list_access_tokens(args.url.clone(), &session_token).await;
+                    }
+                    "revoke-access-token" => {
+                        let id = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("Access token ID: ")?
+                        };
+                        commands::revoke_access_token(args.url.clone(), &session_token, id).await;
+                    }
+                    "users" => {
+                        let query = line.split_once(' ').map(|(_, q)| q.trim().to_string());
+                        commands::list_users(args.url.clone(), &session_token, query).await;
+                    }
+                    "activate-user" => {
+                        let id = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("User ID: ")?
+                        };
+                        commands::set_user_active(args.url.clone(), &session_token, id, true).await;
+                    }
+                    "deactivate-user" => {
+                        let id = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("User ID: ")?
+                        };
+                        commands::set_user_active(args.url.clone(), &session_token, id, false)
+                            .await;
+                    }
+                    "set-role" => {
+                        let (id, role) = if line.split(' ').count() == 3 {
+                            (
+                                line.split(' ').nth(1).unwrap().to_string(),
+                                line.split(' ').nth(2).unwrap().to_string(),
+                            )
+                        } else {
+                            let id = readline.readline("User ID: ")?;
+                            let role = readline.readline("Role (user, auditor, admin): ")?;
+                            (id, role)
+                        };
+                        commands::set_user_role(args.url.clone(), &session_token, id, role).await;
+                    }
+                    "force-password-reset" => {
+                        let id = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("User ID: ")?
+                        };
+                        commands::force_password_reset(args.url.clone(), &session_token, id).await;
+                    }
+                    "revoke-user-sessions" => {
+                        let id = if line.split(' ').count() == 2 {
+                            line.split(' ').nth(1).unwrap().to_string()
+                        } else {
+                            readline.readline("User ID: ")?
+                        };
+                        commands::revoke_user_sessions(args.url.clone(), &session_token, id).await;
+                    }
+                    "master-keys" => {
+                        commands::master_key_status(args.url.clone(), &session_token).await;
                     }
                     &_ => commands::help(&helper),
                 }
@@ -136,3 +504,29 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Unlocks the keyring of the logged in user if they have one, asking for the password. Returns
+/// `false` if they have one that couldn't be unlocked, so that nothing is uploaded without it.
+async fn unlock_keyring<H: History>(
+    url: String,
+    session_token: &str,
+    readline: &mut Editor<helper::CliHelper, H>,
+    keyring: &mut Option<e2e::Keyring>,
+) -> Result<bool> {
+    if keyring.is_some() {
+        return Ok(true);
+    }
+
+    let sealed = match commands::get_keyring(url, session_token).await {
+        Some(Some(k)) => k,
+        Some(None) => return Ok(true),
+        None => return Ok(false),
+    };
+
+    helper::CliHelper::begin_masking(readline);
+    let password = readline.readline("Password to unlock your keyring: ")?;
+    helper::CliHelper::end_masking(readline);
+    *keyring = commands::unlock_keyring(&sealed, &password);
+
+    Ok(keyring.is_some())
+}
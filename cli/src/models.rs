@@ -5,22 +5,28 @@ pub struct User {
     pub id: String,
     pub username: String,
     pub email: String,
+    pub is_email_verified: bool,
     pub added_on: DateTime<Local>,
     pub last_seen_on: DateTime<Local>,
     pub is_active: bool,
+    pub role: String,
+    pub is_password_reset_required: bool,
 }
 
 impl Display for User {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "User {}\n    Username: {}\n    Email: {}\n    Added On: {}\n    Last Seen On: {}\n    Is Active: {}",
+            "User {}\n    Username: {}\n    Email: {}\n    Is Email Verified: {}\n    Role: {}\n    Added On: {}\n    Last Seen On: {}\n    Is Active: {}\n    Is Password Reset Required: {}",
             self.id,
             self.username,
             self.email,
+            self.is_email_verified,
+            self.role,
             self.added_on,
             self.last_seen_on,
-            self.is_active
+            self.is_active,
+            self.is_password_reset_required
         )
     }
 }
@@ -31,6 +37,7 @@ impl From<protobuf::pandorica_common::User> for User {
             id: value.id,
             username: value.username,
             email: value.email.unwrap_or("<no email>".into()),
+            is_email_verified: value.is_email_verified,
             added_on: NaiveDateTime::from_timestamp_micros(value.added_on)
                 .unwrap()
                 .and_local_timezone(Utc)
@@ -42,6 +49,8 @@ impl From<protobuf::pandorica_common::User> for User {
                 .unwrap()
                 .with_timezone(&Local),
             is_active: value.is_active,
+            role: value.role,
+            is_password_reset_required: value.is_password_reset_required,
         }
     }
 }
@@ -52,15 +61,23 @@ pub struct Session {
     pub added_on: DateTime<Local>,
     pub last_used_on: DateTime<Local>,
     pub expires_on: DateTime<Local>,
+    pub client_address: String,
+    pub user_agent: String,
+    pub client_version: String,
+    pub device_name: String,
 }
 
 impl Display for Session {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Session {}\n    User ID: {}\n    Added On: {}\n    Last Used On: {}\n    Expires On: {}",
+            "Session {}\n    User ID: {}\n    Device Name: {}\n    Client Address: {}\n    User Agent: {}\n    Client Version: {}\n    Added On: {}\n    Last Used On: {}\n    Expires On: {}",
             self.id,
             self.user_id,
+            self.device_name,
+            self.client_address,
+            self.user_agent,
+            self.client_version,
             self.added_on,
             self.last_used_on,
             self.expires_on
@@ -88,6 +105,134 @@ impl From<protobuf::pandorica_common::Session> for Session {
                 .and_local_timezone(Utc)
                 .unwrap()
                 .with_timezone(&Local),
+            client_address: value.client_address.unwrap_or("<unknown>".into()),
+            user_agent: value.user_agent.unwrap_or("<unknown>".into()),
+            client_version: value.client_version.unwrap_or("<unknown>".into()),
+            device_name: value.device_name.unwrap_or("<no name>".into()),
+        }
+    }
+}
+
+pub struct AccessToken {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub added_on: DateTime<Local>,
+    pub expires_on: DateTime<Local>,
+    pub last_used_on: String,
+}
+
+impl Display for AccessToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Access Token {}\n    Name: {}\n    Scopes: {}\n    Added On: {}\n    Expires On: {}\n    Last Used On: {}",
+            self.id,
+            self.name,
+            self.scopes.join(", "),
+            self.added_on,
+            self.expires_on,
+            self.last_used_on
+        )
+    }
+}
+
+impl From<protobuf::pandorica_common::AccessToken> for AccessToken {
+    fn from(value: protobuf::pandorica_common::AccessToken) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            scopes: value.scopes,
+            added_on: NaiveDateTime::from_timestamp_micros(value.added_on)
+                .unwrap()
+                .and_local_timezone(Utc)
+                .unwrap()
+                .with_timezone(&Local),
+            expires_on: NaiveDateTime::from_timestamp_micros(value.expires_on)
+                .unwrap()
+                .and_local_timezone(Utc)
+                .unwrap()
+                .with_timezone(&Local),
+            last_used_on: value
+                .last_used_on
+                .and_then(NaiveDateTime::from_timestamp_micros)
+                .map(|l| {
+                    l.and_local_timezone(Utc)
+                        .unwrap()
+                        .with_timezone(&Local)
+                        .to_string()
+                })
+                .unwrap_or("<never>".into()),
+        }
+    }
+}
+
+pub struct MasterKey {
+    pub id: String,
+    pub added_on: DateTime<Local>,
+    pub expires_on: DateTime<Local>,
+    pub is_active: bool,
+}
+
+impl Display for MasterKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Master Key {}\n    Added On: {}\n    Expires On: {}\n    Is Active: {}",
+            self.id, self.added_on, self.expires_on, self.is_active
+        )
+    }
+}
+
+impl From<protobuf::pandorica_admin::MasterKey> for MasterKey {
+    fn from(value: protobuf::pandorica_admin::MasterKey) -> Self {
+        Self {
+            id: value.id,
+            added_on: NaiveDateTime::from_timestamp_micros(value.added_on)
+                .unwrap()
+                .and_local_timezone(Utc)
+                .unwrap()
+                .with_timezone(&Local),
+            expires_on: NaiveDateTime::from_timestamp_micros(value.expires_on)
+                .unwrap()
+                .and_local_timezone(Utc)
+                .unwrap()
+                .with_timezone(&Local),
+            is_active: value.is_active,
+        }
+    }
+}
+
+pub struct File {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub added_on: DateTime<Local>,
+    pub is_end_to_end: bool,
+}
+
+impl Display for File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "File {}\n    Name: {}\n    Size: {} bytes\n    Added On: {}\n    Is End To End: {}",
+            self.id, self.name, self.size, self.added_on, self.is_end_to_end
+        )
+    }
+}
+
+impl From<protobuf::pandorica_file::File> for File {
+    fn from(value: protobuf::pandorica_file::File) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            size: value.size,
+            added_on: NaiveDateTime::from_timestamp_micros(value.added_on)
+                .unwrap()
+                .and_local_timezone(Utc)
+                .unwrap()
+                .with_timezone(&Local),
+            is_end_to_end: value.wrapped_dek.is_some(),
         }
     }
 }
@@ -0,0 +1,309 @@
+// The client side of OPAQUE (RFC 9807). The password never leaves this process: the server
+// only ever sees blinded values and the envelope sealed with what they unblind to.
+//
+// The cipher suite has to match the one the server uses, or no login can succeed.
+
+use opaque_ke::ciphersuite::CipherSuite;
+use opaque_ke::{
+    ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+    ClientRegistrationFinishParameters, CredentialResponse, RegistrationResponse, Ristretto255,
+    TripleDh,
+};
+use protobuf::pandorica_auth::{AuthResponse, RecoverAccountRequest};
+use rand::rngs::OsRng;
+use shared::error::{EmptyResult, OperationResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::e2e::{Keyring, RecoveryKey};
+
+/// Whether the password may be sent to the server for an account from before OPAQUE. Only the
+/// user can turn this on, with `--legacy-password`, since a server could otherwise get the
+/// password of any account by pretending not to know it.
+static IS_LEGACY_PASSWORD_ALLOWED: AtomicBool = AtomicBool::new(false);
+
+pub fn allow_legacy_password() {
+    IS_LEGACY_PASSWORD_ALLOWED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_legacy_password_allowed() -> bool {
+    IS_LEGACY_PASSWORD_ALLOWED.load(Ordering::Relaxed)
+}
+
+pub struct DefaultCipherSuite;
+
+impl CipherSuite for DefaultCipherSuite {
+    type OprfCs = Ristretto255;
+    type KeGroup = Ristretto255;
+    type KeyExchange = TripleDh;
+    type Ksf = argon2::Argon2<'static>;
+}
+
+/// What a request that asks for the password of the logged in user sends along.
+pub enum PasswordProof {
+    Opaque(String),
+    /// The password itself, for an account from before OPAQUE.
+    Password(String),
+}
+
+impl PasswordProof {
+    /// Returns the `password` and `opaque_proof` fields of the request.
+    pub fn into_fields(self) -> (String, Option<String>) {
+        match self {
+            PasswordProof::Opaque(proof) => (String::new(), Some(proof)),
+            PasswordProof::Password(password) => (password, None),
+        }
+    }
+}
+
+/// How a login ended, short of an error.
+pub enum Login {
+    LoggedIn(AuthResponse),
+    /// The password didn't open the server's response. An account from before OPAQUE gets the
+    /// same, and can still log in the old way while the server allows it, if the user asked
+    /// for that.
+    Rejected {
+        is_plaintext_allowed: bool,
+    },
+}
+
+/// Every new account encrypts its files end to end. Returns the recovery key for its keyring
+/// along with the session, to be shown to the user once.
+pub async fn register(
+    url: String,
+    username: String,
+    email: Option<String>,
+    password: &str,
+    device_name: Option<&str>,
+) -> OperationResult<(AuthResponse, RecoveryKey)> {
+    let start = ClientRegistration::<DefaultCipherSuite>::start(&mut OsRng, password.as_bytes())
+        .map_err(|_| anyhow::Error::msg("opaque_registration_failed"))?;
+    let response = crate::client::opaque_registration_start(
+        url.clone(),
+        username.clone(),
+        start.message.serialize().to_vec(),
+    )
+    .await?;
+
+    let upload = finish_registration(start.state, password, &response)?;
+
+    let keyring = Keyring::generate();
+    let recovery_key = RecoveryKey::generate();
+
+    let response = crate::client::opaque_register(
+        url,
+        username,
+        email,
+        upload,
+        Some(keyring.seal(password)?),
+        Some(keyring.seal_for_recovery(&recovery_key)?),
+        device_name,
+    )
+    .await?;
+
+    Ok((response, recovery_key))
+}
+
+pub async fn login(
+    url: String,
+    username: String,
+    password: &str,
+    device_name: Option<&str>,
+) -> OperationResult<Login> {
+    let start = ClientLogin::<DefaultCipherSuite>::start(&mut OsRng, password.as_bytes())
+        .map_err(|_| anyhow::Error::msg("opaque_login_failed"))?;
+    let response = crate::client::opaque_login_start(
+        url.clone(),
+        username,
+        start.message.serialize().to_vec(),
+    )
+    .await?;
+
+    let credential_finalization =
+        match finish_login(start.state, password, &response.credential_response)? {
+            Some(f) => f,
+            None => {
+                return Ok(Login::Rejected {
+                    is_plaintext_allowed: response.is_plaintext_allowed,
+                })
+            }
+        };
+
+    let response = crate::client::opaque_login_finish(
+        url,
+        response.login_id,
+        credential_finalization,
+        device_name,
+    )
+    .await?;
+
+    Ok(Login::LoggedIn(response))
+}
+
+/// Registers `new_password` for the logged in user, and seals their keyring with it.
+pub async fn set_password(
+    url: String,
+    session_token: &str,
+    current_password: String,
+    new_password: &str,
+) -> EmptyResult {
+    let start =
+        ClientRegistration::<DefaultCipherSuite>::start(&mut OsRng, new_password.as_bytes())
+            .map_err(|_| anyhow::Error::msg("opaque_registration_failed"))?;
+    let response = crate::client::opaque_password_start(
+        url.clone(),
+        session_token,
+        start.message.serialize().to_vec(),
+    )
+    .await?;
+
+    let upload = finish_registration(start.state, new_password, &response.registration_response)?;
+
+    let keyring = match response.keyring {
+        Some(k) => Some(Keyring::unlock(&k, &current_password)?.seal(new_password)?),
+        None => None,
+    };
+    let current_password = password_proof(url.clone(), session_token, current_password).await?;
+
+    crate::client::opaque_set_password(url, session_token, current_password, upload, keyring).await
+}
+
+/// Sets a new password for a user who lost theirs, with their recovery key, which is replaced by
+/// the one returned.
+pub async fn recover_account(
+    url: String,
+    username: String,
+    recovery_key: &RecoveryKey,
+    new_password: &str,
+) -> OperationResult<RecoveryKey> {
+    let start =
+        ClientRegistration::<DefaultCipherSuite>::start(&mut OsRng, new_password.as_bytes())
+            .map_err(|_| anyhow::Error::msg("opaque_registration_failed"))?;
+    let response = crate::client::recover_account_start(
+        url.clone(),
+        username,
+        start.message.serialize().to_vec(),
+    )
+    .await?;
+
+    let upload = finish_registration(start.state, new_password, &response.registration_response)?;
+
+    let sealed = response
+        .recovery_key
+        .ok_or_else(|| anyhow::Error::msg("account_recovery_unavailable"))?;
+    let keyring = Keyring::unlock_with_recovery(
+        &sealed,
+        &response.public_key,
+        &response.signing_public_key,
+        recovery_key,
+    )?;
+
+    let new_recovery_key = RecoveryKey::generate();
+    let new_sealed = keyring.seal_for_recovery(&new_recovery_key)?;
+    let signature = recovery_key.sign(&response.recovery_id, &upload, &new_sealed.verifying_key);
+
+    crate::client::recover_account(
+        url,
+        RecoverAccountRequest {
+            recovery_id: response.recovery_id,
+            registration_upload: upload,
+            keyring: Some(keyring.seal(new_password)?),
+            recovery_key: Some(new_sealed),
+            signature,
+        },
+    )
+    .await?;
+
+    Ok(new_recovery_key)
+}
+
+/// Sets a new password with a token from a password reset mail.
+pub async fn reset_password(url: String, token: String, new_password: &str) -> EmptyResult {
+    let start =
+        ClientRegistration::<DefaultCipherSuite>::start(&mut OsRng, new_password.as_bytes())
+            .map_err(|_| anyhow::Error::msg("opaque_registration_failed"))?;
+    let response = crate::client::reset_password_start(
+        url.clone(),
+        token.clone(),
+        start.message.serialize().to_vec(),
+    )
+    .await?;
+
+    let upload = finish_registration(start.state, new_password, &response)?;
+
+    crate::client::reset_password(url, token, upload).await
+}
+
+/// Proves the password of the logged in user again, for the requests that ask for it. The
+/// server only takes the proof, unless the account is from before OPAQUE, in which case the
+/// password itself is returned to be sent.
+pub async fn password_proof(
+    url: String,
+    session_token: &str,
+    password: String,
+) -> OperationResult<PasswordProof> {
+    let start = ClientLogin::<DefaultCipherSuite>::start(&mut OsRng, password.as_bytes())
+        .map_err(|_| anyhow::Error::msg("opaque_login_failed"))?;
+    let response = crate::client::opaque_reauthenticate_start(
+        url.clone(),
+        session_token,
+        start.message.serialize().to_vec(),
+    )
+    .await?;
+    let response = match response {
+        Some(r) => r,
+        None if is_legacy_password_allowed() => return Ok(PasswordProof::Password(password)),
+        None => return Err(anyhow::Error::msg("opaque_not_registered").into()),
+    };
+
+    let credential_finalization =
+        finish_login(start.state, &password, &response.credential_response)?
+            .ok_or_else(|| anyhow::Error::msg("invalid_password"))?;
+
+    let proof = crate::client::opaque_reauthenticate_finish(
+        url,
+        session_token,
+        response.login_id,
+        credential_finalization,
+    )
+    .await?;
+
+    Ok(PasswordProof::Opaque(proof))
+}
+
+fn finish_registration(
+    state: ClientRegistration<DefaultCipherSuite>,
+    password: &str,
+    response: &[u8],
+) -> OperationResult<Vec<u8>> {
+    let response = RegistrationResponse::deserialize(response)
+        .map_err(|_| anyhow::Error::msg("invalid_opaque_message"))?;
+    let result = state
+        .finish(
+            &mut OsRng,
+            password.as_bytes(),
+            response,
+            ClientRegistrationFinishParameters::default(),
+        )
+        .map_err(|_| anyhow::Error::msg("opaque_registration_failed"))?;
+
+    Ok(result.message.serialize().to_vec())
+}
+
+/// Returns the last message of a login, or `None` if the password doesn't open the response.
+fn finish_login(
+    state: ClientLogin<DefaultCipherSuite>,
+    password: &str,
+    response: &[u8],
+) -> OperationResult<Option<Vec<u8>>> {
+    let response = CredentialResponse::deserialize(response)
+        .map_err(|_| anyhow::Error::msg("invalid_opaque_message"))?;
+
+    match state.finish(
+        password.as_bytes(),
+        response,
+        ClientLoginFinishParameters::default(),
+    ) {
+        Ok(result) => Ok(Some(result.message.serialize().to_vec())),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,13 @@
+use crate::helpers::breach_filter::BreachFilter;
+use shared::error::EmptyResult;
+use std::path::Path;
+
+pub fn build(input: &str, output: &str, false_positive_rate: f64) -> EmptyResult {
+    println!("Building {} from {}...", output, input);
+
+    let count = BreachFilter::build(Path::new(input), Path::new(output), false_positive_rate)?;
+
+    println!("Added {} hashes to {}", count, output);
+
+    Ok(())
+}
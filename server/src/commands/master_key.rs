@@ -0,0 +1,87 @@
+use crate::kms::backup::MasterKeyShare;
+use crate::kms::KeyManagementSystem;
+use secret_vault_value::SecretValue;
+use shared::error::{EmptyResult, OperationResult};
+use singleton::sync::Singleton;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+pub async fn export(id: Option<String>, threshold: u8, shares: u8, out_dir: &str) -> EmptyResult {
+    MasterKeyShare::verify_threshold(threshold, shares as usize)?;
+
+    let mut passphrases = Vec::with_capacity(shares as usize);
+    for custodian in 1..=shares {
+        passphrases.push(prompt_new_passphrase(custodian)?);
+    }
+
+    let exported = KeyManagementSystem::lock()
+        .await
+        .export_master_key(id.as_deref(), threshold, &passphrases)
+        .await?;
+
+    for (index, share) in exported.iter().enumerate() {
+        let file_name = format!(
+            "{}-{}.share",
+            share.master_key_id.replace(':', "-"),
+            index + 1
+        );
+        let path = Path::new(out_dir).join(file_name);
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut share_file = options.open(&path)?;
+        share_file.write_all(&share.to_bytes())?;
+        share_file.flush()?;
+
+        println!("Share {} for custodian {}", path.display(), index + 1);
+    }
+
+    println!(
+        "Any {} of the {} shares can recover {}",
+        threshold, shares, exported[0].master_key_id
+    );
+
+    Ok(())
+}
+
+pub async fn recover(paths: &[String]) -> EmptyResult {
+    let mut shares = Vec::with_capacity(paths.len());
+    for path in paths {
+        let share = MasterKeyShare::parse(&std::fs::read(path)?)?;
+        let passphrase = SecretValue::from(rpassword::prompt_password(format!(
+            "Passphrase for {}: ",
+            path
+        ))?);
+        shares.push((share, passphrase));
+    }
+
+    let master_key_id = KeyManagementSystem::lock()
+        .await
+        .recover_master_key(&shares)
+        .await?;
+
+    println!(
+        "Recovered {} and wrapped it under the configured HSM key",
+        master_key_id
+    );
+
+    Ok(())
+}
+
+fn prompt_new_passphrase(custodian: u8) -> OperationResult<SecretValue> {
+    let passphrase =
+        rpassword::prompt_password(format!("Passphrase for custodian {}: ", custodian))?;
+    if passphrase.is_empty() {
+        return Err(anyhow::Error::msg("invalid_master_key_backup__passphrase_empty").into());
+    }
+
+    let confirmation = rpassword::prompt_password("Repeat the passphrase: ")?;
+    if passphrase != confirmation {
+        return Err(anyhow::Error::msg("invalid_master_key_backup__passphrase_mismatch").into());
+    }
+
+    Ok(SecretValue::from(passphrase))
+}
@@ -0,0 +1,4 @@
+pub mod breach_filter;
+pub mod master_key;
+pub mod role;
+pub mod throttle;
@@ -0,0 +1,27 @@
+use crate::models::auth::Role;
+use crate::repos;
+use shared::error::EmptyResult;
+
+/// Gives a user a role. This is how the first admin comes to be.
+pub async fn set(username: &str, role: &str) -> EmptyResult {
+    let role = match Role::parse(role) {
+        Some(r) => r,
+        None => {
+            return Err(
+                anyhow::Error::msg("Unknown role, pass one of user, auditor or admin").into(),
+            )
+        }
+    };
+
+    let user = repos::user::read_by_username(username).await?;
+    if user.is_none() {
+        return Err(anyhow::format_err!("User {} not found", username).into());
+    }
+    let mut user = user.unwrap();
+
+    user.role = role;
+    repos::user::update_role(&user).await?;
+    println!("User {} is now {}", username, role.as_str());
+
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use crate::helpers::throttle;
+use crate::repos;
+use shared::error::EmptyResult;
+use std::net::IpAddr;
+
+/// Lifts the backoff or lockout of a username, a client address, or both.
+pub async fn unlock(username: Option<String>, client: Option<IpAddr>) -> EmptyResult {
+    if username.is_none() && client.is_none() {
+        return Err(anyhow::Error::msg("Nothing to unlock, pass --username or --client").into());
+    }
+
+    if let Some(username) = username {
+        repos::login_throttle::delete_by_key(&throttle::username_key(&username)).await?;
+        println!("Unlocked username {}", username);
+    }
+
+    if let Some(client) = client {
+        repos::login_throttle::delete_by_key(&throttle::client_key(client)).await?;
+        println!("Unlocked client {}", client);
+    }
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
-use crypto::hsm::HsmSettings;
+use crate::kms::cipher::Algorithm;
+use crypto::hsm::HsmSettings as CloudHsmSettings;
 use serde::{Deserialize, Serialize};
 use singleton::{Singleton, SingletonInit};
 use std::borrow::Cow;
@@ -12,7 +13,19 @@ pub struct Settings {
     pub listen_addr: Cow<'static, str>,
     pub db: DatabaseSettings,
     pub hsm: HsmSettings,
+    #[serde(default)]
+    pub kms: KmsSettings,
+    #[serde(default)]
+    pub session: SessionSettings,
+    #[serde(default)]
+    pub totp: TotpSettings,
+    #[serde(default)]
+    pub access_token: AccessTokenSettings,
+    #[serde(default)]
+    pub security: SecuritySettings,
     pub fs: FilesystemSettings,
+    #[serde(default)]
+    pub mail: MailSettings,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -23,9 +36,189 @@ pub struct DatabaseSettings {
     pub pass: Cow<'static, str>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct HsmSettings {
+    /// Configs written before the provider could be chosen are all for the Cloud HSM.
+    #[serde(default = "HsmSettings::default_provider")]
+    pub provider: Cow<'static, str>,
+    pub software: Option<SoftwareHsmSettings>,
+    pub aws: Option<AwsKmsSettings>,
+    pub vault: Option<VaultTransitSettings>,
+    pub pkcs11: Option<Pkcs11Settings>,
+    #[serde(flatten)]
+    pub cloud: CloudHsmSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SoftwareHsmSettings {
+    pub key_file: Option<Cow<'static, str>>,
+    pub key_env: Option<Cow<'static, str>>,
+    pub passphrase_env: Cow<'static, str>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AwsKmsSettings {
+    pub key_id: Cow<'static, str>,
+    pub region: Cow<'static, str>,
+    pub endpoint: Option<Cow<'static, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VaultTransitSettings {
+    pub addr: Cow<'static, str>,
+    pub mount: Cow<'static, str>,
+    pub key: Cow<'static, str>,
+    pub token_env: Cow<'static, str>,
+    pub namespace: Option<Cow<'static, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Pkcs11Settings {
+    pub module_path: Cow<'static, str>,
+    pub slot: u64,
+    pub key_label: Cow<'static, str>,
+    pub pin_env: Cow<'static, str>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct KmsSettings {
+    /// The cipher used for new DEKs, key encryption keys and encrypted values. Existing records
+    /// keep the one they were written with.
+    pub algorithm: Algorithm,
+    pub rotation_period_days: i64,
+    pub rotation_check_interval_minutes: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// A session expires once it has not been used for this long.
+    pub idle_timeout_minutes: i64,
+    /// Neither a session nor the refresh tokens descending from the same login outlive this.
+    pub absolute_timeout_hours: i64,
+    /// How often the activity timestamps of sessions, access tokens and users are written.
+    /// The idle timeout is only accurate to about this much.
+    pub activity_write_interval_seconds: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct TotpSettings {
+    /// The name authenticator apps list the account under.
+    pub issuer: Cow<'static, str>,
+    /// How long a login waiting for its second factor stays valid.
+    pub pending_timeout_minutes: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessTokenSettings {
+    /// Used when a personal access token is created without a lifetime.
+    pub default_lifetime_days: u32,
+    pub max_lifetime_days: u32,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SecuritySettings {
+    pub password: PasswordSettings,
+    pub login: LoginSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordSettings {
+    /// Lengths are counted in characters. The maximum also bounds the work a login can cause.
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+    /// The lowest zxcvbn score, from 0 to 4, that a new password may have. Not checked if unset.
+    pub min_strength_score: Option<u8>,
+    /// A filter built with `pandorica build-breach-filter`. Passwords found in it are rejected.
+    pub breach_filter: Option<Cow<'static, str>>,
+    /// How many of the most recent passwords can't be chosen again.
+    pub history_size: u32,
+    /// Changing any of the Argon2id costs re-hashes passwords as their owners log in.
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    /// Whether clients may still send passwords to the server, as they did before OPAQUE. The
+    /// CLI moves an account over to OPAQUE the next time it logs in, but until then the account
+    /// can't log in without this.
+    pub allow_plaintext: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoginSettings {
+    /// Failed logins older than this are forgotten.
+    pub failure_window_minutes: i64,
+    /// After this many failures, every further one makes the next login wait twice as long,
+    /// starting at `backoff_base_seconds`.
+    pub backoff_after_failures: u32,
+    pub backoff_base_seconds: i64,
+    pub backoff_max_seconds: i64,
+    /// Failures after which a username, or a client address, is locked out for
+    /// `lockout_minutes`. `pandorica unlock` lifts a lockout early.
+    pub lockout_after_failures: u32,
+    pub client_lockout_after_failures: u32,
+    pub lockout_minutes: i64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct FilesystemSettings {
     pub provider: Cow<'static, str>,
+    pub s3: Option<S3Settings>,
+    /// Uploads larger than this are rejected.
+    #[serde(default = "FilesystemSettings::default_max_upload_size_mib")]
+    pub max_upload_size_mib: u64,
+    /// An upload that hasn't finished after this long is given up on, and what it stored so far
+    /// is deleted.
+    #[serde(default = "FilesystemSettings::default_stale_upload_hours")]
+    pub stale_upload_hours: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct S3Settings {
+    pub endpoint: Option<Cow<'static, str>>,
+    pub bucket: Cow<'static, str>,
+    pub region: Cow<'static, str>,
+    pub access_key_id: Option<Cow<'static, str>>,
+    pub secret_access_key: Option<Cow<'static, str>>,
+    pub path_style: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct MailSettings {
+    /// Either `smtp`, or `spool` to write every mail to a directory instead of sending it.
+    pub provider: Cow<'static, str>,
+    pub from: Cow<'static, str>,
+    pub spool: Option<SpoolMailSettings>,
+    pub smtp: Option<SmtpMailSettings>,
+    pub verification_lifetime_hours: i64,
+    pub password_reset_lifetime_minutes: i64,
+    /// A user is sent at most one mail of each kind this often, however many they ask for.
+    pub resend_interval_seconds: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SpoolMailSettings {
+    pub dir: Cow<'static, str>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SmtpMailSettings {
+    pub host: Cow<'static, str>,
+    pub port: u16,
+    /// One of `tls`, `starttls`, or `none` for a local sink like MailHog.
+    pub security: Cow<'static, str>,
+    pub username: Option<Cow<'static, str>>,
+    pub password_env: Option<Cow<'static, str>>,
 }
 
 impl SingletonInit<Settings> for Settings {
@@ -77,10 +270,132 @@ impl Default for Settings {
                 user: "root".into(),
                 pass: "root".into(),
             },
-            hsm: HsmSettings::default(),
+            hsm: HsmSettings {
+                provider: "software".into(),
+                software: Some(SoftwareHsmSettings {
+                    key_file: Some("master.kek".into()),
+                    key_env: None,
+                    passphrase_env: "PANDORICA_HSM_PASSPHRASE".into(),
+                }),
+                aws: None,
+                vault: None,
+                pkcs11: None,
+                cloud: CloudHsmSettings::default(),
+            },
+            kms: KmsSettings::default(),
+            session: SessionSettings::default(),
+            totp: TotpSettings::default(),
+            access_token: AccessTokenSettings::default(),
+            security: SecuritySettings::default(),
             fs: FilesystemSettings {
                 provider: "memory".into(),
+                s3: None,
+                max_upload_size_mib: FilesystemSettings::default_max_upload_size_mib(),
+                stale_upload_hours: FilesystemSettings::default_stale_upload_hours(),
             },
+            mail: MailSettings::default(),
+        }
+    }
+}
+
+impl HsmSettings {
+    fn default_provider() -> Cow<'static, str> {
+        "gcp".into()
+    }
+}
+
+impl FilesystemSettings {
+    fn default_max_upload_size_mib() -> u64 {
+        1024
+    }
+
+    fn default_stale_upload_hours() -> i64 {
+        24
+    }
+}
+
+impl Default for KmsSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::XChaCha20Poly1305,
+            rotation_period_days: 90,
+            rotation_check_interval_minutes: 60,
+        }
+    }
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            idle_timeout_minutes: 30,
+            absolute_timeout_hours: 168,
+            activity_write_interval_seconds: 60,
+        }
+    }
+}
+
+impl Default for TotpSettings {
+    fn default() -> Self {
+        Self {
+            issuer: "Pandorica".into(),
+            pending_timeout_minutes: 5,
+        }
+    }
+}
+
+impl Default for AccessTokenSettings {
+    fn default() -> Self {
+        Self {
+            default_lifetime_days: 30,
+            max_lifetime_days: 365,
+        }
+    }
+}
+
+impl Default for PasswordSettings {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_special: true,
+            min_strength_score: None,
+            breach_filter: None,
+            history_size: 5,
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            allow_plaintext: true,
+        }
+    }
+}
+
+impl Default for LoginSettings {
+    fn default() -> Self {
+        Self {
+            failure_window_minutes: 60,
+            backoff_after_failures: 3,
+            backoff_base_seconds: 1,
+            backoff_max_seconds: 60,
+            lockout_after_failures: 10,
+            client_lockout_after_failures: 100,
+            lockout_minutes: 15,
+        }
+    }
+}
+
+impl Default for MailSettings {
+    fn default() -> Self {
+        Self {
+            provider: "spool".into(),
+            from: "Pandorica <pandorica@localhost>".into(),
+            spool: Some(SpoolMailSettings { dir: "mail".into() }),
+            smtp: None,
+            verification_lifetime_hours: 48,
+            password_reset_lifetime_minutes: 30,
+            resend_interval_seconds: 60,
         }
     }
 }
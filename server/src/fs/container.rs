@@ -0,0 +1,445 @@
+// Encrypted file container, version 2
+//
+// | magic "PDRC" | version: u8 | algorithm: u8 | chunk size: u32 BE | DEK length: u32 BE | DEK |
+//
+// The algorithm is 1 for XChaCha20-Poly1305 and 2 for AES-256-GCM-SIV. The header is followed by
+// the sealed chunks. Every chunk except the last one holds exactly `chunk size` bytes of
+// plaintext, and the last one may be empty. Each chunk is sealed with a nonce made of the DEK
+// nonce minus its last 5 bytes, a 32-bit BE chunk counter and a final-chunk flag. Reordered,
+// truncated or extended files therefore fail to decrypt.
+//
+// The header up to and including the chunk size is authenticated as associated data of every
+// chunk. The wrapped DEK is left out so that it can be re-wrapped under a new master key without
+// touching the chunks.
+
+use crate::kms::cipher::{Algorithm, Cipher};
+use crate::models::crypto::Dek;
+use shared::error::OperationResult;
+
+pub const MAGIC: &[u8; 4] = b"PDRC";
+pub const VERSION: u8 = 2;
+pub const ALGORITHM_XCHACHA20POLY1305: u8 = 1;
+pub const ALGORITHM_AES256GCMSIV: u8 = 2;
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+const MAX_DEK_SIZE: usize = 4 * 1024;
+const FIXED_HEADER_SIZE: usize = 14;
+const AUTHENTICATED_HEADER_SIZE: usize = 10;
+const NONCE_SUFFIX_SIZE: usize = 5;
+const TAG_SIZE: usize = 16;
+
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub chunk_size: u32,
+    pub dek: Vec<u8>,
+}
+
+impl Header {
+    /// Chunks are authenticated together with the header, so `algorithm` has to support
+    /// associated data.
+    pub fn new(dek: Vec<u8>, algorithm: Algorithm) -> Self {
+        Self {
+            version: VERSION,
+            algorithm,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            dek,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FIXED_HEADER_SIZE + self.dek.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(self.version);
+        bytes.push(match self.algorithm {
+            Algorithm::Aes256GcmSiv => ALGORITHM_AES256GCMSIV,
+            _ => ALGORITHM_XCHACHA20POLY1305,
+        });
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.extend_from_slice(&(self.dek.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.dek);
+        bytes
+    }
+
+    /// Returns the header and its encoded length, or `None` if `buffer` is too short to hold it.
+    pub fn parse(buffer: &[u8]) -> OperationResult<Option<(Header, usize)>> {
+        if buffer.len() < FIXED_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        if &buffer[..4] != MAGIC {
+            return Err(anyhow::Error::msg("invalid_file__magic").into());
+        }
+
+        let version = buffer[4];
+        if version != VERSION {
+            return Err(anyhow::Error::msg("invalid_file__version").into());
+        }
+
+        let algorithm = match buffer[5] {
+            ALGORITHM_XCHACHA20POLY1305 => Algorithm::XChaCha20Poly1305,
+            ALGORITHM_AES256GCMSIV => Algorithm::Aes256GcmSiv,
+            _ => return Err(anyhow::Error::msg("invalid_file__algorithm").into()),
+        };
+
+        let chunk_size = u32::from_be_bytes(buffer[6..10].try_into()?);
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(anyhow::Error::msg("invalid_file__chunk_size").into());
+        }
+
+        let dek_size = u32::from_be_bytes(buffer[10..14].try_into()?) as usize;
+        if dek_size > MAX_DEK_SIZE {
+            return Err(anyhow::Error::msg("invalid_file__dek_size").into());
+        }
+
+        if buffer.len() < FIXED_HEADER_SIZE + dek_size {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            Header {
+                version,
+                algorithm,
+                chunk_size,
+                dek: buffer[FIXED_HEADER_SIZE..FIXED_HEADER_SIZE + dek_size].to_vec(),
+            },
+            FIXED_HEADER_SIZE + dek_size,
+        )))
+    }
+}
+
+struct ChunkCipher {
+    cipher: Cipher,
+    nonce_prefix: Vec<u8>,
+    associated_data: Vec<u8>,
+    counter: u32,
+    is_finished: bool,
+}
+
+impl ChunkCipher {
+    fn new(header: &Header, dek: &Dek<'_>) -> OperationResult<Self> {
+        let mut associated_data = header.to_bytes();
+        associated_data.truncate(AUTHENTICATED_HEADER_SIZE);
+
+        if !header.algorithm.supports_associated_data() {
+            return Err(anyhow::Error::msg("invalid_file__algorithm").into());
+        }
+        let cipher = Cipher::new(header.algorithm, &dek.decoded_key)?;
+
+        if dek.nonce.len() != header.algorithm.nonce_size() {
+            return Err(anyhow::Error::msg("invalid_dek__nonce").into());
+        }
+        let nonce_prefix = dek.nonce[..dek.nonce.len() - NONCE_SUFFIX_SIZE].to_vec();
+
+        Ok(Self {
+            cipher,
+            nonce_prefix,
+            associated_data,
+            counter: 0,
+            is_finished: false,
+        })
+    }
+
+    fn next_nonce(&mut self, is_final: bool) -> OperationResult<Vec<u8>> {
+        if self.is_finished {
+            return Err(anyhow::Error::msg("invalid_file__chunk_after_final").into());
+        }
+
+        let mut nonce = Vec::with_capacity(self.nonce_prefix.len() + NONCE_SUFFIX_SIZE);
+        nonce.extend_from_slice(&self.nonce_prefix);
+        nonce.extend_from_slice(&self.counter.to_be_bytes());
+        nonce.push(is_final as u8);
+
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| anyhow::Error::msg("invalid_file__too_many_chunks"))?;
+        self.is_finished = is_final;
+
+        Ok(nonce)
+    }
+
+    fn seal(&mut self, chunk: &[u8], is_final: bool) -> OperationResult<Vec<u8>> {
+        let nonce = self.next_nonce(is_final)?;
+
+        self.cipher
+            .encrypt(&nonce, chunk, &self.associated_data)
+            .map_err(|_| anyhow::Error::msg("invalid_file__encryption").into())
+    }
+
+    fn open(&mut self, chunk: &[u8], is_final: bool) -> OperationResult<Vec<u8>> {
+        let nonce = self.next_nonce(is_final)?;
+
+        self.cipher
+            .decrypt(&nonce, chunk, &self.associated_data)
+            .map_err(|_| anyhow::Error::msg("invalid_file__chunk").into())
+    }
+}
+
+pub struct Encoder {
+    cipher: ChunkCipher,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new(header: &Header, dek: &Dek<'_>) -> OperationResult<Self> {
+        Ok(Self {
+            cipher: ChunkCipher::new(header, dek)?,
+            chunk_size: header.chunk_size as usize,
+            buffer: Vec::with_capacity(header.chunk_size as usize),
+        })
+    }
+
+    /// Buffers `data` and returns every chunk that is known not to be the final one.
+    pub fn update(&mut self, data: &[u8]) -> OperationResult<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut sealed = Vec::new();
+        let mut offset = 0;
+        while self.buffer.len() - offset > self.chunk_size {
+            let chunk = &self.buffer[offset..offset + self.chunk_size];
+            sealed.extend(self.cipher.seal(chunk, false)?);
+            offset += self.chunk_size;
+        }
+        self.buffer.drain(..offset);
+
+        Ok(sealed)
+    }
+
+    pub fn finalize(&mut self) -> OperationResult<Vec<u8>> {
+        let sealed = self.cipher.seal(&self.buffer, true)?;
+        self.buffer.clear();
+
+        Ok(sealed)
+    }
+}
+
+pub struct Decoder {
+    cipher: ChunkCipher,
+    sealed_chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(header: &Header, dek: &Dek<'_>) -> OperationResult<Self> {
+        let sealed_chunk_size = header.chunk_size as usize + TAG_SIZE;
+
+        Ok(Self {
+            cipher: ChunkCipher::new(header, dek)?,
+            sealed_chunk_size,
+            buffer: Vec::with_capacity(sealed_chunk_size),
+        })
+    }
+
+    /// Buffers `data` and returns the plaintext of every chunk that is known not to be the
+    /// final one.
+    pub fn update(&mut self, data: &[u8]) -> OperationResult<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut plaintext = Vec::new();
+        let mut offset = 0;
+        while self.buffer.len() - offset > self.sealed_chunk_size {
+            let chunk = &self.buffer[offset..offset + self.sealed_chunk_size];
+            plaintext.extend(self.cipher.open(chunk, false)?);
+            offset += self.sealed_chunk_size;
+        }
+        self.buffer.drain(..offset);
+
+        Ok(plaintext)
+    }
+
+    pub fn finalize(&mut self) -> OperationResult<Vec<u8>> {
+        if self.buffer.len() < TAG_SIZE {
+            return Err(anyhow::Error::msg("invalid_file__truncated").into());
+        }
+
+        let plaintext = self.cipher.open(&self.buffer, true)?;
+        self.buffer.clear();
+
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secret_vault_value::SecretValue;
+
+    const CHUNK_SIZE: u32 = 16;
+    const SEALED_CHUNK_SIZE: usize = CHUNK_SIZE as usize + TAG_SIZE;
+    const ALGORITHMS: [Algorithm; 2] = [Algorithm::XChaCha20Poly1305, Algorithm::Aes256GcmSiv];
+
+    fn dek(algorithm: Algorithm) -> Dek<'static> {
+        Dek::new(
+            SecretValue::from(vec![7_u8; 32]),
+            vec![9_u8; algorithm.nonce_size()],
+            "key_encryption_key:test".to_string(),
+            algorithm,
+            Vec::new(),
+            Vec::new(),
+        )
+    }
+
+    fn header(algorithm: Algorithm) -> Header {
+        let mut header = Header::new(b"wrapped DEK".to_vec(), algorithm);
+        header.chunk_size = CHUNK_SIZE;
+        header
+    }
+
+    /// Seals `plaintext`, handing it to the encoder `piece` bytes at a time.
+    fn seal(header: &Header, dek: &Dek<'_>, plaintext: &[u8], piece: usize) -> Vec<u8> {
+        let mut encoder = Encoder::new(header, dek).unwrap();
+        let mut sealed = Vec::new();
+        for data in plaintext.chunks(piece) {
+            sealed.extend(encoder.update(data).unwrap());
+        }
+        sealed.extend(encoder.finalize().unwrap());
+        sealed
+    }
+
+    /// Opens `sealed`, handing it to the decoder `piece` bytes at a time.
+    fn open(
+        header: &Header,
+        dek: &Dek<'_>,
+        sealed: &[u8],
+        piece: usize,
+    ) -> OperationResult<Vec<u8>> {
+        let mut decoder = Decoder::new(header, dek)?;
+        let mut plaintext = Vec::new();
+        for data in sealed.chunks(piece) {
+            plaintext.extend(decoder.update(data)?);
+        }
+        plaintext.extend(decoder.finalize()?);
+        Ok(plaintext)
+    }
+
+    fn plaintext(size: usize) -> Vec<u8> {
+        (0..size).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn round_trips_at_any_size() {
+        for algorithm in ALGORITHMS {
+            let header = header(algorithm);
+            let dek = dek(algorithm);
+
+            for size in [0, 1, 15, 16, 17, 32, 33, 100] {
+                for piece in [1, 7, 16, 1000] {
+                    let plaintext = plaintext(size);
+                    let sealed = seal(&header, &dek, &plaintext, piece);
+                    assert_eq!(open(&header, &dek, &sealed, piece).unwrap(), plaintext);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn an_empty_file_is_one_empty_final_chunk() {
+        let header = header(Algorithm::XChaCha20Poly1305);
+        let dek = dek(Algorithm::XChaCha20Poly1305);
+
+        let sealed = seal(&header, &dek, &[], 1);
+        assert_eq!(sealed.len(), TAG_SIZE);
+        assert!(open(&header, &dek, &sealed, 1).unwrap().is_empty());
+
+        assert!(open(&header, &dek, &[], 1).is_err());
+    }
+
+    #[test]
+    fn only_the_last_chunk_is_final() {
+        let header = header(Algorithm::XChaCha20Poly1305);
+        let dek = dek(Algorithm::XChaCha20Poly1305);
+
+        // A full last chunk is the final one, rather than followed by an empty one
+        let sealed = seal(&header, &dek, &plaintext(2 * CHUNK_SIZE as usize), 1);
+        assert_eq!(sealed.len(), 2 * SEALED_CHUNK_SIZE);
+
+        let mut cipher = ChunkCipher::new(&header, &dek).unwrap();
+        cipher.seal(b"final", true).unwrap();
+        assert!(cipher.seal(b"after", false).is_err());
+    }
+
+    #[test]
+    fn truncated_files_fail() {
+        let header = header(Algorithm::XChaCha20Poly1305);
+        let dek = dek(Algorithm::XChaCha20Poly1305);
+        let sealed = seal(&header, &dek, &plaintext(40), 1000);
+        assert_eq!(sealed.len(), 2 * SEALED_CHUNK_SIZE + 8 + TAG_SIZE);
+
+        // Cut at a chunk boundary, the last chunk left was sealed as one that isn't final
+        assert!(open(&header, &dek, &sealed[..2 * SEALED_CHUNK_SIZE], 1000).is_err());
+        assert!(open(&header, &dek, &sealed[..SEALED_CHUNK_SIZE], 1000).is_err());
+        assert!(open(&header, &dek, &sealed[..sealed.len() - 1], 1000).is_err());
+    }
+
+    #[test]
+    fn extended_files_fail() {
+        let header = header(Algorithm::XChaCha20Poly1305);
+        let dek = dek(Algorithm::XChaCha20Poly1305);
+        let sealed = seal(&header, &dek, &plaintext(40), 1000);
+
+        let mut extended = sealed.clone();
+        extended.extend_from_slice(&sealed[..SEALED_CHUNK_SIZE]);
+        assert!(open(&header, &dek, &extended, 1000).is_err());
+    }
+
+    #[test]
+    fn reordered_chunks_fail() {
+        let header = header(Algorithm::XChaCha20Poly1305);
+        let dek = dek(Algorithm::XChaCha20Poly1305);
+        let sealed = seal(&header, &dek, &plaintext(40), 1000);
+
+        let mut reordered = Vec::new();
+        reordered.extend_from_slice(&sealed[SEALED_CHUNK_SIZE..2 * SEALED_CHUNK_SIZE]);
+        reordered.extend_from_slice(&sealed[..SEALED_CHUNK_SIZE]);
+        reordered.extend_from_slice(&sealed[2 * SEALED_CHUNK_SIZE..]);
+        assert!(open(&header, &dek, &reordered, 1000).is_err());
+    }
+
+    #[test]
+    fn the_header_is_authenticated_except_for_the_dek() {
+        let dek = dek(Algorithm::Aes256GcmSiv);
+        let sealed = seal(&header(Algorithm::Aes256GcmSiv), &dek, &plaintext(40), 1000);
+
+        let mut rewrapped = header(Algorithm::Aes256GcmSiv);
+        rewrapped.dek = b"re-wrapped DEK".to_vec();
+        assert_eq!(
+            open(&rewrapped, &dek, &sealed, 1000).unwrap(),
+            plaintext(40)
+        );
+
+        let mut resized = header(Algorithm::Aes256GcmSiv);
+        resized.chunk_size = 2 * CHUNK_SIZE;
+        assert!(open(&resized, &dek, &sealed, 1000).is_err());
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let header = header(Algorithm::Aes256GcmSiv);
+        let bytes = header.to_bytes();
+
+        let (parsed, size) = Header::parse(&bytes).unwrap().unwrap();
+        assert_eq!(size, bytes.len());
+        assert_eq!(parsed.version, VERSION);
+        assert_eq!(parsed.chunk_size, CHUNK_SIZE);
+        assert_eq!(parsed.dek, header.dek);
+        assert_eq!(parsed.algorithm, Algorithm::Aes256GcmSiv);
+
+        assert!(Header::parse(&bytes[..bytes.len() - 1]).unwrap().is_none());
+        assert!(Header::parse(&bytes[..FIXED_HEADER_SIZE - 1])
+            .unwrap()
+            .is_none());
+
+        let mut corrupted = bytes.clone();
+        corrupted[0] = b'X';
+        assert!(Header::parse(&corrupted).is_err());
+
+        for version in [0, 1, VERSION + 1] {
+            let mut corrupted = bytes.clone();
+            corrupted[4] = version;
+            assert!(Header::parse(&corrupted).is_err());
+        }
+    }
+}
@@ -1,38 +1,404 @@
+use crate::config::{FilesystemSettings, S3Settings, Settings};
+use crate::fs::container::{Decoder, Encoder, Header};
+use crate::kms::cipher::Algorithm;
+use crate::kms::KeyManagementSystem;
+use crate::models::crypto::Dek;
+use bytes::Bytes;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
 use object_store::local::LocalFileSystem;
 use object_store::memory::InMemory;
-use object_store::ObjectStore;
+use object_store::path::Path;
+use object_store::{MultipartId, ObjectStore};
+use shared::error::{EmptyResult, OperationResult};
+use singleton::{
+    sync::Singleton as SyncSingleton, unsync::Singleton as UnsyncSingleton, Singleton,
+    SingletonInit,
+};
+use std::pin::Pin;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
 
-#[allow(dead_code)]
+pub mod container;
+
+type FileStream = Pin<Box<dyn Stream<Item = object_store::Result<Bytes>> + Send>>;
+
+#[derive(Singleton)]
+#[singleton(sync = false)]
 pub struct FileSystem {
-    #[allow(dead_code)]
     file_store: Box<dyn ObjectStore>,
 }
 
 impl FileSystem {
-    // TODO: Add support for GCS, AWS S3, Azure Blob Storage
+    // TODO: Add support for GCS, Azure Blob Storage
     // https://docs.rs/object_store/latest/object_store/
-    #[allow(dead_code)]
-    pub fn new(file_store: &str) -> Self {
-        let file_store: Box<dyn ObjectStore> = match file_store {
-            "local" => Self::construct_local_fs(),
+    pub fn new(settings: &FilesystemSettings) -> OperationResult<Self> {
+        let file_store: Box<dyn ObjectStore> = match settings.provider.as_ref() {
+            "local" => Self::construct_local_fs()?,
             "memory" => Self::construct_memory_fs(),
-            _ => panic!("Unknown file store"),
+            "s3" => Self::construct_s3_fs(settings.s3.as_ref())?,
+            val => return Err(anyhow::format_err!("Unknown file store: {}", val).into()),
         };
 
-        Self { file_store }
+        Ok(Self { file_store })
     }
 
-    fn construct_local_fs() -> Box<LocalFileSystem> {
-        let prefix = std::env::current_dir()
-            .map_err(|e| panic!("Failed to get current directory: {}", e))
-            .unwrap();
-        let fs = LocalFileSystem::new_with_prefix(prefix.join("data").as_path())
-            .map_err(|e| panic!("Failed to get current directory: {}", e))
-            .unwrap();
-        Box::new(fs)
+    pub async fn write(&self, location: &str, kek_id: &str) -> OperationResult<FileWriter<'_>> {
+        // Chunks are authenticated together with the header, which the untagged cipher cannot do
+        let algorithm = match Settings::get().kms.algorithm {
+            Algorithm::ChaCha20Poly1305 => Algorithm::XChaCha20Poly1305,
+            algorithm => algorithm,
+        };
+
+        let dek: Dek;
+        {
+            let kms = KeyManagementSystem::lock().await;
+            dek = kms.generate_dek(kek_id, algorithm).await?;
+        }
+
+        let header = Header::new(dek.to_bytes()?, algorithm);
+        let encoder = Encoder::new(&header, &dek)?;
+
+        let location = Path::from(location);
+        let (multipart_id, mut writer) = self.file_store.put_multipart(&location).await?;
+        writer.write_all(&header.to_bytes()).await?;
+
+        Ok(FileWriter {
+            file_store: self.file_store.as_ref(),
+            location,
+            multipart_id,
+            writer,
+            encoder,
+            size: 0,
+        })
+    }
+
+    pub async fn read(&self, location: &str) -> OperationResult<FileReader> {
+        let mut stream = self
+            .file_store
+            .get(&Path::from(location))
+            .await?
+            .into_stream();
+        let (header, remainder) = Self::read_header(&mut stream).await?;
+
+        let mut dek = Dek::from_bytes(&header.dek)?;
+        {
+            let kms = KeyManagementSystem::lock().await;
+            kms.decrypt_dek(&mut dek).await?;
+        }
+
+        let mut decoder = Decoder::new(&header, &dek)?;
+        let pending = decoder.update(&remainder)?;
+
+        Ok(FileReader {
+            stream,
+            decoder,
+            pending,
+            is_finished: false,
+        })
+    }
+
+    /// Deleting a file that doesn't exist succeeds, as some stores report it and others don't.
+    pub async fn delete(&self, location: &str) -> EmptyResult {
+        match self.file_store.delete(&Path::from(location)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Drops the parts of an upload that was never finished, e.g. because the server stopped
+    /// while receiving it.
+    pub async fn abort(&self, location: &str, multipart_id: &str) -> EmptyResult {
+        self.file_store
+            .abort_multipart(&Path::from(location), &multipart_id.to_string())
+            .await?;
+        Ok(())
+    }
+
+    /// Moves the DEK in the file header, if it predates per-user keys, under the key encryption
+    /// key `kek_id`. Returns `false` if it already is wrapped by a key encryption key, or if there
+    /// is no such file.
+    pub async fn rewrap(&self, location: &str, kek_id: &str) -> OperationResult<bool> {
+        let location = Path::from(location);
+        // A missing file would otherwise stop every run of the re-wrap job at the same place
+        let mut stream = match self.file_store.get(&location).await {
+            Ok(r) => r.into_stream(),
+            Err(object_store::Error::NotFound { .. }) => {
+                tracing::warn!("Skipped re-wrapping {}, which doesn't exist", location);
+                return Ok(false);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (header, remainder) = Self::read_header(&mut stream).await?;
+
+        let mut dek = Dek::from_bytes(&header.dek)?;
+        {
+            let kms = KeyManagementSystem::lock().await;
+            if !kms.rewrap_dek(&mut dek, kek_id).await? {
+                return Ok(false);
+            }
+        }
+
+        let temporary = Path::from(format!("{}.rewrap", location));
+        let (multipart_id, mut writer) = self.file_store.put_multipart(&temporary).await?;
+
+        let result = Self::copy_rewrapped(&header, dek, remainder, stream, &mut writer).await;
+        if let Err(e) = result {
+            self.file_store
+                .abort_multipart(&temporary, &multipart_id)
+                .await?;
+            return Err(e);
+        }
+
+        self.file_store.rename(&temporary, &location).await?;
+
+        Ok(true)
+    }
+
+    async fn read_header(stream: &mut FileStream) -> OperationResult<(Header, Vec<u8>)> {
+        let mut buffer: Vec<u8> = Vec::new();
+        loop {
+            if let Some((header, header_size)) = Header::parse(&buffer)? {
+                return Ok((header, buffer.split_off(header_size)));
+            }
+
+            match stream.next().await {
+                Some(bytes) => buffer.extend_from_slice(&bytes?),
+                None => return Err(anyhow::Error::msg("invalid_file__truncated").into()),
+            }
+        }
+    }
+
+    async fn copy_rewrapped(
+        header: &Header,
+        dek: Dek<'_>,
+        remainder: Vec<u8>,
+        mut stream: FileStream,
+        writer: &mut Box<dyn AsyncWrite + Unpin + Send>,
+    ) -> EmptyResult {
+        let mut rewrapped_header = Header::new(dek.to_bytes()?, header.algorithm);
+        rewrapped_header.chunk_size = header.chunk_size;
+        writer.write_all(&rewrapped_header.to_bytes()).await?;
+
+        writer.write_all(&remainder).await?;
+        while let Some(bytes) = stream.next().await {
+            writer.write_all(&bytes?).await?;
+        }
+
+        writer.shutdown().await?;
+
+        Ok(())
+    }
+
+    fn construct_local_fs() -> OperationResult<Box<LocalFileSystem>> {
+        let prefix = std::env::current_dir()?;
+        let fs = LocalFileSystem::new_with_prefix(prefix.join("data").as_path())?;
+        Ok(Box::new(fs))
     }
 
     fn construct_memory_fs() -> Box<InMemory> {
         Box::new(InMemory::new())
     }
+
+    fn construct_s3_fs(settings: Option<&S3Settings>) -> OperationResult<Box<AmazonS3>> {
+        let settings = match settings {
+            Some(s) => s,
+            None => {
+                return Err(
+                    anyhow::Error::msg("Missing [fs.s3] settings for the s3 file store").into(),
+                )
+            }
+        };
+
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(settings.bucket.as_ref())
+            .with_region(settings.region.as_ref())
+            .with_virtual_hosted_style_request(!settings.path_style);
+        if let Some(endpoint) = settings.endpoint.as_ref() {
+            builder = builder
+                .with_endpoint(endpoint.as_ref())
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+        if let Some(access_key_id) = settings.access_key_id.as_ref() {
+            builder = builder.with_access_key_id(access_key_id.as_ref());
+        }
+        if let Some(secret_access_key) = settings.secret_access_key.as_ref() {
+            builder = builder.with_secret_access_key(secret_access_key.as_ref());
+        }
+
+        let fs = builder.build()?;
+        Ok(Box::new(fs))
+    }
+}
+
+impl SingletonInit<FileSystem> for FileSystem {
+    fn init() -> FileSystem {
+        match FileSystem::new(&Settings::get().fs) {
+            Ok(f) => f,
+            Err(e) => panic!("Failed to set up the file store: {:?}", e),
+        }
+    }
+}
+
+pub struct FileWriter<'a> {
+    file_store: &'a dyn ObjectStore,
+    location: Path,
+    multipart_id: MultipartId,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+    encoder: Encoder,
+    size: u64,
+}
+
+impl<'a> FileWriter<'a> {
+    pub fn multipart_id(&self) -> &str {
+        &self.multipart_id
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> EmptyResult {
+        let sealed = self.encoder.update(data)?;
+        self.writer.write_all(&sealed).await?;
+        self.size += data.len() as u64;
+
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> OperationResult<u64> {
+        let sealed = self.encoder.finalize()?;
+        self.writer.write_all(&sealed).await?;
+        self.writer.shutdown().await?;
+
+        Ok(self.size)
+    }
+
+    pub async fn abort(self) -> EmptyResult {
+        self.file_store
+            .abort_multipart(&self.location, &self.multipart_id)
+            .await?;
+
+        Ok(())
+    }
+}
+
+pub struct FileReader {
+    stream: FileStream,
+    decoder: Decoder,
+    pending: Vec<u8>,
+    is_finished: bool,
+}
+
+impl FileReader {
+    pub async fn next(&mut self) -> OperationResult<Option<Vec<u8>>> {
+        if !self.pending.is_empty() {
+            return Ok(Some(std::mem::take(&mut self.pending)));
+        }
+
+        while !self.is_finished {
+            let plaintext = match self.stream.next().await {
+                Some(bytes) => self.decoder.update(&bytes?)?,
+                None => {
+                    self.is_finished = true;
+                    self.decoder.finalize()?
+                }
+            };
+
+            if !plaintext.is_empty() {
+                return Ok(Some(plaintext));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The MinIO service in docker-compose.yml, with the bucket `minio-setup` creates.
+    fn minio_settings() -> S3Settings {
+        S3Settings {
+            endpoint: Some(
+                std::env::var("PANDORICA_TEST_S3_ENDPOINT")
+                    .unwrap_or_else(|_| "http://127.0.0.1:9000".into())
+                    .into(),
+            ),
+            bucket: "pandorica".into(),
+            region: "us-east-1".into(),
+            access_key_id: Some("minioadmin".into()),
+            secret_access_key: Some("minioadmin".into()),
+            path_style: true,
+        }
+    }
+
+    async fn read(file_store: &dyn ObjectStore, location: &Path) -> object_store::Result<Bytes> {
+        file_store.get(location).await?.bytes().await
+    }
+
+    fn settings(provider: &'static str, s3: Option<S3Settings>) -> FilesystemSettings {
+        FilesystemSettings {
+            provider: provider.into(),
+            s3,
+            max_upload_size_mib: 1,
+            stale_upload_hours: 1,
+        }
+    }
+
+    #[test]
+    fn s3_without_settings_is_an_error() {
+        assert!(FileSystem::new(&settings("s3", None)).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the MinIO service from docker-compose.yml"]
+    async fn s3_writes_renames_and_aborts() {
+        let file_system = FileSystem::new(&settings("s3", Some(minio_settings()))).unwrap();
+        let file_store = file_system.file_store.as_ref();
+
+        let prefix = format!("test-{}", rand::random::<u64>());
+        let location = Path::from(format!("{}/file", prefix));
+        let temporary = Path::from(format!("{}/file.rewrap", prefix));
+
+        let (_, mut writer) = file_store.put_multipart(&temporary).await.unwrap();
+        writer.write_all(b"stored in parts").await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(
+            read(file_store, &temporary).await.unwrap().as_ref(),
+            b"stored in parts"
+        );
+
+        file_store.rename(&temporary, &location).await.unwrap();
+        assert_eq!(
+            read(file_store, &location).await.unwrap().as_ref(),
+            b"stored in parts"
+        );
+        assert!(matches!(
+            read(file_store, &temporary).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+
+        let aborted = Path::from(format!("{}/aborted", prefix));
+        let (multipart_id, mut writer) = file_store.put_multipart(&aborted).await.unwrap();
+        writer.write_all(b"never stored").await.unwrap();
+        file_store
+            .abort_multipart(&aborted, &multipart_id)
+            .await
+            .unwrap();
+        assert!(matches!(
+            read(file_store, &aborted).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+
+        file_store.delete(&location).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the MinIO service from docker-compose.yml"]
+    async fn s3_rewrap_skips_missing_files() {
+        let file_system = FileSystem::new(&settings("s3", Some(minio_settings()))).unwrap();
+
+        let location = format!("test-{}/missing", rand::random::<u64>());
+        assert!(!file_system
+            .rewrap(&location, "key_encryption_key:unused")
+            .await
+            .unwrap());
+    }
 }
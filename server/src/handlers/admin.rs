@@ -0,0 +1,184 @@
+use async_trait::async_trait;
+use protobuf::pandorica_admin::{
+    admin_service_server, ForcePasswordResetRequest, ForcePasswordResetResponse,
+    GetMasterKeyStatusRequest, GetMasterKeyStatusResponse, ListUsersRequest, ListUsersResponse,
+    RevokeUserSessionsRequest, RevokeUserSessionsResponse, SetUserActiveRequest,
+    SetUserActiveResponse, SetUserRoleRequest, SetUserRoleResponse,
+};
+use tonic::{Request, Response, Status};
+
+use crate::helpers::auth_guard::rpc_name;
+use crate::helpers::authorization::{Access, AuthContext};
+use crate::models::auth::{Role, User};
+use crate::repos;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(Default)]
+pub struct AdminService {}
+
+impl AdminService {
+    /// The least role each RPC needs. Anything not listed is for admins only.
+    pub fn access(path: &str) -> Access {
+        match rpc_name(path) {
+            "ListUsers" | "GetMasterKeyStatus" => Access::Role(Role::Auditor),
+            _ => Access::Role(Role::Admin),
+        }
+    }
+
+    async fn read_user<'a>(id: &str) -> Result<User<'a>, Status> {
+        let user = repos::user::read(id.split(':').last().unwrap()).await?;
+        user.ok_or_else(|| Status::not_found("user_not_found"))
+    }
+
+    /// Keeps admins from locking themselves out, which could leave no admin at all.
+    fn check_not_self(admin: &User, user: &User) -> Result<(), Status> {
+        if admin.get_id().full_identifier() == user.get_id().full_identifier() {
+            return Err(Status::failed_precondition("admin_self_modification"));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl admin_service_server::AdminService for AdminService {
+    async fn list_users(
+        &self,
+        request: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        let request = request.into_inner();
+
+        let limit = match request.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            l => l.min(MAX_PAGE_SIZE),
+        };
+        let query = request.query.as_deref().filter(|q| !q.is_empty());
+
+        let users = repos::user::search(query, request.cursor.as_deref(), limit).await?;
+        let next_cursor = match users.last() {
+            Some(u) if users.len() == limit as usize => {
+                Some(u.get_id().partial_identifier().to_string())
+            }
+            _ => None,
+        };
+
+        // Emails are encrypted under each user's own key and stay that way here
+        let users = users
+            .into_iter()
+            .map(|mut u| {
+                u.email = None;
+                u.into()
+            })
+            .collect();
+
+        Ok(Response::new(ListUsersResponse { users, next_cursor }))
+    }
+
+    async fn set_user_active(
+        &self,
+        request: Request<SetUserActiveRequest>,
+    ) -> Result<Response<SetUserActiveResponse>, Status> {
+        let admin = AuthContext::get(&request)?.user;
+        let request = request.into_inner();
+
+        let mut user = Self::read_user(&request.id).await?;
+        Self::check_not_self(&admin, &user)?;
+
+        user.is_active = request.is_active;
+        repos::user::update_is_active(&user).await?;
+        if !user.is_active {
+            repos::refresh_token::revoke_all_by_user_id(user.get_id().full_identifier()).await?;
+        }
+
+        tracing::info!(
+            "{} set user {} active: {}",
+            admin.username,
+            user.get_id().full_identifier(),
+            user.is_active
+        );
+
+        Ok(Response::new(SetUserActiveResponse {}))
+    }
+
+    async fn set_user_role(
+        &self,
+        request: Request<SetUserRoleRequest>,
+    ) -> Result<Response<SetUserRoleResponse>, Status> {
+        let admin = AuthContext::get(&request)?.user;
+        let request = request.into_inner();
+
+        let role = Role::parse(request.role.as_str());
+        if role.is_none() {
+            return Err(Status::invalid_argument("invalid_role"));
+        }
+
+        let mut user = Self::read_user(&request.id).await?;
+        Self::check_not_self(&admin, &user)?;
+
+        user.role = role.unwrap();
+        repos::user::update_role(&user).await?;
+
+        tracing::info!(
+            "{} set the role of user {} to {}",
+            admin.username,
+            user.get_id().full_identifier(),
+            user.role.as_str()
+        );
+
+        Ok(Response::new(SetUserRoleResponse {}))
+    }
+
+    async fn force_password_reset(
+        &self,
+        request: Request<ForcePasswordResetRequest>,
+    ) -> Result<Response<ForcePasswordResetResponse>, Status> {
+        let admin = AuthContext::get(&request)?.user;
+        let request = request.into_inner();
+
+        let mut user = Self::read_user(&request.id).await?;
+
+        user.is_password_reset_required = true;
+        repos::user::update_is_password_reset_required(&user).await?;
+        repos::refresh_token::revoke_all_by_user_id(user.get_id().full_identifier()).await?;
+
+        tracing::info!(
+            "{} forced a password reset of user {}",
+            admin.username,
+            user.get_id().full_identifier()
+        );
+
+        Ok(Response::new(ForcePasswordResetResponse {}))
+    }
+
+    async fn revoke_user_sessions(
+        &self,
+        request: Request<RevokeUserSessionsRequest>,
+    ) -> Result<Response<RevokeUserSessionsResponse>, Status> {
+        let admin = AuthContext::get(&request)?.user;
+        let request = request.into_inner();
+
+        let user = Self::read_user(&request.id).await?;
+        repos::refresh_token::revoke_all_by_user_id(user.get_id().full_identifier()).await?;
+
+        tracing::info!(
+            "{} revoked the sessions of user {}",
+            admin.username,
+            user.get_id().full_identifier()
+        );
+
+        Ok(Response::new(RevokeUserSessionsResponse {}))
+    }
+
+    async fn get_master_key_status(
+        &self,
+        _request: Request<GetMasterKeyStatusRequest>,
+    ) -> Result<Response<GetMasterKeyStatusResponse>, Status> {
+        let master_keys = repos::mk::read_all().await?;
+
+        Ok(Response::new(GetMasterKeyStatusResponse {
+            master_keys: master_keys.into_iter().map(|m| m.into()).collect(),
+        }))
+    }
+}
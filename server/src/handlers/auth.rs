@@ -1,52 +1,390 @@
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use shared::error::EmptyResult;
 use std::borrow::Cow;
 use tonic::{Request, Response, Status};
 
-use crate::helpers::authorization::get_session;
-use crate::models::auth::{Password, Session, User};
+use crate::config::Settings;
+use crate::helpers::auth_guard::rpc_name;
+use crate::helpers::authorization::{
+    check_no_password, check_password, check_plaintext_allowed, verify_password, Access,
+    AuthContext,
+};
+use crate::helpers::client::client_info;
+use crate::helpers::opaque::{self, Opaque};
+use crate::helpers::{email, keys, recovery, throttle, totp};
+use crate::models::auth::{
+    AccountRecovery, ClientInfo, EmailToken, EmailTokenPurpose, OpaqueLogin, Password,
+    RefreshToken, Session, Token, User,
+};
+use crate::models::crypto::{EncryptedValue, Keyring, RecoveryKey};
 use crate::{repos, validators};
 use protobuf::pandorica_auth::{
-    auth_service_server, AuthResponse, LoginRequest, RegistrationRequest,
+    auth_service_server, AuthResponse, ChangePasswordRequest, ChangePasswordResponse,
+    ConfirmTotpRequest, ConfirmTotpResponse, DisableTotpRequest, DisableTotpResponse,
+    EnrollTotpRequest, EnrollTotpResponse, LoginRequest, OpaqueLoginFinishRequest,
+    OpaqueLoginStartRequest, OpaqueLoginStartResponse, OpaquePasswordStartRequest,
+    OpaqueReauthenticateFinishResponse, OpaqueReauthenticateStartRequest, OpaqueRegisterRequest,
+    OpaqueRegistrationStartRequest, OpaqueRegistrationStartResponse, OpaqueSetPasswordRequest,
+    RecoverAccountRequest, RecoverAccountResponse, RecoverAccountStartRequest,
+    RecoverAccountStartResponse, RefreshRequest, RegistrationRequest, RequestPasswordResetRequest,
+    RequestPasswordResetResponse, ResetPasswordRequest, ResetPasswordResponse,
+    ResetPasswordStartRequest, ResetPasswordStartResponse, VerifyEmailRequest, VerifyEmailResponse,
+    VerifyMfaRequest,
 };
+use secret_vault_value::SecretValue;
+use singleton::{sync::Singleton as SyncSingleton, unsync::Singleton as UnsyncSingleton};
+
+/// Failed second factor attempts after which a pending login has to start over.
+const MAX_MFA_ATTEMPTS: u32 = 5;
 
 #[derive(Default)]
 pub struct AuthService {}
 
+impl AuthService {
+    pub fn access(path: &str) -> Access {
+        match rpc_name(path) {
+            "Register"
+            | "Login"
+            | "Refresh"
+            | "OpaqueRegistrationStart"
+            | "OpaqueRegister"
+            | "OpaqueLoginStart"
+            | "OpaqueLoginFinish"
+            | "RecoverAccountStart"
+            | "RecoverAccount"
+            | "VerifyEmail"
+            | "RequestPasswordReset"
+            | "ResetPasswordStart"
+            | "ResetPassword" => Access::Public,
+            "VerifyMfa" => Access::MfaPending,
+            "ChangePassword"
+            | "OpaquePasswordStart"
+            | "OpaqueSetPassword"
+            | "OpaqueReauthenticateStart"
+            | "OpaqueReauthenticateFinish" => Access::PasswordChange,
+            _ => Access::Session,
+        }
+    }
+
+    /// Creates an account with its first session, signed in with either a password or an
+    /// OPAQUE registration.
+    async fn create_account(
+        username: String,
+        email: Option<String>,
+        password: Option<Password<'_>>,
+        opaque_registration: Option<Vec<u8>>,
+        keyring: Option<Keyring<'_>>,
+        recovery_key: Option<RecoveryKey<'_>>,
+        client: ClientInfo<'_>,
+    ) -> Result<AuthResponse, Status> {
+        let mut password = match password {
+            Some(p) => Some(repos::password::create(p).await?),
+            None => None,
+        };
+
+        let family_id = Token::generate_family_id();
+        let absolute_expires_on = Self::absolute_expires_on();
+
+        let session = Session::new(
+            String::default(),
+            family_id.clone(),
+            absolute_expires_on,
+            client,
+        )?;
+        let token = session.token.clone().unwrap();
+        let mut session = repos::session::create(session).await?;
+
+        let kek_id = keys::create_kek().await?;
+
+        let mut user = User::new(
+            username,
+            email,
+            kek_id,
+            password
+                .as_ref()
+                .map(|p| p.get_id().full_identifier().to_string()),
+            session.get_id().full_identifier().to_string(),
+        )
+        .await?;
+        user.opaque_registration = opaque_registration.map(Cow::Owned);
+        user.keyring = keyring;
+        user.recovery_key = recovery_key;
+        let mut user = repos::user::create(user).await?;
+
+        if let Some(password) = password.as_mut() {
+            password.user_id = user.get_id().full_identifier().to_string().into();
+            repos::password::update(password).await?;
+        }
+
+        session.user_id = user.get_id().full_identifier().to_string().into();
+        repos::session::update(&session).await?;
+
+        let refresh_token = Self::issue_refresh_token(
+            user.get_id().full_identifier().to_string(),
+            family_id,
+            absolute_expires_on,
+        )
+        .await?;
+
+        // The account works without a verified address, so a mail that can't be sent is only
+        // logged
+        if user.email.is_some() {
+            if let Err(e) = email::send_verification(&mut user).await {
+                tracing::warn!(
+                    "Failed to send the verification mail to {}: {:?}",
+                    user.get_id().full_identifier(),
+                    e
+                );
+            }
+
+            user.email.as_mut().unwrap().decrypt().await?;
+        }
+
+        Ok(AuthResponse {
+            user: Some(user.into()),
+            session: Some(session.into()),
+            token: token.as_sensitive_str().into(),
+            refresh_token: refresh_token.as_sensitive_str().into(),
+            is_mfa_pending: false,
+        })
+    }
+
+    /// Logs in a user whose password was accepted, however it was checked. With TOTP enabled,
+    /// that only gets them a session waiting for the second factor.
+    async fn complete_login(
+        mut user: User<'_>,
+        client: ClientInfo<'_>,
+    ) -> Result<AuthResponse, Status> {
+        if user.is_totp_enabled {
+            let absolute_expires_on =
+                Utc::now() + Duration::minutes(Settings::get().totp.pending_timeout_minutes);
+
+            let mut session = Session::new(
+                user.get_id().full_identifier().to_string(),
+                Token::generate_family_id(),
+                absolute_expires_on,
+                client,
+            )?;
+            session.is_mfa_pending = true;
+            let token = session.token.clone().unwrap();
+            let session = repos::session::create(session).await?;
+
+            return Ok(AuthResponse {
+                user: None,
+                session: Some(session.into()),
+                token: token.as_sensitive_str().into(),
+                refresh_token: String::new(),
+                is_mfa_pending: true,
+            });
+        }
+
+        throttle::reset(&throttle::username_key(&user.username)).await?;
+
+        if user.email.is_some() {
+            user.email.as_mut().unwrap().decrypt().await?;
+        }
+
+        let family_id = Token::generate_family_id();
+        let absolute_expires_on = Self::absolute_expires_on();
+
+        let session = Session::new(
+            user.get_id().full_identifier().to_string(),
+            family_id.clone(),
+            absolute_expires_on,
+            client,
+        )?;
+        let token = session.token.clone().unwrap();
+        let session = repos::session::create(session).await?;
+        let refresh_token = Self::issue_refresh_token(
+            user.get_id().full_identifier().to_string(),
+            family_id,
+            absolute_expires_on,
+        )
+        .await?;
+
+        user.last_seen_on = Utc::now();
+        repos::user::add_session(&user, &session).await?;
+
+        Ok(AuthResponse {
+            user: Some(user.into()),
+            session: Some(session.into()),
+            token: token.as_sensitive_str().into(),
+            refresh_token: refresh_token.as_sensitive_str().into(),
+            is_mfa_pending: false,
+        })
+    }
+
+    fn absolute_expires_on() -> DateTime<Utc> {
+        Utc::now() + Duration::hours(Settings::get().session.absolute_timeout_hours)
+    }
+
+    /// The user a password reset token was sent to, as long as they can still use it.
+    async fn password_reset_user<'a>(email_token: &EmailToken<'_>) -> Result<User<'a>, Status> {
+        let user = repos::user::read(email_token.user_id.split(':').last().unwrap()).await?;
+
+        match user {
+            Some(u) if u.is_active && u.keyring.is_none() => Ok(u),
+            // The files encrypted end to end since the token was sent would be lost with the
+            // password
+            Some(u) if u.is_active => Err(Status::failed_precondition("recovery_key_required")),
+            _ => Err(Status::permission_denied("invalid_email_token")),
+        }
+    }
+
+    async fn issue_refresh_token(
+        user_id: String,
+        family_id: String,
+        expires_on: DateTime<Utc>,
+    ) -> Result<SecretValue, Status> {
+        let refresh_token = RefreshToken::new(user_id, family_id, expires_on)?;
+        let token = refresh_token.token.clone().unwrap();
+        repos::refresh_token::create(refresh_token).await?;
+
+        Ok(token)
+    }
+}
+
 #[async_trait]
 impl auth_service_server::AuthService for AuthService {
     async fn register(
         &self,
         request: Request<RegistrationRequest>,
     ) -> Result<Response<AuthResponse>, Status> {
+        check_plaintext_allowed()?;
+
+        let client = client_info(&request);
         let request = request.into_inner();
 
         EmptyResult::from(validators::username_format(request.username.as_str()))?;
         EmptyResult::from(validators::username_duplicate(request.username.as_str()).await)?;
         EmptyResult::from(validators::email(request.email.as_ref()))?;
-        EmptyResult::from(validators::password(request.password.as_str()))?;
+        let mut user_inputs = vec![request.username.as_str()];
+        if let Some(email) = request.email.as_ref() {
+            user_inputs.push(email.as_str());
+        }
+        EmptyResult::from(validators::password(
+            request.password.as_str(),
+            &user_inputs,
+        ))?;
+        EmptyResult::from(validators::breached_password(request.password.as_str()).await?)?;
 
         let password = Password::new(request.password.into(), String::default())?;
-        let mut password = repos::password::create(password).await?;
 
-        let session = Session::new(String::default());
-        let mut session = repos::session::create(session).await?;
+        Ok(Response::new(
+            Self::create_account(
+                request.username,
+                request.email,
+                Some(password),
+                None,
+                None,
+                None,
+                client,
+            )
+            .await?,
+        ))
+    }
 
-        let user = User::new(
-            request.username,
-            request.email,
-            password.get_id().full_identifier().to_string(),
-            session.get_id().full_identifier().to_string(),
+    async fn login(
+        &self,
+        request: Request<LoginRequest>,
+    ) -> Result<Response<AuthResponse>, Status> {
+        check_plaintext_allowed()?;
+
+        let address = request.remote_addr().map(|a| a.ip());
+        let client = client_info(&request);
+        let request = request.into_inner();
+
+        EmptyResult::from(validators::username_format(request.username.as_str()))?;
+
+        // Counted as a failure until the password is verified, so that concurrent guesses can't
+        // all get in before the first one is judged
+        let throttle_keys = throttle::keys(request.username.as_str(), address);
+        throttle::attempt(&throttle_keys).await?;
+
+        // Every way a login can fail looks the same from the outside, so that it doesn't reveal
+        // which usernames exist
+        let user = repos::user::read_by_username(request.username.as_str()).await?;
+        // The policy only applies to new passwords, so tightening it doesn't lock anyone out
+        let is_too_long =
+            request.password.chars().count() > Settings::get().security.password.max_length;
+        let is_verified = match user.as_ref() {
+            Some(u) if !is_too_long => {
+                check_password(u, request.password).await?.is_some() && u.is_active
+            }
+            Some(_) => false,
+            None => {
+                if !is_too_long {
+                    check_no_password(request.password)?;
+                }
+                false
+            }
+        };
+        if !is_verified {
+            return Err(Status::unauthenticated("invalid_credentials"));
+        }
+        let user = user.unwrap();
+
+        throttle::forgive(&throttle_keys).await?;
+
+        Ok(Response::new(Self::complete_login(user, client).await?))
+    }
+
+    async fn verify_mfa(
+        &self,
+        request: Request<VerifyMfaRequest>,
+    ) -> Result<Response<AuthResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let mut pending_session = context.session()?.clone();
+        let mut user = context.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let client = client_info(&request);
+        let request = request.into_inner();
+
+        // The password alone resets nothing, so guessing codes over many logins still runs into
+        // the lockout
+        let throttle_keys = throttle::keys(&user.username, address);
+        throttle::attempt(&throttle_keys).await?;
+
+        // Counted before the code is checked, so that concurrent guesses can't go past the limit
+        pending_session.mfa_attempts =
+            repos::session::record_mfa_attempt(&pending_session, MAX_MFA_ATTEMPTS).await?;
+        if pending_session.mfa_attempts > MAX_MFA_ATTEMPTS {
+            return Err(Status::permission_denied("invalid_mfa_code"));
+        }
+
+        if !totp::verify_user_code(&mut user, request.code.as_str()).await? {
+            return Err(Status::permission_denied("invalid_mfa_code"));
+        }
+
+        throttle::forgive(&throttle_keys).await?;
+        throttle::reset(&throttle::username_key(&user.username)).await?;
+
+        // The pending session is replaced rather than upgraded, so that its token, which only
+        // ever proved the password, never grants more than that
+        pending_session.expires_on = Utc::now();
+        repos::session::update(&pending_session).await?;
+
+        let family_id = Token::generate_family_id();
+        let absolute_expires_on = Self::absolute_expires_on();
+
+        let session = Session::new(
+            user.get_id().full_identifier().to_string(),
+            family_id.clone(),
+            absolute_expires_on,
+            client,
+        )?;
+        let token = session.token.clone().unwrap();
+        let session = repos::session::create(session).await?;
+        let refresh_token = Self::issue_refresh_token(
+            user.get_id().full_identifier().to_string(),
+            family_id,
+            absolute_expires_on,
         )
         .await?;
-        let mut user = repos::user::create(user).await?;
 
-        password.user_id = user.get_id().full_identifier().to_string().into();
-        repos::password::update(&password).await?;
-
-        session.user_id = user.get_id().full_identifier().to_string().into();
-        repos::session::update(&session).await?;
+        user.last_seen_on = Utc::now();
+        repos::user::add_session(&user, &session).await?;
 
         if user.email.is_some() {
             user.email.as_mut().unwrap().decrypt().await?;
@@ -55,49 +393,88 @@ impl auth_service_server::AuthService for AuthService {
         Ok(Response::new(AuthResponse {
             user: Some(user.into()),
             session: Some(session.into()),
+            token: token.as_sensitive_str().into(),
+            refresh_token: refresh_token.as_sensitive_str().into(),
+            is_mfa_pending: false,
         }))
     }
 
-    async fn login(
+    async fn refresh(
         &self,
-        request: Request<LoginRequest>,
+        request: Request<RefreshRequest>,
     ) -> Result<Response<AuthResponse>, Status> {
+        let client = client_info(&request);
         let request = request.into_inner();
 
-        EmptyResult::from(validators::username_format(request.username.as_str()))?;
-        EmptyResult::from(validators::password(request.password.as_str()))?;
+        let token = Token::parse(request.refresh_token.as_str());
+        if token.is_none() {
+            return Err(Status::unauthenticated("invalid_refresh_token"));
+        }
+        let (selector, verifier) = token.unwrap();
 
-        let user = repos::user::read_by_username(request.username.as_str()).await?;
-        if user.is_none() {
-            return Err(Status::not_found("user_not_found"));
+        let refresh_token = repos::refresh_token::read_by_token_selector(selector).await?;
+        if refresh_token.is_none() {
+            return Err(Status::unauthenticated("invalid_refresh_token"));
         }
-        let mut user = user.unwrap();
+        let refresh_token = refresh_token.unwrap();
 
-        let request_password = Password::new(request.password.into(), String::default())?;
-        let password =
-            repos::password::read_active_by_user_id(user.get_id().full_identifier()).await?;
-        if password.is_none() {
-            return Err(Status::not_found("password_not_found"));
+        if !Token::verify(&verifier, &refresh_token.token_hash)? {
+            return Err(Status::unauthenticated("invalid_refresh_token"));
         }
-        let password = password.unwrap();
 
-        if !request_password.verify(&password)? {
-            return Err(Status::permission_denied("invalid_password"));
+        // A refresh token is only ever handed to one client, so seeing it twice means it leaked
+        // and nothing descending from the same login can be trusted anymore.
+        if refresh_token.used_on.is_some() {
+            repos::refresh_token::revoke_family(&refresh_token.family_id).await?;
+            return Err(Status::unauthenticated("refresh_token_reused"));
         }
 
-        if user.email.is_some() {
-            user.email.as_mut().unwrap().decrypt().await?;
+        if !refresh_token.verify() {
+            return Err(Status::unauthenticated("refresh_token_expired"));
         }
 
-        let session = Session::new(user.get_id().full_identifier().to_string());
+        if !repos::refresh_token::mark_used(&refresh_token).await? {
+            repos::refresh_token::revoke_family(&refresh_token.family_id).await?;
+            return Err(Status::unauthenticated("refresh_token_reused"));
+        }
+
+        let user = repos::user::read(refresh_token.user_id.split(':').last().unwrap()).await?;
+        if user.is_none() {
+            return Err(Status::unauthenticated("user_not_found"));
+        }
+        let mut user = user.unwrap();
+        if !user.is_active {
+            return Err(Status::unauthenticated("user_inactive"));
+        }
+
+        let session = Session::new(
+            user.get_id().full_identifier().to_string(),
+            refresh_token.family_id.to_string(),
+            refresh_token.expires_on,
+            client,
+        )?;
+        let token = session.token.clone().unwrap();
         let session = repos::session::create(session).await?;
-        user.sessions.push(Cow::Borrowed(session.get_id()));
+        let next_refresh_token = Self::issue_refresh_token(
+            user.get_id().full_identifier().to_string(),
+            refresh_token.family_id.to_string(),
+            refresh_token.expires_on,
+        )
+        .await?;
+
         user.last_seen_on = Utc::now();
-        repos::user::update(&user).await?;
+        repos::user::add_session(&user, &session).await?;
+
+        if user.email.is_some() {
+            user.email.as_mut().unwrap().decrypt().await?;
+        }
 
         Ok(Response::new(AuthResponse {
             user: Some(user.into()),
             session: Some(session.into()),
+            token: token.as_sensitive_str().into(),
+            refresh_token: next_refresh_token.as_sensitive_str().into(),
+            is_mfa_pending: false,
         }))
     }
 
@@ -105,14 +482,687 @@ impl auth_service_server::AuthService for AuthService {
         &self,
         request: Request<protobuf::pandorica_auth::LogoutRequest>,
     ) -> Result<Response<protobuf::pandorica_auth::LogoutResponse>, Status> {
-        let metadata = request.metadata();
-        let mut session = get_session(metadata).await?;
+        let mut session = AuthContext::get(&request)?.session()?.clone();
 
         if session.verify() {
             session.expires_on = Utc::now();
             repos::session::update(&session).await?;
         }
 
+        // Sessions from before refresh tokens have no family, and revoking the empty one would
+        // hit every other such session
+        if !session.family_id.is_empty() {
+            repos::refresh_token::revoke_family(&session.family_id).await?;
+        }
+
         Ok(Response::new(protobuf::pandorica_auth::LogoutResponse {}))
     }
+
+    async fn enroll_totp(
+        &self,
+        request: Request<EnrollTotpRequest>,
+    ) -> Result<Response<EnrollTotpResponse>, Status> {
+        let mut user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        verify_password(&user, request.password, request.opaque_proof, address).await?;
+
+        if user.is_totp_enabled {
+            return Err(Status::failed_precondition("totp_already_enabled"));
+        }
+
+        let secret = totp::generate_secret();
+        let (recovery_codes, recovery_code_hashes) = totp::generate_recovery_codes().await?;
+        let response = EnrollTotpResponse {
+            secret: totp::encode_secret(&secret),
+            uri: totp::provisioning_uri(&user.username, &secret)?,
+            recovery_codes,
+        };
+
+        let kek_id = keys::user_kek_id(&mut user).await?;
+        user.totp_secret = Some(EncryptedValue::new(secret, &kek_id).await?);
+        user.recovery_codes = recovery_code_hashes.into_iter().map(Cow::Owned).collect();
+        if !repos::user::enroll_totp(&user).await? {
+            return Err(Status::failed_precondition("totp_already_enabled"));
+        }
+
+        Ok(Response::new(response))
+    }
+
+    async fn confirm_totp(
+        &self,
+        request: Request<ConfirmTotpRequest>,
+    ) -> Result<Response<ConfirmTotpResponse>, Status> {
+        let mut user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        if user.is_totp_enabled {
+            return Err(Status::failed_precondition("totp_already_enabled"));
+        }
+        if user.totp_secret.is_none() {
+            return Err(Status::failed_precondition("totp_not_enrolled"));
+        }
+
+        // Only a code from the app proves that it was set up, so recovery codes don't count here
+        let secret = user.totp_secret.as_mut().unwrap();
+        secret.decrypt().await?;
+        let step = totp::verify(
+            secret.value().unwrap(),
+            request.code.as_str(),
+            user.totp_last_step,
+        )?;
+        let is_enabled = match step {
+            Some(s) => repos::user::enable_totp(&user, s).await?,
+            None => false,
+        };
+        if !is_enabled {
+            return Err(Status::permission_denied("invalid_mfa_code"));
+        }
+
+        Ok(Response::new(ConfirmTotpResponse {}))
+    }
+
+    async fn disable_totp(
+        &self,
+        request: Request<DisableTotpRequest>,
+    ) -> Result<Response<DisableTotpResponse>, Status> {
+        let mut user = AuthContext::get(&request)?.user;
+        let request = request.into_inner();
+
+        verify_password(&user, request.password, request.opaque_proof, address).await?;
+
+        if !user.is_totp_enabled {
+            return Err(Status::failed_precondition("totp_not_enabled"));
+        }
+
+        if !totp::verify_user_code(&mut user, request.code.as_str()).await? {
+            return Err(Status::permission_denied("invalid_mfa_code"));
+        }
+
+        repos::user::disable_totp(&user).await?;
+
+        Ok(Response::new(DisableTotpResponse {}))
+    }
+
+    async fn change_password(
+        &self,
+        request: Request<ChangePasswordRequest>,
+    ) -> Result<Response<ChangePasswordResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let session = context.session()?;
+        let user = &context.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        check_plaintext_allowed()?;
+        if user.opaque_registration.is_some() {
+            return Err(Status::failed_precondition("opaque_password"));
+        }
+        // The keyring sealed with the current password can only be sealed again over OPAQUE
+        if user.keyring.is_some() {
+            return Err(Status::failed_precondition("keyring_required"));
+        }
+
+        EmptyResult::from(validators::password(
+            request.new_password.as_str(),
+            &[user.username.as_ref()],
+        ))?;
+        EmptyResult::from(validators::breached_password(request.new_password.as_str()).await?)?;
+
+        // A stolen session is no better for guessing the password than a login
+        let throttle_keys = throttle::keys(&user.username, address);
+        throttle::attempt(&throttle_keys).await?;
+        let mut previous_password = check_password(user, request.current_password)
+            .await?
+            .ok_or_else(|| Status::permission_denied("invalid_password"))?;
+        throttle::forgive(&throttle_keys).await?;
+
+        let password = Password::new(
+            request.new_password.into(),
+            user.get_id().full_identifier().to_string(),
+        )?;
+        let history = repos::password::read_recent_by_user_id(
+            user.get_id().full_identifier(),
+            Settings::get().security.password.history_size,
+        )
+        .await?;
+        for old_password in history.iter() {
+            if password.verify(old_password)? {
+                return Err(Status::invalid_argument("invalid_password__reused"));
+            }
+        }
+
+        // The old password is only deactivated once the new one is stored, so that a failure in
+        // between can't leave the user without one
+        let password = repos::password::create(password).await?;
+        repos::user::add_password(user, password.get_id().full_identifier()).await?;
+        previous_password.is_active = false;
+        repos::password::update(&previous_password).await?;
+
+        // Whoever else knew the old password may still be logged in with it
+        repos::refresh_token::revoke_all_by_user_id_except(session).await?;
+
+        Ok(Response::new(ChangePasswordResponse {}))
+    }
+
+    async fn opaque_registration_start(
+        &self,
+        request: Request<OpaqueRegistrationStartRequest>,
+    ) -> Result<Response<OpaqueRegistrationStartResponse>, Status> {
+        let request = request.into_inner();
+
+        // Only for usernames that are still free. Answering for a taken one would let anyone
+        // evaluate its password file's OPRF without being throttled.
+        EmptyResult::from(validators::username_format(request.username.as_str()))?;
+        EmptyResult::from(validators::username_duplicate(request.username.as_str()).await)?;
+
+        let registration_response = Opaque::lock()
+            .await
+            .start_registration(&request.registration_request, &request.username)?;
+
+        Ok(Response::new(OpaqueRegistrationStartResponse {
+            registration_response,
+            keyring: None,
+        }))
+    }
+
+    async fn opaque_register(
+        &self,
+        request: Request<OpaqueRegisterRequest>,
+    ) -> Result<Response<AuthResponse>, Status> {
+        let client = client_info(&request);
+        let request = request.into_inner();
+
+        EmptyResult::from(validators::username_format(request.username.as_str()))?;
+        EmptyResult::from(validators::username_duplicate(request.username.as_str()).await)?;
+        EmptyResult::from(validators::email(request.email.as_ref()))?;
+
+        // The password never gets here, so checking it against the policy is up to the client
+        let registration = opaque::finish_registration(&request.registration_upload)?;
+
+        let keyring = match request.keyring {
+            Some(k) => {
+                EmptyResult::from(validators::keyring(&k))?;
+                Some(Keyring::from(k))
+            }
+            None => None,
+        };
+        let recovery_key = recovery::parse_recovery_key(request.recovery_key, keyring.is_some())?;
+
+        Ok(Response::new(
+            Self::create_account(
+                request.username,
+                request.email,
+                None,
+                Some(registration),
+                keyring,
+                recovery_key,
+                client,
+            )
+            .await?,
+        ))
+    }
+
+    async fn opaque_login_start(
+        &self,
+        request: Request<OpaqueLoginStartRequest>,
+    ) -> Result<Response<OpaqueLoginStartResponse>, Status> {
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        EmptyResult::from(validators::username_format(request.username.as_str()))?;
+
+        // The client finds out whether its password was right before the server does, and has
+        // no reason to come back if it wasn't, so every login counts as a failure until it is
+        // finished
+        let throttle_keys = throttle::keys(request.username.as_str(), address);
+        throttle::attempt(&throttle_keys).await?;
+
+        // Unknown usernames, inactive users and accounts that are still on Argon2id all get a
+        // response that no password opens
+        let user = repos::user::read_by_username(request.username.as_str()).await?;
+        let registration = user
+            .as_ref()
+            .filter(|u| u.is_active)
+            .and_then(|u| u.opaque_registration.as_deref());
+
+        let (credential_response, state) = Opaque::lock().await.start_login(
+            registration,
+            &request.credential_request,
+            &request.username,
+        )?;
+        let login = OpaqueLogin::new(request.username, None, state).await?;
+        let login = repos::opaque_login::create(login).await?;
+
+        Ok(Response::new(OpaqueLoginStartResponse {
+            login_id: login.get_id().as_string(),
+            credential_response,
+            is_plaintext_allowed: Settings::get().security.password.allow_plaintext,
+        }))
+    }
+
+    async fn opaque_login_finish(
+        &self,
+        request: Request<OpaqueLoginFinishRequest>,
+    ) -> Result<Response<AuthResponse>, Status> {
+        let address = request.remote_addr().map(|a| a.ip());
+        let client = client_info(&request);
+        let request = request.into_inner();
+
+        let login = repos::opaque_login::take(request.login_id.split(':').last().unwrap()).await?;
+        let login = match login {
+            Some(l) if l.user_id.is_none() => l,
+            _ => return Err(Status::unauthenticated("invalid_credentials")),
+        };
+        if !login.verify() {
+            return Err(Status::unauthenticated("opaque_login_expired"));
+        }
+
+        let mut state = login.state;
+        state.decrypt().await?;
+        if !opaque::finish_login(state.value().unwrap(), &request.credential_finalization)? {
+            return Err(Status::unauthenticated("invalid_credentials"));
+        }
+
+        // The user may have been deactivated since the login started
+        let user = repos::user::read_by_username(&login.username).await?;
+        let user = match user {
+            Some(u) if u.is_active && u.opaque_registration.is_some() => u,
+            _ => return Err(Status::unauthenticated("invalid_credentials")),
+        };
+
+        throttle::forgive(&throttle::keys(&user.username, address)).await?;
+
+        Ok(Response::new(Self::complete_login(user, client).await?))
+    }
+
+    async fn opaque_password_start(
+        &self,
+        request: Request<OpaquePasswordStartRequest>,
+    ) -> Result<Response<OpaqueRegistrationStartResponse>, Status> {
+        let user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        // Like a login, this evaluates the OPRF for the user, so it counts as a failure until
+        // the new password is set
+        throttle::attempt(&throttle::keys(&user.username, address)).await?;
+
+        let registration_response = Opaque::lock()
+            .await
+            .start_registration(&request.registration_request, &user.username)?;
+
+        // The client has to seal the keyring again with the new password before it can set it
+        Ok(Response::new(OpaqueRegistrationStartResponse {
+            registration_response,
+            keyring: user.keyring.map(|k| k.into()),
+        }))
+    }
+
+    async fn opaque_set_password(
+        &self,
+        request: Request<OpaqueSetPasswordRequest>,
+    ) -> Result<Response<ChangePasswordResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let session = context.session()?;
+        let mut user = context.user.clone();
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        let registration = opaque::finish_registration(&request.registration_upload)?;
+
+        verify_password(
+            &user,
+            request.current_password,
+            request.opaque_proof,
+            address,
+        )
+        .await?;
+
+        // The private keys are sealed with the password, so they have to be sealed again with the
+        // new one, or the files wrapped to them are lost
+        match (user.keyring.as_ref(), request.keyring) {
+            (None, None) => {}
+            (Some(_), None) => return Err(Status::failed_precondition("keyring_required")),
+            (None, Some(_)) => return Err(Status::failed_precondition("keyring_not_found")),
+            (Some(current), Some(keyring)) => {
+                EmptyResult::from(validators::keyring(&keyring))?;
+                let keyring = Keyring::from(keyring);
+                if !current.has_same_keys(&keyring) {
+                    return Err(Status::failed_precondition("keyring_mismatch"));
+                }
+
+                user.keyring = Some(keyring);
+            }
+        }
+
+        // An account moving over from Argon2id keeps its password, so that doesn't count as the
+        // change an admin may have asked for
+        let is_password_change = user.opaque_registration.is_some();
+        user.opaque_registration = Some(Cow::Owned(registration));
+        repos::user::update_opaque_registration(&user, is_password_change).await?;
+
+        // The old hash can't be used to log in anymore
+        let password =
+            repos::password::read_active_by_user_id(user.get_id().full_identifier()).await?;
+        if let Some(mut password) = password {
+            password.is_active = false;
+            repos::password::update(&password).await?;
+        }
+
+        throttle::forgive(&throttle::keys(&user.username, address)).await?;
+
+        // The server can't tell whether the password changed, so this is treated like a change
+        repos::refresh_token::revoke_all_by_user_id_except(session).await?;
+
+        Ok(Response::new(ChangePasswordResponse {}))
+    }
+
+    async fn opaque_reauthenticate_start(
+        &self,
+        request: Request<OpaqueReauthenticateStartRequest>,
+    ) -> Result<Response<OpaqueLoginStartResponse>, Status> {
+        let user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        // Unlike a login, this may tell its caller that they are still on Argon2id, since it is
+        // their own account
+        let registration = match user.opaque_registration.as_deref() {
+            Some(r) => r,
+            None => return Err(Status::failed_precondition("opaque_not_registered")),
+        };
+
+        // A stolen session is no better for guessing the password than a login
+        let throttle_keys = throttle::keys(&user.username, address);
+        throttle::attempt(&throttle_keys).await?;
+
+        let (credential_response, state) = Opaque::lock().await.start_login(
+            Some(registration),
+            &request.credential_request,
+            &user.username,
+        )?;
+        let login = OpaqueLogin::new(
+            user.username.to_string(),
+            Some(user.get_id().full_identifier().to_string()),
+            state,
+        )
+        .await?;
+        let login = repos::opaque_login::create(login).await?;
+
+        Ok(Response::new(OpaqueLoginStartResponse {
+            login_id: login.get_id().as_string(),
+            credential_response,
+            is_plaintext_allowed: Settings::get().security.password.allow_plaintext,
+        }))
+    }
+
+    async fn opaque_reauthenticate_finish(
+        &self,
+        request: Request<OpaqueLoginFinishRequest>,
+    ) -> Result<Response<OpaqueReauthenticateFinishResponse>, Status> {
+        let user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        let login_id = request.login_id.split(':').last().unwrap();
+        let login = repos::opaque_login::read(login_id).await?;
+        let login = match login {
+            Some(l)
+                if l.verified_on.is_none()
+                    && l.user_id.as_deref() == Some(user.get_id().full_identifier()) =>
+            {
+                l
+            }
+            _ => return Err(Status::not_found("opaque_login_not_found")),
+        };
+        if !login.verify() {
+            return Err(Status::failed_precondition("opaque_login_expired"));
+        }
+
+        let mut state = login.state.clone();
+        state.decrypt().await?;
+        if !opaque::finish_login(state.value().unwrap(), &request.credential_finalization)? {
+            repos::opaque_login::take(login_id).await?;
+            return Err(Status::permission_denied("invalid_password"));
+        }
+
+        if !repos::opaque_login::mark_verified(&login, Utc::now() + opaque::proof_timeout()).await?
+        {
+            return Err(Status::not_found("opaque_login_not_found"));
+        }
+
+        throttle::forgive(&throttle::keys(&user.username, address)).await?;
+
+        Ok(Response::new(OpaqueReauthenticateFinishResponse {
+            password_proof: login.get_id().as_string(),
+        }))
+    }
+
+    async fn recover_account_start(
+        &self,
+        request: Request<RecoverAccountStartRequest>,
+    ) -> Result<Response<RecoverAccountStartResponse>, Status> {
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        EmptyResult::from(validators::username_format(request.username.as_str()))?;
+
+        // Only the recovery key can finish a recovery, but starting one is limited like a login
+        let throttle_keys = throttle::keys(request.username.as_str(), address);
+        throttle::attempt(&throttle_keys).await?;
+
+        // The keyring sealed with the password is left out, since anyone can get this far and it
+        // could be used to guess the password
+        let user = repos::user::read_by_username(request.username.as_str()).await?;
+        let (user, keyring, recovery_key) = match user {
+            Some(u) if u.is_active && u.keyring.is_some() && u.recovery_key.is_some() => {
+                let keyring = u.keyring.clone().unwrap();
+                let recovery_key = u.recovery_key.clone().unwrap();
+                (u, keyring, recovery_key)
+            }
+            _ => return Err(Status::not_found("account_recovery_unavailable")),
+        };
+
+        let registration_response = Opaque::lock()
+            .await
+            .start_registration(&request.registration_request, &user.username)?;
+
+        let recovery = AccountRecovery::new(user.get_id().full_identifier().to_string());
+        let recovery = repos::account_recovery::create(recovery).await?;
+
+        Ok(Response::new(RecoverAccountStartResponse {
+            recovery_id: recovery.get_id().as_string(),
+            registration_response,
+            recovery_key: Some(recovery_key.into()),
+            public_key: keyring.public_key.into(),
+            signing_public_key: keyring.signing_public_key.into(),
+        }))
+    }
+
+    async fn recover_account(
+        &self,
+        request: Request<RecoverAccountRequest>,
+    ) -> Result<Response<RecoverAccountResponse>, Status> {
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        let recovery =
+            repos::account_recovery::take(request.recovery_id.split(':').last().unwrap()).await?;
+        let recovery = match recovery {
+            Some(r) if r.verify() => r,
+            _ => return Err(Status::not_found("account_recovery_not_found")),
+        };
+
+        let user = repos::user::read(recovery.user_id.split(':').last().unwrap()).await?;
+        let mut user = match user {
+            Some(u) if u.is_active && u.keyring.is_some() && u.recovery_key.is_some() => u,
+            _ => return Err(Status::not_found("account_recovery_unavailable")),
+        };
+
+        let keyring = request
+            .keyring
+            .ok_or_else(|| Status::invalid_argument("invalid_keyring__missing"))?;
+        EmptyResult::from(validators::keyring(&keyring))?;
+        let keyring = Keyring::from(keyring);
+        let new_recovery_key = recovery::parse_recovery_key(request.recovery_key, true)?
+            .ok_or_else(|| Status::invalid_argument("invalid_recovery_key__missing"))?;
+
+        if !recovery::verify_signature(
+            user.recovery_key.as_ref().unwrap(),
+            &recovery.get_id().as_string(),
+            &request.registration_upload,
+            &new_recovery_key.verifying_key,
+            &request.signature,
+        ) {
+            return Err(Status::permission_denied("invalid_recovery_key"));
+        }
+
+        if !user.keyring.as_ref().unwrap().has_same_keys(&keyring) {
+            return Err(Status::failed_precondition("keyring_mismatch"));
+        }
+
+        let registration = opaque::finish_registration(&request.registration_upload)?;
+
+        // The recovery key has been used, so it is replaced like the password
+        user.opaque_registration = Some(Cow::Owned(registration));
+        user.keyring = Some(keyring);
+        user.recovery_key = Some(new_recovery_key);
+        repos::user::update_recovered_credentials(&user).await?;
+
+        let password =
+            repos::password::read_active_by_user_id(user.get_id().full_identifier()).await?;
+        if let Some(mut password) = password {
+            password.is_active = false;
+            repos::password::update(&password).await?;
+        }
+
+        // Whoever had the password may have been using it, so nobody stays logged in
+        repos::refresh_token::revoke_all_by_user_id(user.get_id().full_identifier()).await?;
+        throttle::forgive(&throttle::keys(&user.username, address)).await?;
+
+        tracing::info!(
+            "User {} recovered their account",
+            user.get_id().full_identifier()
+        );
+
+        Ok(Response::new(RecoverAccountResponse {}))
+    }
+
+    async fn verify_email(
+        &self,
+        request: Request<VerifyEmailRequest>,
+    ) -> Result<Response<VerifyEmailResponse>, Status> {
+        let request = request.into_inner();
+
+        let email_token =
+            email::find_token(request.token.as_str(), EmailTokenPurpose::Verification).await?;
+
+        let user = repos::user::read(email_token.user_id.split(':').last().unwrap()).await?;
+        let mut user = match user {
+            Some(u) if u.is_active => u,
+            _ => return Err(Status::permission_denied("invalid_email_token")),
+        };
+
+        if !repos::email_token::mark_used(&email_token).await? {
+            return Err(Status::permission_denied("email_token_expired"));
+        }
+
+        user.is_email_verified = true;
+        repos::user::update_is_email_verified(&user).await?;
+
+        Ok(Response::new(VerifyEmailResponse {}))
+    }
+
+    async fn request_password_reset(
+        &self,
+        request: Request<RequestPasswordResetRequest>,
+    ) -> Result<Response<RequestPasswordResetResponse>, Status> {
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        EmptyResult::from(validators::username_format(request.username.as_str()))?;
+
+        // Each request counts against the client like a failed login, so that nobody can queue up
+        // mails without limit. The username is left out, or anyone could lock its owner out.
+        if let Some(address) = address {
+            throttle::attempt(&[throttle::client_key(address)]).await?;
+        }
+
+        // Answered before anything is looked up, so that neither the answer nor the time it
+        // takes tells which usernames exist or have a verified address
+        tokio::spawn(async move {
+            if let Err(e) = email::send_password_reset(&request.username).await {
+                tracing::warn!(
+                    "Failed to send a password reset to {}: {:?}",
+                    request.username,
+                    e
+                );
+            }
+        });
+
+        Ok(Response::new(RequestPasswordResetResponse {}))
+    }
+
+    async fn reset_password_start(
+        &self,
+        request: Request<ResetPasswordStartRequest>,
+    ) -> Result<Response<ResetPasswordStartResponse>, Status> {
+        let request = request.into_inner();
+
+        let email_token =
+            email::find_token(request.token.as_str(), EmailTokenPurpose::PasswordReset).await?;
+        let user = Self::password_reset_user(&email_token).await?;
+
+        let registration_response = Opaque::lock()
+            .await
+            .start_registration(&request.registration_request, &user.username)?;
+
+        Ok(Response::new(ResetPasswordStartResponse {
+            registration_response,
+        }))
+    }
+
+    async fn reset_password(
+        &self,
+        request: Request<ResetPasswordRequest>,
+    ) -> Result<Response<ResetPasswordResponse>, Status> {
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        let email_token =
+            email::find_token(request.token.as_str(), EmailTokenPurpose::PasswordReset).await?;
+        let mut user = Self::password_reset_user(&email_token).await?;
+
+        let registration = opaque::finish_registration(&request.registration_upload)?;
+
+        if !repos::email_token::mark_used(&email_token).await? {
+            return Err(Status::permission_denied("email_token_expired"));
+        }
+
+        // TOTP stays on, so a reset only stands in for the password
+        user.opaque_registration = Some(Cow::Owned(registration));
+        repos::user::update_opaque_registration(&user, true).await?;
+
+        // Accounts still on Argon2id move over to OPAQUE, like they would at their next login
+        let password =
+            repos::password::read_active_by_user_id(user.get_id().full_identifier()).await?;
+        if let Some(mut password) = password {
+            password.is_active = false;
+            repos::password::update(&password).await?;
+        }
+
+        // Whoever had the password may have been using it, so nobody stays logged in
+        repos::refresh_token::revoke_all_by_user_id(user.get_id().full_identifier()).await?;
+        throttle::forgive(&throttle::keys(&user.username, address)).await?;
+
+        tracing::info!(
+            "User {} reset their password",
+            user.get_id().full_identifier()
+        );
+
+        Ok(Response::new(ResetPasswordResponse {}))
+    }
 }
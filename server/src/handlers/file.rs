@@ -0,0 +1,198 @@
+use async_trait::async_trait;
+use shared::error::EmptyResult;
+use singleton::unsync::Singleton as UnsyncSingleton;
+use std::borrow::Cow;
+use std::pin::Pin;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::config::Settings;
+use crate::fs::{FileSystem, FileWriter};
+use crate::helpers::auth_guard::rpc_name;
+use crate::helpers::authorization::{Access, AuthContext};
+use crate::helpers::keys;
+use crate::models::auth::{Scope, User};
+use crate::models::fs::File;
+use crate::{repos, validators};
+use protobuf::pandorica_file::{
+    download_response, file_service_server, upload_request, DownloadRequest, DownloadResponse,
+    UploadRequest, UploadResponse,
+};
+
+#[derive(Default)]
+pub struct FileService {}
+
+#[async_trait]
+impl file_service_server::FileService for FileService {
+    async fn upload(
+        &self,
+        request: Request<Streaming<UploadRequest>>,
+    ) -> Result<Response<UploadResponse>, Status> {
+        let mut user = AuthContext::get(&request)?.user;
+        let mut stream = request.into_inner();
+
+        let name = match stream.message().await? {
+            Some(UploadRequest {
+                data: Some(upload_request::Data::Name(name)),
+            }) => name,
+            _ => return Err(Status::invalid_argument("invalid_file__name_missing")),
+        };
+
+        EmptyResult::from(validators::file_name(name.as_str()))?;
+
+        let kek_id = keys::user_kek_id(&mut user).await?;
+
+        let file = File::new(user.get_id().full_identifier().to_string(), name);
+        let mut file = repos::file::create(file).await?;
+
+        let mut writer = FileSystem::get().write(&file.location(), &kek_id).await?;
+        file.multipart_id = Some(writer.multipart_id().to_string().into());
+
+        let result = match repos::file::update(&file).await {
+            Ok(()) => Self::receive_chunks(&mut stream, &mut writer, &user).await,
+            Err(e) => Err(Status::from(e)),
+        };
+        let result = match result {
+            Ok(wrapped_dek) => writer
+                .finish()
+                .await
+                .map(|size| (size, wrapped_dek))
+                .map_err(Status::from),
+            Err(e) => {
+                // The record is deleted either way, and a part left behind is only wasted space
+                if let Err(abort_error) = writer.abort().await {
+                    tracing::error!(
+                        "Failed to abort the upload to {}: {:?}",
+                        file.location(),
+                        abort_error
+                    );
+                }
+                Err(e)
+            }
+        };
+
+        match result {
+            Ok((size, wrapped_dek)) => {
+                file.size = size;
+                file.wrapped_dek = wrapped_dek.map(Cow::Owned);
+                file.is_uploading = false;
+                file.multipart_id = None;
+            }
+            Err(e) => {
+                repos::file::delete(file.get_id().partial_identifier()).await?;
+                return Err(e);
+            }
+        }
+        repos::file::update(&file).await?;
+
+        Ok(Response::new(UploadResponse {
+            file: Some(file.into()),
+        }))
+    }
+
+    type DownloadStream = Pin<Box<dyn Stream<Item = Result<DownloadResponse, Status>> + Send>>;
+
+    async fn download(
+        &self,
+        request: Request<DownloadRequest>,
+    ) -> Result<Response<Self::DownloadStream>, Status> {
+        let user = AuthContext::get(&request)?.user;
+        let request = request.into_inner();
+
+        let file = repos::file::read(request.id.split(':').last().unwrap()).await?;
+        if file.is_none() {
+            return Err(Status::not_found("file_not_found"));
+        }
+        let file = file.unwrap();
+        if file.user_id != user.get_id().full_identifier() || file.is_uploading {
+            return Err(Status::not_found("file_not_found"));
+        }
+
+        let mut reader = FileSystem::get().read(&file.location()).await?;
+        let metadata = file.into();
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let message = DownloadResponse {
+                data: Some(download_response::Data::File(metadata)),
+            };
+            if tx.send(Ok(message)).await.is_err() {
+                return;
+            }
+
+            loop {
+                let message = match reader.next().await {
+                    Ok(Some(chunk)) => Ok(DownloadResponse {
+                        data: Some(download_response::Data::Chunk(chunk)),
+                    }),
+                    Ok(None) => return,
+                    Err(e) => Err(Status::from(e)),
+                };
+
+                let is_err = message.is_err();
+                if tx.send(message).await.is_err() || is_err {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+impl FileService {
+    pub fn access(path: &str) -> Access {
+        match rpc_name(path) {
+            "Upload" => Access::Scope(Scope::FilesWrite),
+            "Download" => Access::Scope(Scope::FilesRead),
+            _ => Access::Session,
+        }
+    }
+
+    /// Writes the chunks of an upload. Returns the wrapped DEK of a file encrypted end to end,
+    /// which has to come before the first chunk.
+    async fn receive_chunks(
+        stream: &mut Streaming<UploadRequest>,
+        writer: &mut FileWriter<'_>,
+        user: &User<'_>,
+    ) -> Result<Option<Vec<u8>>, Status> {
+        let max_size = Settings::get().fs.max_upload_size_mib * 1024 * 1024;
+
+        let mut wrapped_dek = None;
+        let mut has_chunks = false;
+        let mut size: u64 = 0;
+        while let Some(message) = stream.message().await? {
+            match message.data {
+                Some(upload_request::Data::Chunk(chunk)) => {
+                    has_chunks = true;
+                    size += chunk.len() as u64;
+                    if size > max_size {
+                        return Err(Status::invalid_argument("invalid_file__too_large"));
+                    }
+                    writer.write(&chunk).await?;
+                }
+                Some(upload_request::Data::WrappedDek(w)) => {
+                    if has_chunks || wrapped_dek.is_some() {
+                        return Err(Status::invalid_argument(
+                            "invalid_file__wrapped_dek_position",
+                        ));
+                    }
+                    if user.keyring.is_none() {
+                        return Err(Status::failed_precondition("keyring_not_found"));
+                    }
+                    EmptyResult::from(validators::wrapped_dek(&w))?;
+
+                    wrapped_dek = Some(w);
+                }
+                Some(upload_request::Data::Name(_)) => {
+                    return Err(Status::invalid_argument("invalid_file__name_duplicate"))
+                }
+                None => {}
+            }
+        }
+
+        Ok(wrapped_dek)
+    }
+}
@@ -1,40 +1,49 @@
-use crate::helpers::authorization::get_session;
-use crate::repos;
+use crate::config::Settings;
+use crate::fs::FileSystem;
+use crate::helpers::auth_guard::rpc_name;
+use crate::helpers::authorization::{verify_password, Access, AuthContext};
+use crate::helpers::{email, recovery};
+use crate::models::auth::{AccessToken, EmailTokenPurpose, Scope};
+use crate::models::crypto::Keyring;
+use crate::{repos, validators};
 use async_trait::async_trait;
+use chrono::{Duration, Utc};
 use protobuf::pandorica_common;
-use protobuf::pandorica_user::{user_service_server, MeRequest, MeResponse};
+use protobuf::pandorica_user::{
+    user_service_server, CreateAccessTokenRequest, CreateAccessTokenResponse, DeleteRequest,
+    DeleteResponse, GetKeyringRequest, GetKeyringResponse, ListAccessTokensRequest,
+    ListAccessTokensResponse, MeRequest, MeResponse, RevokeAccessTokenRequest,
+    RevokeAccessTokenResponse, RevokeOtherSessionsRequest, RevokeOtherSessionsResponse,
+    RevokeSessionRequest, RevokeSessionResponse, SendEmailVerificationRequest,
+    SendEmailVerificationResponse, SetKeyringRequest, SetKeyringResponse, SetRecoveryKeyRequest,
+    SetRecoveryKeyResponse,
+};
+use shared::error::EmptyResult;
+use singleton::unsync::Singleton as UnsyncSingleton;
 use tonic::{Request, Response, Status};
 
 #[derive(Default)]
 pub struct UserService {}
 
+impl UserService {
+    pub fn access(path: &str) -> Access {
+        match rpc_name(path) {
+            "Me" => Access::Scope(Scope::AccountRead),
+            // Files can't be read or written end to end without it
+            "GetKeyring" => Access::Scope(Scope::FilesRead),
+            _ => Access::Session,
+        }
+    }
+}
+
 #[async_trait]
 impl user_service_server::UserService for UserService {
     async fn me(&self, request: Request<MeRequest>) -> Result<Response<MeResponse>, Status> {
-        let metadata = request.metadata();
-        let session = get_session(metadata).await?;
-
-        let user = repos::user::read(session.user_id.split(':').last().unwrap()).await?;
-        if user.is_none() {
-            return Err(Status::unauthenticated("User not found"));
-        }
-        let mut user = user.unwrap();
-        if !user.is_active {
-            return Err(Status::unauthenticated("User is inactive"));
-        }
+        let mut user = AuthContext::get(&request)?.user;
         if user.email.is_some() {
             user.email.as_mut().unwrap().decrypt().await?;
         }
 
-        let session = repos::session::read(session.get_id().partial_identifier()).await?;
-        if session.is_none() {
-            return Err(Status::unauthenticated("Session not found"));
-        }
-        let session = session.unwrap();
-        if !session.verify() {
-            return Err(Status::unauthenticated("Session expired"));
-        }
-
         let sessions = repos::session::read_all_by_user_id(user.get_id().full_identifier()).await?;
         let mut parsed_sessions: Vec<pandorica_common::Session> = Vec::new();
         for session in sessions {
@@ -50,4 +59,228 @@ impl user_service_server::UserService for UserService {
             sessions: parsed_sessions,
         }))
     }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        verify_password(&user, request.password, request.opaque_proof, address).await?;
+
+        let files = repos::file::read_all_by_user_id(user.get_id().full_identifier()).await?;
+        repos::user::delete(&user).await?;
+
+        // The files can no longer be decrypted once the key encryption key is gone, so failing
+        // to remove one only leaves unreadable data behind
+        for file in files {
+            if let Err(e) = FileSystem::get().delete(&file.location()).await {
+                tracing::warn!("Failed to delete {}: {:?}", file.location(), e);
+            }
+        }
+
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    async fn revoke_session(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<RevokeSessionResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let session = context.session()?;
+        let request = request.into_inner();
+
+        // Someone else's session is reported the same way as a missing one, so that session IDs
+        // can't be probed
+        let target = repos::session::read(request.id.split(':').last().unwrap()).await?;
+        let mut target = match target {
+            Some(t) if t.user_id == session.user_id && t.verify() => t,
+            _ => return Err(Status::not_found("session_not_found")),
+        };
+
+        // Sessions from before refresh tokens have no family, and revoking the empty one would
+        // hit every other such session
+        if target.family_id.is_empty() {
+            target.expires_on = Utc::now();
+            repos::session::update(&target).await?;
+        } else {
+            repos::refresh_token::revoke_family(&target.family_id).await?;
+        }
+
+        Ok(Response::new(RevokeSessionResponse {}))
+    }
+
+    async fn revoke_other_sessions(
+        &self,
+        request: Request<RevokeOtherSessionsRequest>,
+    ) -> Result<Response<RevokeOtherSessionsResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let session = context.session()?;
+
+        repos::refresh_token::revoke_all_by_user_id_except(session).await?;
+
+        Ok(Response::new(RevokeOtherSessionsResponse {}))
+    }
+
+    async fn create_access_token(
+        &self,
+        request: Request<CreateAccessTokenRequest>,
+    ) -> Result<Response<CreateAccessTokenResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let session = context.session()?;
+        let request = request.into_inner();
+
+        let lifetime_days = request
+            .lifetime_days
+            .unwrap_or(Settings::get().access_token.default_lifetime_days);
+        EmptyResult::from(validators::access_token(
+            request.name.as_str(),
+            &request.scopes,
+            lifetime_days,
+        ))?;
+
+        let mut scopes: Vec<Scope> = Vec::new();
+        for scope in request.scopes.iter().filter_map(|s| Scope::parse(s)) {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+
+        let access_token = AccessToken::new(
+            session.user_id.to_string(),
+            request.name.trim().to_string(),
+            scopes,
+            Utc::now() + Duration::days(lifetime_days as i64),
+        )?;
+        let token = access_token.token.clone().unwrap();
+        let access_token = repos::access_token::create(access_token).await?;
+
+        Ok(Response::new(CreateAccessTokenResponse {
+            access_token: Some(access_token.into()),
+            token: token.as_sensitive_str().into(),
+        }))
+    }
+
+    async fn list_access_tokens(
+        &self,
+        request: Request<ListAccessTokensRequest>,
+    ) -> Result<Response<ListAccessTokensResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let session = context.session()?;
+
+        let access_tokens = repos::access_token::read_all_by_user_id(&session.user_id).await?;
+        let access_tokens: Vec<pandorica_common::AccessToken> = access_tokens
+            .into_iter()
+            .filter(|a| a.verify())
+            .map(|a| a.into())
+            .collect();
+
+        Ok(Response::new(ListAccessTokensResponse { access_tokens }))
+    }
+
+    async fn revoke_access_token(
+        &self,
+        request: Request<RevokeAccessTokenRequest>,
+    ) -> Result<Response<RevokeAccessTokenResponse>, Status> {
+        let context = AuthContext::get(&request)?;
+        let session = context.session()?;
+        let request = request.into_inner();
+
+        let access_token = repos::access_token::read(request.id.split(':').last().unwrap()).await?;
+        let mut access_token = match access_token {
+            Some(a) if a.user_id == session.user_id && a.verify() => a,
+            _ => return Err(Status::not_found("access_token_not_found")),
+        };
+
+        access_token.is_revoked = true;
+        repos::access_token::update(&access_token).await?;
+
+        Ok(Response::new(RevokeAccessTokenResponse {}))
+    }
+
+    async fn get_keyring(
+        &self,
+        request: Request<GetKeyringRequest>,
+    ) -> Result<Response<GetKeyringResponse>, Status> {
+        let user = AuthContext::get(&request)?.user;
+
+        Ok(Response::new(GetKeyringResponse {
+            keyring: user.keyring.map(|k| k.into()),
+        }))
+    }
+
+    async fn set_keyring(
+        &self,
+        request: Request<SetKeyringRequest>,
+    ) -> Result<Response<SetKeyringResponse>, Status> {
+        let mut user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        let keyring = request
+            .keyring
+            .ok_or_else(|| Status::invalid_argument("invalid_keyring__missing"))?;
+        EmptyResult::from(validators::keyring(&keyring))?;
+        let recovery_key = recovery::parse_recovery_key(request.recovery_key, true)?;
+
+        verify_password(&user, request.password, request.opaque_proof, address).await?;
+
+        // Replacing the key pairs would lose every file wrapped to them. Sealing them with a new
+        // password is part of changing it.
+        if user.keyring.is_some() {
+            return Err(Status::failed_precondition("keyring_exists"));
+        }
+
+        user.keyring = Some(Keyring::from(keyring));
+        user.recovery_key = recovery_key;
+        if !repos::user::set_keyring(&user).await? {
+            return Err(Status::failed_precondition("keyring_exists"));
+        }
+
+        Ok(Response::new(SetKeyringResponse {}))
+    }
+
+    async fn set_recovery_key(
+        &self,
+        request: Request<SetRecoveryKeyRequest>,
+    ) -> Result<Response<SetRecoveryKeyResponse>, Status> {
+        let mut user = AuthContext::get(&request)?.user;
+        let address = request.remote_addr().map(|a| a.ip());
+        let request = request.into_inner();
+
+        let recovery_key =
+            recovery::parse_recovery_key(request.recovery_key, user.keyring.is_some())?
+                .ok_or_else(|| Status::invalid_argument("invalid_recovery_key__missing"))?;
+
+        verify_password(&user, request.password, request.opaque_proof, address).await?;
+
+        // Whatever the old recovery key was, it stops working here
+        user.recovery_key = Some(recovery_key);
+        repos::user::update_recovery_key(&user).await?;
+
+        Ok(Response::new(SetRecoveryKeyResponse {}))
+    }
+
+    async fn send_email_verification(
+        &self,
+        request: Request<SendEmailVerificationRequest>,
+    ) -> Result<Response<SendEmailVerificationResponse>, Status> {
+        let mut user = AuthContext::get(&request)?.user;
+
+        if user.email.is_none() {
+            return Err(Status::failed_precondition("email_not_set"));
+        }
+        if user.is_email_verified {
+            return Err(Status::failed_precondition("email_already_verified"));
+        }
+        if !email::is_resend_allowed(&user, EmailTokenPurpose::Verification).await? {
+            return Err(Status::resource_exhausted("email_sent_recently"));
+        }
+
+        email::send_verification(&mut user).await?;
+
+        Ok(Response::new(SendEmailVerificationResponse {}))
+    }
 }
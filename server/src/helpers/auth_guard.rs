@@ -0,0 +1,70 @@
+use std::convert::Infallible;
+use tonic::body::BoxBody;
+use tonic::codegen::{http, BoxFuture, Context, Poll, Service};
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Body, NamedService};
+
+use crate::helpers::authorization::{authorize, Access};
+
+/// Wraps a service so that each of its RPCs is authenticated once, before its handler runs,
+/// with what `policy` returns for the RPC's path. Handlers find the caller in the request
+/// extensions as an `AuthContext`.
+#[derive(Clone)]
+pub struct AuthGuard<S> {
+    inner: S,
+    policy: fn(&str) -> Access,
+}
+
+impl<S> AuthGuard<S> {
+    pub fn new(inner: S, policy: fn(&str) -> Access) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl<S: NamedService> NamedService for AuthGuard<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<Body>> for AuthGuard<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<Body>) -> Self::Future {
+        // The instance that was polled ready has to be the one that handles the request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let access = (self.policy)(request.uri().path());
+
+        Box::pin(async move {
+            if let Access::Public = access {
+                return inner.call(request).await;
+            }
+
+            let metadata = MetadataMap::from_headers(request.headers().clone());
+            let context = match authorize(&metadata, access).await {
+                Ok(c) => c,
+                Err(status) => return Ok(status.to_http()),
+            };
+
+            request.extensions_mut().insert(context);
+            inner.call(request).await
+        })
+    }
+}
+
+/// The name of the RPC a request path such as `/pandorica_auth.AuthService/Login` is for.
+pub fn rpc_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or_default()
+}
@@ -1,38 +1,296 @@
-use crate::models::auth::Session;
+use crate::config::Settings;
+use crate::helpers::throttle;
+use crate::models::auth::{AccessToken, Password, Role, Scope, Session, Token, User};
 use crate::repos;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
+use singleton::unsync::Singleton as UnsyncSingleton;
+use std::net::IpAddr;
 use tonic::metadata::MetadataMap;
-use tonic::Status;
+use tonic::{Request, Status};
 
-pub async fn get_session(metadata: &MetadataMap) -> Result<Session, Status> {
-    let metadata = metadata.get("session_id");
+/// What an RPC requires of its caller.
+#[derive(Clone, Copy)]
+pub enum Access {
+    /// Nothing, like logging in.
+    Public,
+    /// A session that has passed the password check but still waits for a second factor.
+    MfaPending,
+    /// A session, even of a user who has been told to reset their password, which is all that
+    /// such a session is good for.
+    PasswordChange,
+    Session,
+    /// A session, or a personal access token with the scope.
+    Scope(Scope),
+    /// A session of a user with at least the role.
+    Role(Role),
+}
+
+/// Who made a request, put into the request extensions by `AuthGuard` for every RPC that
+/// isn't public.
+#[derive(Clone)]
+pub struct AuthContext {
+    pub user: User<'static>,
+    /// `None` for a personal access token.
+    pub session: Option<Session<'static>>,
+    /// What the request may do. A session may do everything.
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl AuthContext {
+    pub fn get<T>(request: &Request<T>) -> Result<AuthContext, Status> {
+        request
+            .extensions()
+            .get::<AuthContext>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("No session token provided"))
+    }
+
+    pub fn session(&self) -> Result<&Session<'static>, Status> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| Status::permission_denied("session_required"))
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match self.scopes.as_ref() {
+            Some(s) => s.contains(&scope),
+            None => true,
+        }
+    }
+}
+
+/// Authenticates a request to an RPC that isn't public.
+pub async fn authorize(metadata: &MetadataMap, access: Access) -> Result<AuthContext, Status> {
+    let token = bearer_token(metadata)?;
+    if let Some(token) = token.strip_prefix(AccessToken::PREFIX) {
+        let scope = match access {
+            Access::Scope(s) => s,
+            _ => return Err(Status::permission_denied("session_required")),
+        };
+
+        let (access_token, user) = authenticate_access_token(token).await?;
+        if user.is_password_reset_required {
+            return Err(Status::failed_precondition("password_reset_required"));
+        }
+
+        let context = AuthContext {
+            user,
+            session: None,
+            scopes: Some(access_token.scopes),
+        };
+        if !context.has_scope(scope) {
+            return Err(Status::permission_denied("insufficient_scope"));
+        }
+
+        return Ok(context);
+    }
+
+    let (session, user) = authenticate(token).await?;
+    match access {
+        Access::MfaPending if !session.is_mfa_pending => {
+            return Err(Status::failed_precondition("mfa_not_pending"))
+        }
+        Access::MfaPending => {}
+        _ if session.is_mfa_pending => return Err(Status::unauthenticated("mfa_required")),
+        Access::PasswordChange => {}
+        _ if user.is_password_reset_required => {
+            return Err(Status::failed_precondition("password_reset_required"))
+        }
+        Access::Role(role) if user.role < role => {
+            return Err(Status::permission_denied("insufficient_role"))
+        }
+        _ => {}
+    }
+
+    Ok(AuthContext {
+        user,
+        session: Some(session),
+        scopes: None,
+    })
+}
+
+/// Checks that the user knows their password, before something a session alone isn't enough
+/// for. An account on OPAQUE never sends its password, and sends `opaque_proof` instead, the
+/// proof from `AuthService::opaque_reauthenticate_finish`, which is used up.
+pub async fn verify_password(
+    user: &User<'_>,
+    password: String,
+    opaque_proof: Option<String>,
+    address: Option<IpAddr>,
+) -> Result<(), Status> {
+    if user.opaque_registration.is_some() {
+        let proof = opaque_proof.ok_or_else(|| Status::permission_denied("invalid_password"))?;
+        let proof = proof.split(':').last().unwrap();
+        if !repos::opaque_login::take_proof(proof, user.get_id().full_identifier()).await? {
+            return Err(Status::permission_denied("invalid_password"));
+        }
+
+        return Ok(());
+    }
+
+    check_plaintext_allowed()?;
+
+    // A stolen session is no better for guessing the password than a login
+    let throttle_keys = throttle::keys(&user.username, address);
+    throttle::attempt(&throttle_keys).await?;
+    if check_password(user, password).await?.is_none() {
+        return Err(Status::permission_denied("invalid_password"));
+    }
+    throttle::forgive(&throttle_keys).await?;
+
+    Ok(())
+}
+
+/// Fails once passwords may no longer be sent to the server.
+pub fn check_plaintext_allowed() -> Result<(), Status> {
+    if !Settings::get().security.password.allow_plaintext {
+        return Err(Status::failed_precondition("plaintext_password_disabled"));
+    }
+
+    Ok(())
+}
+
+/// Checks `password` against the user's active password, which is returned. A password hashed
+/// with outdated Argon2id parameters is re-hashed on the way. A wrong password, or a user
+/// without one, is `None`, and takes about as long as a match.
+pub async fn check_password<'a>(
+    user: &'a User<'_>,
+    password: String,
+) -> Result<Option<Password<'a>>, Status> {
+    let request_password = Password::new(password.into(), String::default())?;
+    let password = repos::password::read_active_by_user_id(user.get_id().full_identifier()).await?;
+    if password.is_none() {
+        request_password.verify(&request_password)?;
+        return Ok(None);
+    }
+    let mut password = password.unwrap();
+
+    if !request_password.verify(&password)? {
+        return Ok(None);
+    }
+
+    if password.needs_rehash() {
+        password.hash = request_password.hash;
+        repos::password::update(&password).await?;
+    }
+
+    Ok(Some(password))
+}
+
+/// Does the work of `check_password` without a user to check against, so that logins for
+/// unknown usernames can't be told apart by how long they take.
+pub fn check_no_password(password: String) -> Result<(), Status> {
+    let request_password = Password::new(password.into(), String::default())?;
+    request_password.verify(&request_password)?;
+
+    Ok(())
+}
+
+fn bearer_token(metadata: &MetadataMap) -> Result<&str, Status> {
+    let metadata = metadata.get("authorization");
     if metadata.is_none() {
-        return Err(Status::unauthenticated("No session_id provided"));
+        return Err(Status::unauthenticated("No session token provided"));
     }
 
-    let session_id = metadata.unwrap().to_str();
-    if session_id.is_err() {
-        return Err(Status::unauthenticated("Invalid session_id provided"));
+    metadata
+        .unwrap()
+        .to_str()
+        .ok()
+        .and_then(|t| t.strip_prefix("Bearer "))
+        .ok_or_else(|| Status::unauthenticated("Invalid session token provided"))
+}
+
+/// Whether an activity timestamp is old enough to be written again. Writing it on every request
+/// would cost a busy client two writes per call, for a timestamp nobody needs to the second.
+fn is_activity_stale(last_activity: DateTime<Utc>) -> bool {
+    Utc::now() - last_activity
+        >= Duration::seconds(Settings::get().session.activity_write_interval_seconds)
+}
+
+async fn authenticate(token: &str) -> Result<(Session<'static>, User<'static>), Status> {
+    let token = Token::parse(token);
+    if token.is_none() {
+        return Err(Status::unauthenticated("Invalid session token provided"));
     }
-    let session_id = session_id.unwrap();
+    let (selector, verifier) = token.unwrap();
 
-    let session = repos::session::read(session_id).await?;
+    let session = repos::session::read_by_token_selector(selector).await?;
     if session.is_none() {
-        return Err(Status::unauthenticated("Invalid session_id provided"));
+        return Err(Status::unauthenticated("Invalid session token provided"));
     }
     let mut session = session.unwrap();
 
+    if !Token::verify(&verifier, &session.token_hash)? {
+        return Err(Status::unauthenticated("Invalid session token provided"));
+    }
+
+    if !session.verify() {
+        return Err(Status::unauthenticated("Session expired"));
+    }
+
     let user = repos::user::read(session.user_id.split(':').last().unwrap()).await?;
     if user.is_none() {
         return Err(Status::unauthenticated("User not found"));
     }
     let mut user = user.unwrap();
+    if !user.is_active {
+        return Err(Status::unauthenticated("User is inactive"));
+    }
 
-    session.last_used_on = Utc::now();
-    repos::session::update(&session).await?;
+    if is_activity_stale(session.last_used_on) {
+        session.touch();
+        repos::session::touch(&session).await?;
+    }
+
+    if is_activity_stale(user.last_seen_on) {
+        user.last_seen_on = Utc::now();
+        repos::user::update_last_seen_on(&user).await?;
+    }
 
-    user.last_seen_on = Utc::now();
-    repos::user::update(&user).await?;
+    Ok((session, user))
+}
+
+async fn authenticate_access_token(
+    token: &str,
+) -> Result<(AccessToken<'static>, User<'static>), Status> {
+    let token = Token::parse(token);
+    if token.is_none() {
+        return Err(Status::unauthenticated("invalid_access_token"));
+    }
+    let (selector, verifier) = token.unwrap();
+
+    let access_token = repos::access_token::read_by_token_selector(selector).await?;
+    if access_token.is_none() {
+        return Err(Status::unauthenticated("invalid_access_token"));
+    }
+    let mut access_token = access_token.unwrap();
+
+    if !Token::verify(&verifier, &access_token.token_hash)? {
+        return Err(Status::unauthenticated("invalid_access_token"));
+    }
+
+    if !access_token.verify() {
+        return Err(Status::unauthenticated("access_token_expired"));
+    }
+
+    let user = repos::user::read(access_token.user_id.split(':').last().unwrap()).await?;
+    if user.is_none() {
+        return Err(Status::unauthenticated("User not found"));
+    }
+    let mut user = user.unwrap();
+    if !user.is_active {
+        return Err(Status::unauthenticated("User is inactive"));
+    }
+
+    if access_token.last_used_on.map_or(true, is_activity_stale) {
+        access_token.last_used_on = Some(Utc::now());
+        repos::access_token::update_last_used_on(&access_token).await?;
+    }
+
+    if is_activity_stale(user.last_seen_on) {
+        user.last_seen_on = Utc::now();
+        repos::user::update_last_seen_on(&user).await?;
+    }
 
-    Ok(session)
+    Ok((access_token, user))
 }
@@ -0,0 +1,209 @@
+// Breached password filter, version 1
+//
+// | magic "PDBF" | version: u8 | hash count: u8 | bit count: u64 BE | bits |
+//
+// A bloom filter over the SHA-1 hashes listed in the HIBP Pwned Passwords downloads. SHA-1 is
+// already uniformly distributed, so the bit positions are derived from the hash itself by double
+// hashing. Bit `n` is bit `n % 8` of byte `n / 8`.
+
+use sha1::{Digest, Sha1};
+use shared::error::OperationResult;
+use singleton::{unsync::Singleton as UnsyncSingleton, Singleton, SingletonInit};
+use std::f64::consts::LN_2;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::Settings;
+
+const MAGIC: &[u8; 4] = b"PDBF";
+const VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 14;
+const HASH_SIZE: usize = 20;
+const MAX_HASH_COUNT: f64 = 32.0;
+
+#[derive(Singleton)]
+#[singleton(sync = false)]
+pub struct BreachFilter {
+    /// The filter is read bit by bit rather than loaded, since one built from the full corpus
+    /// takes more than a gigabyte. Reads are positional, so checks don't wait on each other.
+    file: Option<Arc<File>>,
+    hash_count: u8,
+    bit_count: u64,
+}
+
+impl BreachFilter {
+    pub fn open(path: &Path) -> OperationResult<Self> {
+        let mut file = File::open(path)?;
+
+        let mut header = [0_u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)
+            .map_err(|_| anyhow::Error::msg("invalid_breach_filter__format"))?;
+        if &header[..4] != MAGIC {
+            return Err(anyhow::Error::msg("invalid_breach_filter__format").into());
+        }
+        if header[4] != VERSION {
+            return Err(anyhow::Error::msg("invalid_breach_filter__version").into());
+        }
+
+        let hash_count = header[5];
+        let bit_count = u64::from_be_bytes(header[6..14].try_into()?);
+        if hash_count == 0
+            || bit_count == 0
+            || file.metadata()?.len() != HEADER_SIZE + (bit_count + 7) / 8
+        {
+            return Err(anyhow::Error::msg("invalid_breach_filter__format").into());
+        }
+
+        Ok(Self {
+            file: Some(Arc::new(file)),
+            hash_count,
+            bit_count,
+        })
+    }
+
+    /// Whether `password` is probably in the corpus the filter was built from. Always `false`
+    /// without a filter.
+    pub async fn contains(&self, password: &str) -> OperationResult<bool> {
+        let file = match self.file.as_ref() {
+            Some(f) => f.clone(),
+            None => return Ok(false),
+        };
+
+        let hash = Sha1::digest(password.as_bytes());
+        let positions: Vec<u64> = positions(&hash, self.hash_count, self.bit_count).collect();
+
+        // The reads block, so they are kept off the threads that serve requests
+        let is_contained = tokio::task::spawn_blocking(move || -> std::io::Result<bool> {
+            let mut byte = [0_u8; 1];
+            for position in positions {
+                read_exact_at(&file, &mut byte, HEADER_SIZE + position / 8)?;
+                if byte[0] & (1 << (position % 8)) == 0 {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        })
+        .await
+        .map_err(anyhow::Error::from)??;
+
+        Ok(is_contained)
+    }
+
+    /// Builds a filter from a file in the HIBP `HASH:COUNT` format, sized so that about
+    /// `false_positive_rate` of the passwords outside of it are rejected anyway. Returns the
+    /// number of hashes added.
+    pub fn build(input: &Path, output: &Path, false_positive_rate: f64) -> OperationResult<u64> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(anyhow::Error::msg("invalid_breach_filter__false_positive_rate").into());
+        }
+
+        // The filter is sized for the number of hashes, so the input is read twice
+        let mut count = 0_u64;
+        for line in BufReader::new(File::open(input)?).lines() {
+            if !line?.trim().is_empty() {
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return Err(anyhow::Error::msg("invalid_breach_filter__empty").into());
+        }
+
+        let bit_count = (-(count as f64) * false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as u64;
+        let hash_count = ((bit_count as f64 / count as f64) * LN_2)
+            .round()
+            .clamp(1.0, MAX_HASH_COUNT) as u8;
+        let mut bits = vec![0_u8; ((bit_count + 7) / 8) as usize];
+
+        for (index, line) in BufReader::new(File::open(input)?).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let hash = line.split(':').next().and_then(|h| hex::decode(h).ok());
+            let hash = match hash {
+                Some(h) if h.len() == HASH_SIZE => h,
+                _ => {
+                    return Err(
+                        anyhow::format_err!("invalid_breach_filter__line: {}", index + 1).into(),
+                    )
+                }
+            };
+
+            for position in positions(&hash, hash_count, bit_count) {
+                bits[(position / 8) as usize] |= 1 << (position % 8);
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(output)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION, hash_count])?;
+        file.write_all(&bit_count.to_be_bytes())?;
+        file.write_all(&bits)?;
+        file.flush()?;
+
+        Ok(count)
+    }
+
+    fn disabled() -> Self {
+        Self {
+            file: None,
+            hash_count: 0,
+            bit_count: 0,
+        }
+    }
+}
+
+impl SingletonInit<BreachFilter> for BreachFilter {
+    fn init() -> BreachFilter {
+        let path = match Settings::get().security.password.breach_filter.as_ref() {
+            Some(p) => p,
+            None => return BreachFilter::disabled(),
+        };
+
+        match BreachFilter::open(Path::new(path.as_ref())) {
+            Ok(f) => f,
+            Err(e) => panic!(
+                "Invalid [security.password] breach filter {}: {:?}",
+                path, e
+            ),
+        }
+    }
+}
+
+fn positions(hash: &[u8], hash_count: u8, bit_count: u64) -> impl Iterator<Item = u64> {
+    let first = u64::from_be_bytes(hash[..8].try_into().unwrap());
+    // Odd, so that it is never zero and the positions don't all land on the first one
+    let second = u64::from_be_bytes(hash[8..16].try_into().unwrap()) | 1;
+
+    (0..hash_count as u64).map(move |i| first.wrapping_add(i.wrapping_mul(second)) % bit_count)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.read_exact_at(buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    let mut read = 0;
+    while read < buffer.len() {
+        match file.seek_read(&mut buffer[read..], offset + read as u64)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+
+    Ok(())
+}
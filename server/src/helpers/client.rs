@@ -0,0 +1,35 @@
+use std::borrow::Cow;
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+
+use crate::models::auth::ClientInfo;
+
+const MAX_VALUE_LENGTH: usize = 256;
+
+/// Collects what the client sent about itself: its address, and the `user-agent`,
+/// `x-client-version` and `x-device-name` headers.
+pub fn client_info<'a, T>(request: &Request<T>) -> ClientInfo<'a> {
+    let metadata = request.metadata();
+
+    ClientInfo {
+        address: request.remote_addr().map(|a| a.ip().to_string().into()),
+        user_agent: header(metadata, "user-agent"),
+        version: header(metadata, "x-client-version"),
+        device_name: header(metadata, "x-device-name"),
+    }
+}
+
+fn header<'a>(metadata: &MetadataMap, key: &str) -> Option<Cow<'a, str>> {
+    let value = metadata.get(key)?.to_str().ok()?.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    Some(
+        value
+            .chars()
+            .take(MAX_VALUE_LENGTH)
+            .collect::<String>()
+            .into(),
+    )
+}
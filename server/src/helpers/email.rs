@@ -0,0 +1,140 @@
+// Mail that proves a user can read what is sent to their address. Each one carries an email
+// token in the same `<selector>.<verifier>` form as session tokens, which the user enters in the
+// CLI as it is.
+
+use chrono::{Duration, Utc};
+use secret_vault_value::SecretValue;
+use shared::error::{EmptyResult, OperationResult};
+use singleton::unsync::Singleton as UnsyncSingleton;
+use tonic::Status;
+
+use crate::config::Settings;
+use crate::mail::Mail;
+use crate::models::auth::{EmailToken, EmailTokenPurpose, Token, User};
+use crate::repos;
+
+/// Whether the user may be mailed for `purpose` again, which is at most once every
+/// `mail.resend_interval_seconds`.
+pub async fn is_resend_allowed(
+    user: &User<'_>,
+    purpose: EmailTokenPurpose,
+) -> OperationResult<bool> {
+    let latest =
+        repos::email_token::read_latest_by_user_id(user.get_id().full_identifier(), purpose)
+            .await?;
+
+    Ok(latest.map_or(true, |t| {
+        t.added_on + Duration::seconds(Settings::get().mail.resend_interval_seconds) <= Utc::now()
+    }))
+}
+
+pub async fn send_verification(user: &mut User<'_>) -> EmptyResult {
+    let hours = Settings::get().mail.verification_lifetime_hours;
+    let token = issue_token(
+        user,
+        EmailTokenPurpose::Verification,
+        Duration::hours(hours),
+    )
+    .await?;
+
+    let body = format!(
+        "Hello {},\n\n\
+        To verify this address for your Pandorica account, run verify-email in pandorica-cli and \
+        enter this token:\n\n    {}\n\n\
+        It expires in {} hours. If you didn't register, you can ignore this mail.\n",
+        user.username,
+        token.as_sensitive_str(),
+        hours
+    );
+
+    send(user, "Verify your email address", body).await
+}
+
+/// Mails a password reset to the user with `username`, if there is one who can get it. Nothing
+/// tells the caller whether that was the case.
+pub async fn send_password_reset(username: &str) -> EmptyResult {
+    // Resetting the password would lose the files encrypted end to end, so those accounts are
+    // recovered with their recovery key instead. Unverified addresses may not be the user's.
+    let user = repos::user::read_by_username(username).await?;
+    let mut user = match user {
+        Some(u) if u.is_active && u.is_email_verified && u.keyring.is_none() => u,
+        _ => return Ok(()),
+    };
+    if !is_resend_allowed(&user, EmailTokenPurpose::PasswordReset).await? {
+        return Ok(());
+    }
+
+    let minutes = Settings::get().mail.password_reset_lifetime_minutes;
+    let token = issue_token(
+        &user,
+        EmailTokenPurpose::PasswordReset,
+        Duration::minutes(minutes),
+    )
+    .await?;
+
+    let body = format!(
+        "Hello {},\n\n\
+        Someone asked to reset the password of your Pandorica account. To choose a new one, run \
+        reset-password in pandorica-cli and enter this token:\n\n    {}\n\n\
+        It expires in {} minutes. If it wasn't you, you can ignore this mail, and your password \
+        stays as it is.\n",
+        user.username,
+        token.as_sensitive_str(),
+        minutes
+    );
+
+    send(&mut user, "Reset your password", body).await
+}
+
+/// Finds the email token for `purpose` that `value` is. One that doesn't exist or is for
+/// something else looks the same as a wrong one.
+pub async fn find_token<'a>(
+    value: &str,
+    purpose: EmailTokenPurpose,
+) -> Result<EmailToken<'a>, Status> {
+    let (selector, verifier) =
+        Token::parse(value).ok_or_else(|| Status::permission_denied("invalid_email_token"))?;
+
+    let email_token = repos::email_token::read_by_token_selector(selector).await?;
+    let email_token = match email_token {
+        Some(t) if t.purpose == purpose => t,
+        _ => return Err(Status::permission_denied("invalid_email_token")),
+    };
+
+    if !Token::verify(&verifier, &email_token.token_hash)? {
+        return Err(Status::permission_denied("invalid_email_token"));
+    }
+    if !email_token.verify() {
+        return Err(Status::permission_denied("email_token_expired"));
+    }
+
+    Ok(email_token)
+}
+
+/// Creates a token for `purpose`, which the ones sent before it stop working for.
+async fn issue_token(
+    user: &User<'_>,
+    purpose: EmailTokenPurpose,
+    lifetime: Duration,
+) -> OperationResult<SecretValue> {
+    let user_id = user.get_id().full_identifier();
+    repos::email_token::revoke_all_by_user_id(user_id, purpose).await?;
+
+    let email_token = EmailToken::new(user_id.to_string(), purpose, Utc::now() + lifetime)?;
+    let token = email_token.token.clone().unwrap();
+    repos::email_token::create(email_token).await?;
+
+    Ok(token)
+}
+
+async fn send(user: &mut User<'_>, subject: &str, body: String) -> EmptyResult {
+    let email = user
+        .email
+        .as_mut()
+        .ok_or_else(|| anyhow::Error::msg("email_not_set"))?;
+    email.decrypt().await?;
+
+    Mail::get()
+        .send(email.value().unwrap().as_sensitive_str(), subject, body)
+        .await
+}
@@ -0,0 +1,41 @@
+use crate::kms::KeyManagementSystem;
+use crate::models::auth::User;
+use crate::repos;
+use shared::error::OperationResult;
+use singleton::sync::Singleton;
+
+/// Creates and stores a new key encryption key, returning its ID.
+pub async fn create_kek() -> OperationResult<String> {
+    let kek = KeyManagementSystem::lock().await.generate_kek().await?;
+    let kek = repos::kek::create(kek).await?;
+
+    Ok(kek.get_id().as_string())
+}
+
+/// Returns the ID of the user's key encryption key. Users registered before per-user keys
+/// existed get one on first use. When concurrent requests race to create it, the first one to
+/// store its key wins and the others throw theirs away.
+pub async fn user_kek_id(user: &mut User<'_>) -> OperationResult<String> {
+    if let Some(kek_id) = user.kek_id.as_ref() {
+        return Ok(kek_id.to_string());
+    }
+
+    let kek_id = create_kek().await?;
+    if !repos::user::set_kek_id(user, &kek_id).await? {
+        repos::kek::delete(kek_id.split(':').last().unwrap()).await?;
+
+        let user_id = user.get_id().as_string();
+        let stored = repos::user::read(user_id.split(':').last().unwrap()).await?;
+        let kek_id = match stored.and_then(|u| u.kek_id) {
+            Some(k) => k.into_owned(),
+            None => return Err(anyhow::Error::msg("user_not_found").into()),
+        };
+        user.kek_id = Some(kek_id.clone().into());
+
+        return Ok(kek_id);
+    }
+
+    user.kek_id = Some(kek_id.clone().into());
+
+    Ok(kek_id)
+}
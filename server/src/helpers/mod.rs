@@ -1 +1,10 @@
+pub mod auth_guard;
 pub mod authorization;
+pub mod breach_filter;
+pub mod client;
+pub mod email;
+pub mod keys;
+pub mod opaque;
+pub mod recovery;
+pub mod throttle;
+pub mod totp;
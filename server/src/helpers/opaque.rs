@@ -0,0 +1,159 @@
+// OPAQUE (RFC 9807), which lets a client prove that it knows a password without ever sending
+// it. The server keeps an OPRF seed and a key pair, its `ServerSetup`, which every registration
+// depends on, so it is created once and kept encrypted like any other server secret.
+//
+// The cipher suite has to match the one in the CLI, or no client can log in.
+
+use chrono::Duration;
+use opaque_ke::ciphersuite::CipherSuite;
+use opaque_ke::{
+    CredentialFinalization, CredentialRequest, RegistrationRequest, RegistrationUpload,
+    Ristretto255, ServerLogin, ServerLoginStartParameters, ServerRegistration, ServerSetup,
+    TripleDh,
+};
+use rand::rngs::OsRng;
+use secret_vault_value::SecretValue;
+use shared::error::EmptyResult;
+use singleton::{sync::Singleton as SyncSingleton, OnceCell, Singleton, SingletonInit};
+use tonic::Status;
+
+use crate::kms::KeyManagementSystem;
+use crate::models::crypto::{EncryptedValue, ServerSecret};
+use crate::repos;
+
+/// Names both the server secret holding the `ServerSetup` and the key encryption key that
+/// encrypts it, along with the state of logins in progress.
+pub const SERVER_SETUP_ID: &str = "opaque_server_setup";
+
+pub struct DefaultCipherSuite;
+
+impl CipherSuite for DefaultCipherSuite {
+    type OprfCs = Ristretto255;
+    type KeGroup = Ristretto255;
+    type KeyExchange = TripleDh;
+    type Ksf = argon2::Argon2<'static>;
+}
+
+/// How long a client has between starting a login and finishing it.
+pub fn login_timeout() -> Duration {
+    Duration::minutes(2)
+}
+
+/// How long the proof from a finished re-authentication can stand in for the password.
+pub fn proof_timeout() -> Duration {
+    Duration::minutes(5)
+}
+
+#[derive(Default, Singleton)]
+#[singleton(use_once_cell = false)]
+pub struct Opaque {
+    server_setup: Option<ServerSetup<DefaultCipherSuite>>,
+}
+
+impl Opaque {
+    /// Loads the `ServerSetup`, creating it on the first start. Needs the KMS.
+    pub async fn init_opaque(&mut self) -> EmptyResult {
+        if let Some(mut secret) = repos::server_secret::read(SERVER_SETUP_ID).await? {
+            secret.value.decrypt().await?;
+            let server_setup =
+                ServerSetup::deserialize(secret.value.value().unwrap().ref_sensitive_value())
+                    .map_err(|_| anyhow::Error::msg("invalid_opaque_server_setup"))?;
+            self.server_setup = Some(server_setup);
+
+            return Ok(());
+        }
+
+        if repos::kek::read(SERVER_SETUP_ID).await?.is_none() {
+            let kek = KeyManagementSystem::lock().await.generate_kek().await?;
+            repos::kek::create_with_id(SERVER_SETUP_ID, kek).await?;
+        }
+
+        let server_setup = ServerSetup::<DefaultCipherSuite>::new(&mut OsRng);
+        let value = EncryptedValue::new(
+            SecretValue::from(server_setup.serialize().to_vec()),
+            SERVER_SETUP_ID,
+        )
+        .await?;
+        repos::server_secret::create_with_id(SERVER_SETUP_ID, ServerSecret::new(value)).await?;
+        self.server_setup = Some(server_setup);
+
+        Ok(())
+    }
+
+    /// Answers the first message of a registration for `username`.
+    pub fn start_registration(&self, request: &[u8], username: &str) -> Result<Vec<u8>, Status> {
+        let request = RegistrationRequest::deserialize(request)
+            .map_err(|_| Status::invalid_argument("invalid_opaque_message"))?;
+        let result = ServerRegistration::start(self.server_setup(), request, username.as_bytes())
+            .map_err(|_| Status::invalid_argument("invalid_opaque_message"))?;
+
+        Ok(result.message.serialize().to_vec())
+    }
+
+    /// Answers the first message of a login for `username`. Without a registration, the
+    /// response looks the same but no password opens it. Returns the response and the state
+    /// `finish_login` needs.
+    pub fn start_login(
+        &self,
+        registration: Option<&[u8]>,
+        request: &[u8],
+        username: &str,
+    ) -> Result<(Vec<u8>, SecretValue), Status> {
+        let registration = match registration {
+            Some(r) => Some(
+                ServerRegistration::<DefaultCipherSuite>::deserialize(r)
+                    .map_err(|_| Status::internal("invalid_opaque_registration"))?,
+            ),
+            None => None,
+        };
+        let request = CredentialRequest::deserialize(request)
+            .map_err(|_| Status::invalid_argument("invalid_opaque_message"))?;
+
+        let result = ServerLogin::start(
+            &mut OsRng,
+            self.server_setup(),
+            registration,
+            request,
+            username.as_bytes(),
+            ServerLoginStartParameters::default(),
+        )
+        .map_err(|_| Status::invalid_argument("invalid_opaque_message"))?;
+
+        Ok((
+            result.message.serialize().to_vec(),
+            SecretValue::from(result.state.serialize().to_vec()),
+        ))
+    }
+
+    fn server_setup(&self) -> &ServerSetup<DefaultCipherSuite> {
+        self.server_setup
+            .as_ref()
+            .expect("OPAQUE is used before it is initialized")
+    }
+}
+
+impl SingletonInit<Opaque> for Opaque {
+    fn init() -> Opaque {
+        Opaque::default()
+    }
+}
+
+/// Turns the last message of a registration into what is stored for the user.
+pub fn finish_registration(upload: &[u8]) -> Result<Vec<u8>, Status> {
+    let upload = RegistrationUpload::<DefaultCipherSuite>::deserialize(upload)
+        .map_err(|_| Status::invalid_argument("invalid_opaque_message"))?;
+
+    Ok(ServerRegistration::finish(upload).serialize().to_vec())
+}
+
+/// Whether the last message of a login proves the password. `state` comes from `start_login`.
+pub fn finish_login(state: &SecretValue, finalization: &[u8]) -> Result<bool, Status> {
+    let state = ServerLogin::<DefaultCipherSuite>::deserialize(state.ref_sensitive_value())
+        .map_err(|_| Status::internal("invalid_opaque_login"))?;
+    let finalization = match CredentialFinalization::deserialize(finalization) {
+        Ok(f) => f,
+        Err(_) => return Ok(false),
+    };
+
+    Ok(state.finish(finalization).is_ok())
+}
@@ -0,0 +1,78 @@
+// Account recovery for users who lost their password. The client shows the user a recovery key
+// once, and seals a copy of their keyring with it. Whoever can sign with the Ed25519 key derived
+// from it may set a new password, and nobody else, admins included, since the server only holds
+// the public half.
+//
+// The client signs what it is about to set, so that a signature is only good for that:
+//
+// | context | recovery ID | registration upload | new verifying key |
+//
+// Each part but the context is prefixed with its length as a u32 BE.
+
+use chrono::Duration;
+use ed25519_dalek::{Signature, VerifyingKey};
+use protobuf::pandorica_common;
+use shared::error::EmptyResult;
+use tonic::Status;
+
+use crate::models::crypto::RecoveryKey;
+use crate::validators;
+
+const CONTEXT: &[u8] = b"pandorica account recovery v1";
+
+/// How long a client has between starting a recovery and finishing it.
+pub fn recovery_timeout() -> Duration {
+    Duration::minutes(5)
+}
+
+/// Checks a recovery key sent along with a keyring. Without one, there is nothing to recover.
+pub fn parse_recovery_key<'a>(
+    recovery_key: Option<pandorica_common::RecoveryKey>,
+    has_keyring: bool,
+) -> Result<Option<RecoveryKey<'a>>, Status> {
+    let recovery_key = match recovery_key {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    if !has_keyring {
+        return Err(Status::invalid_argument(
+            "invalid_recovery_key__keyring_missing",
+        ));
+    }
+    EmptyResult::from(validators::recovery_key(&recovery_key))?;
+
+    Ok(Some(RecoveryKey::from(recovery_key)))
+}
+
+/// Whether `signature` was made with the recovery key that `recovery_key` belongs to.
+pub fn verify_signature(
+    recovery_key: &RecoveryKey<'_>,
+    recovery_id: &str,
+    registration_upload: &[u8],
+    new_verifying_key: &[u8],
+    signature: &[u8],
+) -> bool {
+    let verifying_key = match <[u8; 32]>::try_from(recovery_key.verifying_key.as_ref())
+        .ok()
+        .and_then(|k| VerifyingKey::from_bytes(&k).ok())
+    {
+        Some(k) => k,
+        None => return false,
+    };
+    let signature = match Signature::from_slice(signature) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    let mut message = CONTEXT.to_vec();
+    for part in [
+        recovery_id.as_bytes(),
+        registration_upload,
+        new_verifying_key,
+    ] {
+        message.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        message.extend_from_slice(part);
+    }
+
+    verifying_key.verify_strict(&message, &signature).is_ok()
+}
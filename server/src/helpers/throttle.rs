@@ -0,0 +1,76 @@
+use chrono::{Duration, Utc};
+use singleton::unsync::Singleton as UnsyncSingleton;
+use std::net::IpAddr;
+use tonic::Status;
+
+use crate::config::Settings;
+use crate::repos;
+
+const CLIENT_KEY_PREFIX: &str = "client:";
+
+pub fn username_key(username: &str) -> String {
+    format!("username:{}", username)
+}
+
+pub fn client_key(address: IpAddr) -> String {
+    format!("{}{}", CLIENT_KEY_PREFIX, address)
+}
+
+/// The keys a login attempt counts against: its username and, when known, its client address.
+pub fn keys(username: &str, address: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![username_key(username)];
+    if let Some(address) = address {
+        keys.push(client_key(address));
+    }
+
+    keys
+}
+
+/// Counts an attempt against each of `keys` as a failure before it can be judged, and fails if
+/// any of them is backing off or locked out. An attempt that succeeds is taken back with
+/// `forgive`.
+///
+/// The failure is counted and the next wait set in one statement, so that a burst of concurrent
+/// attempts can't all get in before the first of them is locked out.
+pub async fn attempt(keys: &[String]) -> Result<(), Status> {
+    let settings = &Settings::get().security.login;
+    let window_start = Utc::now() - Duration::minutes(settings.failure_window_minutes);
+
+    for key in keys {
+        let lockout_after_failures = if key.starts_with(CLIENT_KEY_PREFIX) {
+            settings.client_lockout_after_failures
+        } else {
+            settings.lockout_after_failures
+        };
+
+        let is_counted = repos::login_throttle::record_attempt(
+            key,
+            window_start,
+            lockout_after_failures,
+            settings,
+        )
+        .await?;
+        if !is_counted {
+            return Err(Status::resource_exhausted("too_many_attempts"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Takes back a failure that `attempt` counted against each of `keys` before the attempt
+/// could be judged. A backoff it caused is left to run out.
+pub async fn forgive(keys: &[String]) -> Result<(), Status> {
+    for key in keys {
+        repos::login_throttle::forgive(key).await?;
+    }
+
+    Ok(())
+}
+
+/// Forgets the failures counted against `key`.
+pub async fn reset(key: &str) -> Result<(), Status> {
+    repos::login_throttle::delete_by_key(key).await?;
+
+    Ok(())
+}
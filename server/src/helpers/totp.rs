@@ -0,0 +1,207 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use reqwest::Url;
+use secret_vault_value::SecretValue;
+use sha1::Sha1;
+use shared::error::OperationResult;
+use singleton::unsync::Singleton as UnsyncSingleton;
+use subtle::ConstantTimeEq;
+
+use crate::config::Settings;
+use crate::kms::KeyManagementSystem;
+use crate::models::auth::User;
+use crate::repos;
+
+// RFC 6238 with the parameters every authenticator app supports
+const SECRET_SIZE: usize = 20;
+const STEP_SECONDS: i64 = 30;
+const DIGITS: usize = 6;
+/// Codes from this many steps before or after the current one are accepted to allow for clock
+/// drift.
+const ALLOWED_SKEW: i64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_SIZE: usize = 10;
+
+type HmacSha1 = Hmac<Sha1>;
+
+pub fn generate_secret() -> SecretValue {
+    let mut secret = vec![0_u8; SECRET_SIZE];
+    OsRng.fill_bytes(&mut secret);
+
+    SecretValue::from(secret)
+}
+
+/// The secret in the unpadded base32 form authenticator apps expect.
+pub fn encode_secret(secret: &SecretValue) -> String {
+    base32::encode(
+        base32::Alphabet::RFC4648 { padding: false },
+        secret.ref_sensitive_value(),
+    )
+}
+
+/// The `otpauth://` URI that authenticator apps enroll from, usually shown as a QR code.
+pub fn provisioning_uri(username: &str, secret: &SecretValue) -> OperationResult<String> {
+    let issuer = Settings::get().totp.issuer.as_ref();
+
+    let mut uri = Url::parse("otpauth://totp/")?;
+    uri.path_segments_mut()
+        .map_err(|_| anyhow::Error::msg("invalid_totp__uri"))?
+        .push(&format!("{}:{}", issuer, username));
+    uri.query_pairs_mut()
+        .append_pair("secret", &encode_secret(secret))
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP_SECONDS.to_string());
+
+    Ok(uri.to_string())
+}
+
+/// Returns the time step `code` was generated for, as long as it is valid and newer than
+/// `last_step`. Refusing older steps keeps a code from being replayed.
+pub fn verify(secret: &SecretValue, code: &str, last_step: i64) -> OperationResult<Option<i64>> {
+    let code = code.trim();
+    if code.len() != DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+
+    let current_step = Utc::now().timestamp() / STEP_SECONDS;
+    for step in current_step - ALLOWED_SKEW..=current_step + ALLOWED_SKEW {
+        if step <= last_step {
+            continue;
+        }
+
+        let expected = generate_code(secret, step)?;
+        if bool::from(expected.as_bytes().ct_eq(code.as_bytes())) {
+            return Ok(Some(step));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Generates a set of single-use recovery codes, returning them along with the hashes to store.
+pub async fn generate_recovery_codes() -> OperationResult<(Vec<String>, Vec<Vec<u8>>)> {
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    let mut hashes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let mut code = vec![0_u8; RECOVERY_CODE_SIZE];
+        OsRng.fill_bytes(&mut code);
+        let code = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &code);
+
+        hashes.push(KeyManagementSystem::hash_token(code.as_bytes())?);
+        codes.push(
+            code.as_bytes()
+                .chunks(4)
+                .map(|c| String::from_utf8_lossy(c).to_string())
+                .collect::<Vec<_>>()
+                .join("-"),
+        );
+    }
+
+    Ok((codes, hashes))
+}
+
+/// Checks `code` against the user's TOTP secret and, failing that, their recovery codes. A
+/// matching code is used up in the database right away, by a conditional update that only one
+/// of several concurrent requests with the same code can win.
+pub async fn verify_user_code(user: &mut User<'_>, code: &str) -> OperationResult<bool> {
+    let secret = match user.totp_secret.as_mut() {
+        Some(s) => s,
+        None => return Ok(false),
+    };
+    secret.decrypt().await?;
+
+    if let Some(step) = verify(secret.value().unwrap(), code, user.totp_last_step)? {
+        if !repos::user::use_totp_step(user, step).await? {
+            return Ok(false);
+        }
+
+        user.totp_last_step = step;
+        return Ok(true);
+    }
+
+    let code = code.trim().replace('-', "").to_uppercase();
+    let mut index = None;
+    for (i, hash) in user.recovery_codes.iter().enumerate() {
+        if KeyManagementSystem::verify_token(code.as_bytes(), hash)? {
+            index = Some(i);
+            break;
+        }
+    }
+
+    match index {
+        Some(i) => {
+            if !repos::user::use_recovery_code(user, &user.recovery_codes[i]).await? {
+                return Ok(false);
+            }
+
+            user.recovery_codes.remove(i);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn generate_code(secret: &SecretValue, step: i64) -> OperationResult<String> {
+    let mut mac = HmacSha1::new_from_slice(secret.ref_sensitive_value())
+        .map_err(|_| anyhow::Error::msg("invalid_totp__secret"))?;
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation from RFC 4226
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes(hash[offset..offset + 4].try_into()?) & 0x7fff_ffff;
+
+    Ok(format!(
+        "{:0width$}",
+        value % 10_u32.pow(DIGITS as u32),
+        width = DIGITS
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 vectors from RFC 6238 Appendix B. Its codes have 8 digits, of which these are
+    /// the last 6.
+    #[test]
+    fn generate_code_matches_rfc_6238() {
+        let secret = SecretValue::from(b"12345678901234567890".to_vec());
+        let vectors: [(i64, &str); 6] = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+
+        for (time, code) in vectors {
+            assert_eq!(generate_code(&secret, time / STEP_SECONDS).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn verify_refuses_used_steps() {
+        let secret = generate_secret();
+        let step = Utc::now().timestamp() / STEP_SECONDS;
+        let code = generate_code(&secret, step).unwrap();
+
+        assert_eq!(verify(&secret, &code, step - 2).unwrap(), Some(step));
+        assert_eq!(verify(&secret, &code, step).unwrap(), None);
+    }
+
+    #[test]
+    fn verify_refuses_malformed_codes() {
+        let secret = generate_secret();
+
+        assert_eq!(verify(&secret, "12345", 0).unwrap(), None);
+        assert_eq!(verify(&secret, "1234567", 0).unwrap(), None);
+        assert_eq!(verify(&secret, "12a456", 0).unwrap(), None);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::config::AwsKmsSettings;
+use crate::hsm::HsmProvider;
+use async_trait::async_trait;
+use aws_sdk_kms::config::Region;
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::Client;
+use secret_vault_value::SecretValue;
+use shared::error::OperationResult;
+
+pub struct AwsKmsHsmProvider {
+    client: Client,
+    key_id: String,
+}
+
+impl AwsKmsHsmProvider {
+    pub async fn new(settings: &AwsKmsSettings) -> Self {
+        let mut loader = aws_config::from_env().region(Region::new(settings.region.to_string()));
+        if let Some(endpoint) = settings.endpoint.as_ref() {
+            loader = loader.endpoint_url(endpoint.as_ref());
+        }
+
+        Self {
+            client: Client::new(&loader.load().await),
+            key_id: settings.key_id.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl HsmProvider for AwsKmsHsmProvider {
+    async fn generate_random_bytes(&self, size: u32) -> OperationResult<Vec<u8>> {
+        let output = self
+            .client
+            .generate_random()
+            .number_of_bytes(size as i32)
+            .send()
+            .await?;
+
+        match output.plaintext() {
+            Some(p) => Ok(p.as_ref().to_vec()),
+            None => Err(anyhow::Error::msg("aws_kms__missing_random_bytes").into()),
+        }
+    }
+
+    async fn encrypt_envelope(&self, plaintext: SecretValue) -> OperationResult<Vec<u8>> {
+        let output = self
+            .client
+            .encrypt()
+            .key_id(&self.key_id)
+            .plaintext(Blob::new(plaintext.ref_sensitive_value().as_slice()))
+            .send()
+            .await?;
+
+        match output.ciphertext_blob() {
+            Some(c) => Ok(c.as_ref().to_vec()),
+            None => Err(anyhow::Error::msg("aws_kms__missing_ciphertext").into()),
+        }
+    }
+
+    async fn decrypt_envelope(&self, ciphertext: &[u8]) -> OperationResult<SecretValue> {
+        let output = self
+            .client
+            .decrypt()
+            .key_id(&self.key_id)
+            .ciphertext_blob(Blob::new(ciphertext))
+            .send()
+            .await?;
+
+        match output.plaintext() {
+            Some(p) => Ok(SecretValue::from(p.as_ref().to_vec())),
+            None => Err(anyhow::Error::msg("aws_kms__missing_plaintext").into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A provider for a new key in the LocalStack service from docker-compose.yml. LocalStack
+    /// takes any credentials, but the SDK still wants some in the environment.
+    async fn localstack_provider() -> AwsKmsHsmProvider {
+        let settings = AwsKmsSettings {
+            key_id: "".into(),
+            region: "us-east-1".into(),
+            endpoint: Some(
+                std::env::var("PANDORICA_TEST_AWS_ENDPOINT")
+                    .unwrap_or_else(|_| "http://127.0.0.1:4566".into())
+                    .into(),
+            ),
+        };
+        let mut provider = AwsKmsHsmProvider::new(&settings).await;
+
+        let output = provider.client.create_key().send().await.unwrap();
+        provider.key_id = output
+            .key_metadata()
+            .and_then(|m| m.key_id())
+            .unwrap()
+            .to_string();
+
+        provider
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the LocalStack service from docker-compose.yml"]
+    async fn wraps_and_unwraps() {
+        let provider = localstack_provider().await;
+        let key = provider.generate_random_bytes(32).await.unwrap();
+        assert_eq!(key.len(), 32);
+
+        let wrapped = provider
+            .encrypt_envelope(SecretValue::from(key.clone()))
+            .await
+            .unwrap();
+        assert_ne!(wrapped, key);
+
+        let unwrapped = provider.decrypt_envelope(&wrapped).await.unwrap();
+        assert_eq!(unwrapped.ref_sensitive_value(), &key);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the LocalStack service from docker-compose.yml"]
+    async fn refuses_to_unwrap_under_another_key() {
+        let provider = localstack_provider().await;
+        let other = localstack_provider().await;
+
+        let wrapped = other
+            .encrypt_envelope(SecretValue::from(vec![7_u8; 32]))
+            .await
+            .unwrap();
+
+        assert!(provider.decrypt_envelope(&wrapped).await.is_err());
+    }
+}
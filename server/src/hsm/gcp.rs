@@ -0,0 +1,52 @@
+use crate::hsm::HsmProvider;
+use async_trait::async_trait;
+use crypto::hsm::{HsmProvider as CloudHsmProvider, HsmSettings as CloudHsmSettings};
+use secret_vault_value::SecretValue;
+use shared::error::OperationResult;
+use singleton::sync::Singleton;
+
+pub struct GcpHsmProvider {
+    key: String,
+}
+
+impl GcpHsmProvider {
+    pub async fn new(settings: &CloudHsmSettings) -> OperationResult<Self> {
+        let gcp = match settings.gcp.as_ref() {
+            Some(g) => g,
+            None => panic!("Missing [hsm.gcp] settings for the gcp HSM provider"),
+        };
+
+        CloudHsmProvider::lock()
+            .await
+            .init_provider(settings)
+            .await?;
+
+        Ok(Self {
+            key: gcp.key.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl HsmProvider for GcpHsmProvider {
+    async fn generate_random_bytes(&self, size: u32) -> OperationResult<Vec<u8>> {
+        CloudHsmProvider::lock()
+            .await
+            .generate_random_bytes(size)
+            .await
+    }
+
+    async fn encrypt_envelope(&self, plaintext: SecretValue) -> OperationResult<Vec<u8>> {
+        CloudHsmProvider::lock()
+            .await
+            .encrypt_envelope(plaintext, &self.key)
+            .await
+    }
+
+    async fn decrypt_envelope(&self, ciphertext: &[u8]) -> OperationResult<SecretValue> {
+        CloudHsmProvider::lock()
+            .await
+            .decrypt_envelope(ciphertext, &self.key)
+            .await
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::HsmSettings;
+use crate::hsm::aws::AwsKmsHsmProvider;
+use crate::hsm::gcp::GcpHsmProvider;
+use crate::hsm::pkcs11::Pkcs11HsmProvider;
+use crate::hsm::software::SoftwareHsmProvider;
+use crate::hsm::vault::VaultTransitHsmProvider;
+use async_trait::async_trait;
+use secret_vault_value::SecretValue;
+use shared::error::{EmptyResult, OperationResult};
+use singleton::{OnceCell, Singleton, SingletonInit};
+
+mod aws;
+mod gcp;
+mod pkcs11;
+mod software;
+mod vault;
+
+#[async_trait]
+pub trait HsmProvider: Send + Sync {
+    async fn generate_random_bytes(&self, size: u32) -> OperationResult<Vec<u8>>;
+
+    async fn encrypt_envelope(&self, plaintext: SecretValue) -> OperationResult<Vec<u8>>;
+
+    async fn decrypt_envelope(&self, ciphertext: &[u8]) -> OperationResult<SecretValue>;
+}
+
+#[derive(Default, Singleton)]
+#[singleton(use_once_cell = false)]
+pub struct Hsm {
+    provider: Option<Box<dyn HsmProvider>>,
+}
+
+impl Hsm {
+    pub async fn init_hsm(&mut self, settings: &HsmSettings) -> EmptyResult {
+        let provider: Box<dyn HsmProvider> = match settings.provider.as_ref() {
+            "gcp" => Box::new(GcpHsmProvider::new(&settings.cloud).await?),
+            "software" => {
+                let software = match settings.software.as_ref() {
+                    Some(s) => s,
+                    None => panic!("Missing [hsm.software] settings for the software HSM provider"),
+                };
+
+                Box::new(SoftwareHsmProvider::new(software)?)
+            }
+            "aws" => {
+                let aws = match settings.aws.as_ref() {
+                    Some(a) => a,
+                    None => panic!("Missing [hsm.aws] settings for the aws HSM provider"),
+                };
+
+                Box::new(AwsKmsHsmProvider::new(aws).await)
+            }
+            "vault" => {
+                let vault = match settings.vault.as_ref() {
+                    Some(v) => v,
+                    None => panic!("Missing [hsm.vault] settings for the vault HSM provider"),
+                };
+
+                Box::new(VaultTransitHsmProvider::new(vault)?)
+            }
+            "pkcs11" => {
+                let pkcs11 = match settings.pkcs11.as_ref() {
+                    Some(p) => p,
+                    None => panic!("Missing [hsm.pkcs11] settings for the pkcs11 HSM provider"),
+                };
+
+                Box::new(Pkcs11HsmProvider::new(pkcs11)?)
+            }
+            val => {
+                panic!("Unknown HSM provider: {}", val);
+            }
+        };
+
+        self.provider = Some(provider);
+
+        Ok(())
+    }
+
+    pub async fn generate_random_bytes(&self, size: u32) -> OperationResult<Vec<u8>> {
+        self.provider()?.generate_random_bytes(size).await
+    }
+
+    pub async fn encrypt_envelope(&self, plaintext: SecretValue) -> OperationResult<Vec<u8>> {
+        self.provider()?.encrypt_envelope(plaintext).await
+    }
+
+    pub async fn decrypt_envelope(&self, ciphertext: &[u8]) -> OperationResult<SecretValue> {
+        self.provider()?.decrypt_envelope(ciphertext).await
+    }
+
+    fn provider(&self) -> OperationResult<&dyn HsmProvider> {
+        match self.provider.as_ref() {
+            Some(p) => Ok(p.as_ref()),
+            None => Err(anyhow::Error::msg("hsm_not_initialized").into()),
+        }
+    }
+}
+
+impl SingletonInit<Hsm> for Hsm {
+    fn init() -> Hsm {
+        Hsm::default()
+    }
+}
@@ -0,0 +1,177 @@
+use crate::config::Pkcs11Settings;
+use crate::hsm::HsmProvider;
+use async_trait::async_trait;
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::aead::GcmParams;
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, KeyType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use secret_vault_value::SecretValue;
+use shared::error::OperationResult;
+use std::sync::{Mutex, MutexGuard};
+
+const KEY_SIZE: u64 = 32;
+const IV_SIZE: usize = 12;
+const TAG_BITS: u64 = 128;
+
+pub struct Pkcs11HsmProvider {
+    session: Mutex<Session>,
+    key: ObjectHandle,
+}
+
+impl Pkcs11HsmProvider {
+    pub fn new(settings: &Pkcs11Settings) -> OperationResult<Self> {
+        let pin = std::env::var(settings.pin_env.as_ref()).map_err(|_| {
+            anyhow::format_err!(
+                "The {} environment variable must hold the PKCS#11 user PIN",
+                settings.pin_env
+            )
+        })?;
+
+        let pkcs11 = Pkcs11::new(settings.module_path.as_ref())?;
+        pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+        let slot = pkcs11
+            .get_slots_with_token()?
+            .into_iter()
+            .find(|s| s.id() == settings.slot)
+            .ok_or_else(|| anyhow::format_err!("PKCS#11 slot {} not found", settings.slot))?;
+
+        let session = pkcs11.open_rw_session(slot)?;
+        session.login(UserType::User, Some(pin.as_str()))?;
+
+        let key = Self::find_or_generate_key(&session, settings.key_label.as_ref())?;
+
+        Ok(Self {
+            session: Mutex::new(session),
+            key,
+        })
+    }
+
+    fn find_or_generate_key(session: &Session, label: &str) -> OperationResult<ObjectHandle> {
+        let template = vec![
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::KeyType(KeyType::AES),
+            Attribute::Label(label.as_bytes().to_vec()),
+        ];
+
+        let keys = session.find_objects(&template)?;
+        match keys.len() {
+            0 => {}
+            1 => return Ok(keys[0]),
+            _ => {
+                return Err(anyhow::format_err!(
+                    "Found more than one PKCS#11 key labelled {}",
+                    label
+                )
+                .into())
+            }
+        }
+
+        let template = vec![
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Encrypt(true),
+            Attribute::Decrypt(true),
+            Attribute::ValueLen(KEY_SIZE.into()),
+            Attribute::Label(label.as_bytes().to_vec()),
+        ];
+        let key = session.generate_key(&Mechanism::AesKeyGen, &template)?;
+
+        tracing::warn!("Generated a new PKCS#11 AES key labelled {}", label);
+
+        Ok(key)
+    }
+
+    fn session(&self) -> OperationResult<MutexGuard<'_, Session>> {
+        self.session
+            .lock()
+            .map_err(|_| anyhow::Error::msg("pkcs11_session_poisoned").into())
+    }
+}
+
+#[async_trait]
+impl HsmProvider for Pkcs11HsmProvider {
+    async fn generate_random_bytes(&self, size: u32) -> OperationResult<Vec<u8>> {
+        Ok(self.session()?.generate_random_vec(size)?)
+    }
+
+    async fn encrypt_envelope(&self, plaintext: SecretValue) -> OperationResult<Vec<u8>> {
+        let session = self.session()?;
+
+        let iv = session.generate_random_vec(IV_SIZE as u32)?;
+        let mechanism = Mechanism::AesGcm(GcmParams::new(&iv, &[], TAG_BITS.into()));
+        let ciphertext = session.encrypt(&mechanism, self.key, plaintext.ref_sensitive_value())?;
+
+        Ok([iv, ciphertext].concat())
+    }
+
+    async fn decrypt_envelope(&self, ciphertext: &[u8]) -> OperationResult<SecretValue> {
+        if ciphertext.len() <= IV_SIZE {
+            return Err(anyhow::Error::msg("invalid_envelope").into());
+        }
+
+        let (iv, ciphertext) = ciphertext.split_at(IV_SIZE);
+        let mechanism = Mechanism::AesGcm(GcmParams::new(iv, &[], TAG_BITS.into()));
+        let plaintext = self.session()?.decrypt(&mechanism, self.key, ciphertext)?;
+
+        Ok(SecretValue::from(plaintext))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_LABEL: &str = "pandorica-test";
+
+    /// A SoftHSM2 token, set up as in the Integration workflow. A module can only be initialized
+    /// once per process, so everything is checked with the one provider.
+    fn softhsm_settings() -> Pkcs11Settings {
+        Pkcs11Settings {
+            module_path: std::env::var("PANDORICA_TEST_PKCS11_MODULE")
+                .unwrap_or_else(|_| "/usr/lib/softhsm/libsofthsm2.so".into())
+                .into(),
+            slot: std::env::var("PANDORICA_TEST_PKCS11_SLOT")
+                .expect("PANDORICA_TEST_PKCS11_SLOT must hold the slot of the token")
+                .parse()
+                .unwrap(),
+            key_label: KEY_LABEL.into(),
+            pin_env: "PANDORICA_TEST_PKCS11_PIN".into(),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a SoftHSM2 token, see the Integration workflow"]
+    async fn wraps_and_unwraps() {
+        let provider = Pkcs11HsmProvider::new(&softhsm_settings()).unwrap();
+        let key = Pkcs11HsmProvider::find_or_generate_key(&provider.session().unwrap(), KEY_LABEL)
+            .unwrap();
+        assert_eq!(key, provider.key);
+
+        let plaintext = provider.generate_random_bytes(32).await.unwrap();
+        assert_eq!(plaintext.len(), 32);
+
+        let wrapped = provider
+            .encrypt_envelope(SecretValue::from(plaintext.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            wrapped.len(),
+            IV_SIZE + plaintext.len() + TAG_BITS as usize / 8
+        );
+
+        let unwrapped = provider.decrypt_envelope(&wrapped).await.unwrap();
+        assert_eq!(unwrapped.ref_sensitive_value(), &plaintext);
+
+        let mut tampered = wrapped.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(provider.decrypt_envelope(&tampered).await.is_err());
+        assert!(provider
+            .decrypt_envelope(&wrapped[..IV_SIZE])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::SoftwareHsmSettings;
+use crate::hsm::HsmProvider;
+use async_trait::async_trait;
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use scrypt::Params;
+use secret_vault_value::SecretValue;
+use shared::error::OperationResult;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+// Sealed key-encryption key, version 1
+//
+// | magic "PDKK" | version: u8 | scrypt log_n: u8 | scrypt r: u32 BE | scrypt p: u32 BE |
+// | salt: 16 bytes | nonce: 24 bytes | encrypted KEK |
+const KEK_MAGIC: &[u8; 4] = b"PDKK";
+const KEK_VERSION: u8 = 1;
+const KEK_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+const SEALED_HEADER_SIZE: usize = 14 + SALT_SIZE + NONCE_SIZE;
+
+const SCRYPT_LOG_N: u8 = 17;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+pub struct SoftwareHsmProvider {
+    kek: SecretValue,
+}
+
+impl SoftwareHsmProvider {
+    pub fn new(settings: &SoftwareHsmSettings) -> OperationResult<Self> {
+        let passphrase = std::env::var(settings.passphrase_env.as_ref()).map_err(|_| {
+            anyhow::format_err!(
+                "The {} environment variable must hold the software HSM passphrase",
+                settings.passphrase_env
+            )
+        })?;
+        let passphrase = SecretValue::from(passphrase);
+
+        let sealed_kek = match (settings.key_env.as_ref(), settings.key_file.as_ref()) {
+            (Some(key_env), _) => hex::decode(std::env::var(key_env.as_ref())?)?,
+            (None, Some(key_file)) => Self::read_or_create_key_file(key_file, &passphrase)?,
+            (None, None) => {
+                return Err(anyhow::Error::msg("Either key_file or key_env is required").into())
+            }
+        };
+
+        Ok(Self {
+            kek: Self::unseal(&sealed_kek, &passphrase)?,
+        })
+    }
+
+    fn read_or_create_key_file(path: &str, passphrase: &SecretValue) -> OperationResult<Vec<u8>> {
+        if Path::new(path).exists() {
+            return Ok(std::fs::read(path)?);
+        }
+
+        let mut kek = vec![0_u8; KEK_SIZE];
+        OsRng.fill_bytes(&mut kek);
+        let sealed_kek = Self::seal(&SecretValue::from(kek), passphrase)?;
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut key_file = options.open(path)?;
+        key_file.write_all(&sealed_kek)?;
+        key_file.flush()?;
+
+        tracing::warn!(
+            "Generated a new software HSM key-encryption key in {}",
+            path
+        );
+
+        Ok(sealed_kek)
+    }
+
+    fn seal(kek: &SecretValue, passphrase: &SecretValue) -> OperationResult<Vec<u8>> {
+        let mut salt = vec![0_u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = vec![0_u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let params = Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+        let wrapping_key = Self::derive_key(passphrase, &salt, &params)?;
+        let encrypted_kek = ChaCha20Poly1305::encrypt(kek, &wrapping_key, &nonce)?;
+
+        let mut sealed = Vec::with_capacity(SEALED_HEADER_SIZE + encrypted_kek.len());
+        sealed.extend_from_slice(KEK_MAGIC);
+        sealed.push(KEK_VERSION);
+        sealed.push(params.log_n());
+        sealed.extend_from_slice(&params.r().to_be_bytes());
+        sealed.extend_from_slice(&params.p().to_be_bytes());
+        sealed.extend_from_slice(&salt);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&encrypted_kek);
+
+        Ok(sealed)
+    }
+
+    fn unseal(sealed: &[u8], passphrase: &SecretValue) -> OperationResult<SecretValue> {
+        if sealed.len() <= SEALED_HEADER_SIZE || &sealed[..4] != KEK_MAGIC {
+            return Err(anyhow::Error::msg("invalid_kek__format").into());
+        }
+        if sealed[4] != KEK_VERSION {
+            return Err(anyhow::Error::msg("invalid_kek__version").into());
+        }
+
+        let params = Params::new(
+            sealed[5],
+            u32::from_be_bytes(sealed[6..10].try_into()?),
+            u32::from_be_bytes(sealed[10..14].try_into()?),
+        )?;
+        let salt = &sealed[14..14 + SALT_SIZE];
+        let nonce = &sealed[14 + SALT_SIZE..SEALED_HEADER_SIZE];
+
+        let wrapping_key = Self::derive_key(passphrase, salt, &params)?;
+
+        ChaCha20Poly1305::decrypt(&sealed[SEALED_HEADER_SIZE..], &wrapping_key, nonce)
+            .map_err(|_| anyhow::Error::msg("invalid_kek__passphrase").into())
+    }
+
+    fn derive_key(
+        passphrase: &SecretValue,
+        salt: &[u8],
+        params: &Params,
+    ) -> OperationResult<SecretValue> {
+        let mut key = vec![0_u8; KEK_SIZE];
+        scrypt::scrypt(passphrase.ref_sensitive_value(), salt, params, &mut key)?;
+
+        Ok(SecretValue::from(key))
+    }
+}
+
+#[async_trait]
+impl HsmProvider for SoftwareHsmProvider {
+    async fn generate_random_bytes(&self, size: u32) -> OperationResult<Vec<u8>> {
+        let mut bytes = vec![0_u8; size as usize];
+        OsRng.fill_bytes(&mut bytes);
+
+        Ok(bytes)
+    }
+
+    async fn encrypt_envelope(&self, plaintext: SecretValue) -> OperationResult<Vec<u8>> {
+        let mut nonce = vec![0_u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = ChaCha20Poly1305::encrypt(&plaintext, &self.kek, &nonce)?;
+
+        Ok([nonce, ciphertext].concat())
+    }
+
+    async fn decrypt_envelope(&self, ciphertext: &[u8]) -> OperationResult<SecretValue> {
+        if ciphertext.len() <= NONCE_SIZE {
+            return Err(anyhow::Error::msg("invalid_envelope").into());
+        }
+
+        let (nonce, ciphertext) = ciphertext.split_at(NONCE_SIZE);
+        ChaCha20Poly1305::decrypt(ciphertext, &self.kek, nonce)
+    }
+}
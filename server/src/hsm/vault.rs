@@ -0,0 +1,204 @@
+use crate::config::VaultTransitSettings;
+use crate::hsm::HsmProvider;
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::Client;
+use secret_vault_value::SecretValue;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use shared::error::OperationResult;
+
+pub struct VaultTransitHsmProvider {
+    client: Client,
+    base_url: String,
+    key: String,
+    token: SecretValue,
+    namespace: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VaultResponse<T> {
+    data: T,
+}
+
+#[derive(Serialize)]
+struct RandomRequest<'a> {
+    format: &'a str,
+}
+
+#[derive(Deserialize)]
+struct RandomResponse {
+    random_bytes: String,
+}
+
+#[derive(Serialize)]
+struct EncryptRequest {
+    plaintext: String,
+}
+
+#[derive(Deserialize)]
+struct EncryptResponse {
+    ciphertext: String,
+}
+
+#[derive(Serialize)]
+struct DecryptRequest<'a> {
+    ciphertext: &'a str,
+}
+
+#[derive(Deserialize)]
+struct DecryptResponse {
+    plaintext: String,
+}
+
+impl VaultTransitHsmProvider {
+    pub fn new(settings: &VaultTransitSettings) -> OperationResult<Self> {
+        let token = std::env::var(settings.token_env.as_ref()).map_err(|_| {
+            anyhow::format_err!(
+                "The {} environment variable must hold the Vault token",
+                settings.token_env
+            )
+        })?;
+
+        Ok(Self {
+            client: Client::new(),
+            base_url: format!(
+                "{}/v1/{}",
+                settings.addr.trim_end_matches('/'),
+                settings.mount.trim_matches('/')
+            ),
+            key: settings.key.to_string(),
+            token: SecretValue::from(token),
+            namespace: settings.namespace.as_ref().map(|n| n.to_string()),
+        })
+    }
+
+    async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> OperationResult<T> {
+        let mut request = self
+            .client
+            .post(format!("{}/{}", self.base_url, path))
+            .header("X-Vault-Token", self.token.as_sensitive_str())
+            .json(body);
+        if let Some(namespace) = self.namespace.as_ref() {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+
+        let response: VaultResponse<T> = request.send().await?.error_for_status()?.json().await?;
+
+        Ok(response.data)
+    }
+}
+
+#[async_trait]
+impl HsmProvider for VaultTransitHsmProvider {
+    async fn generate_random_bytes(&self, size: u32) -> OperationResult<Vec<u8>> {
+        let response: RandomResponse = self
+            .post(
+                &format!("random/{}", size),
+                &RandomRequest { format: "base64" },
+            )
+            .await?;
+
+        Ok(BASE64.decode(response.random_bytes)?)
+    }
+
+    async fn encrypt_envelope(&self, plaintext: SecretValue) -> OperationResult<Vec<u8>> {
+        let request = EncryptRequest {
+            plaintext: BASE64.encode(plaintext.ref_sensitive_value()),
+        };
+        let response: EncryptResponse = self
+            .post(&format!("encrypt/{}", self.key), &request)
+            .await?;
+
+        Ok(response.ciphertext.into_bytes())
+    }
+
+    async fn decrypt_envelope(&self, ciphertext: &[u8]) -> OperationResult<SecretValue> {
+        let request = DecryptRequest {
+            ciphertext: std::str::from_utf8(ciphertext)?,
+        };
+        let response: DecryptResponse = self
+            .post(&format!("decrypt/{}", self.key), &request)
+            .await?;
+
+        Ok(SecretValue::from(BASE64.decode(response.plaintext)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A provider for a new key in the `vault -dev` service from docker-compose.yml, whose root
+    /// token has to be in `PANDORICA_TEST_VAULT_TOKEN`.
+    async fn dev_provider() -> VaultTransitHsmProvider {
+        let addr = std::env::var("PANDORICA_TEST_VAULT_ADDR")
+            .unwrap_or_else(|_| "http://127.0.0.1:8200".into());
+        let key = format!("pandorica-test-{}", rand::random::<u64>());
+        let settings = VaultTransitSettings {
+            addr: addr.clone().into(),
+            mount: "transit".into(),
+            key: key.clone().into(),
+            token_env: "PANDORICA_TEST_VAULT_TOKEN".into(),
+            namespace: None,
+        };
+        let provider = VaultTransitHsmProvider::new(&settings).unwrap();
+
+        // Fails once the engine is mounted, which a previous run may have done already
+        let _ = provider
+            .client
+            .post(format!("{}/v1/sys/mounts/transit", addr))
+            .header("X-Vault-Token", provider.token.as_sensitive_str())
+            .json(&HashMap::from([("type", "transit")]))
+            .send()
+            .await;
+        provider
+            .client
+            .post(format!("{}/keys/{}", provider.base_url, key))
+            .header("X-Vault-Token", provider.token.as_sensitive_str())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        provider
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Vault service from docker-compose.yml"]
+    async fn wraps_and_unwraps() {
+        let provider = dev_provider().await;
+        let key = provider.generate_random_bytes(32).await.unwrap();
+        assert_eq!(key.len(), 32);
+
+        let wrapped = provider
+            .encrypt_envelope(SecretValue::from(key.clone()))
+            .await
+            .unwrap();
+        assert!(wrapped.starts_with(b"vault:v1:"));
+
+        let unwrapped = provider.decrypt_envelope(&wrapped).await.unwrap();
+        assert_eq!(unwrapped.ref_sensitive_value(), &key);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Vault service from docker-compose.yml"]
+    async fn refuses_to_unwrap_under_another_key() {
+        let provider = dev_provider().await;
+        let other = dev_provider().await;
+
+        let wrapped = other
+            .encrypt_envelope(SecretValue::from(vec![7_u8; 32]))
+            .await
+            .unwrap();
+
+        assert!(provider.decrypt_envelope(&wrapped).await.is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use crate::config::Settings;
+use clokwerk::{AsyncScheduler, TimeUnits};
+use singleton::unsync::Singleton as UnsyncSingleton;
+use std::time::Duration;
+
+mod rotation;
+mod throttle;
+mod uploads;
+
+pub fn start() {
+    let mut scheduler = AsyncScheduler::new();
+    scheduler
+        .every(
+            Settings::get()
+                .kms
+                .rotation_check_interval_minutes
+                .minutes(),
+        )
+        .run(rotation::run);
+    // Failures outside the window count for nothing, so their records only take up space
+    scheduler
+        .every((Settings::get().security.login.failure_window_minutes.max(1) as u32).minutes())
+        .run(throttle::run);
+    scheduler.every(1.hours()).run(uploads::run);
+
+    tokio::spawn(async move {
+        // Picks up a re-wrap job that was interrupted by a restart
+        rotation::run().await;
+
+        loop {
+            scheduler.run_pending().await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
@@ -0,0 +1,145 @@
+use crate::fs::FileSystem;
+use crate::helpers::keys;
+use crate::kms::KeyManagementSystem;
+use crate::models::crypto::RewrapJob;
+use crate::repos;
+use chrono::Utc;
+use shared::error::{EmptyResult, OperationResult};
+use singleton::{sync::Singleton, unsync::Singleton as UnsyncSingleton};
+
+const BATCH_SIZE: u32 = 100;
+
+pub async fn run() {
+    let result = {
+        let mut kms = KeyManagementSystem::lock().await;
+        kms.rotate().await
+    };
+    match result {
+        Ok(true) => tracing::info!("Rotated the master key"),
+        Ok(false) => {}
+        Err(e) => tracing::error!("Failed to rotate the master key: {:?}", e),
+    }
+
+    if let Err(e) = rewrap().await {
+        tracing::error!("Failed to re-wrap data encryption keys: {:?}", e);
+    }
+}
+
+async fn rewrap() -> EmptyResult {
+    let job = repos::rewrap_job::read_pending().await?;
+    let mut job = match job {
+        Some(j) => j,
+        None => return Ok(()),
+    };
+
+    let master_key_id = KeyManagementSystem::lock().await.current_master_key_id();
+    if job.master_key_id != master_key_id {
+        return Err(anyhow::format_err!(
+            "Re-wrap job targets {}, but the current master key is {}",
+            job.master_key_id,
+            master_key_id
+        )
+        .into());
+    }
+
+    tracing::info!("Re-wrapping data encryption keys under {}", master_key_id);
+
+    while rewrap_keks(&mut job).await? {
+        repos::rewrap_job::update(&job).await?;
+    }
+    while rewrap_users(&mut job).await? {
+        repos::rewrap_job::update(&job).await?;
+    }
+    while rewrap_files(&mut job).await? {
+        repos::rewrap_job::update(&job).await?;
+    }
+
+    job.completed_on = Some(Utc::now());
+    repos::rewrap_job::update(&job).await?;
+
+    tracing::info!(
+        "Re-wrapped {} key encryption keys, {} encrypted values and {} files",
+        job.rewrapped_keys,
+        job.rewrapped_values,
+        job.rewrapped_files
+    );
+
+    Ok(())
+}
+
+async fn rewrap_keks(job: &mut RewrapJob<'_>) -> OperationResult<bool> {
+    let keks = repos::kek::read_batch_after(job.kek_cursor.as_deref(), BATCH_SIZE).await?;
+    if keks.is_empty() {
+        return Ok(false);
+    }
+
+    for mut kek in keks {
+        // The lock is held for the re-wrap alone, so that the writes don't hold up every request
+        let is_rewrapped = KeyManagementSystem::lock()
+            .await
+            .rewrap_kek(&mut kek)
+            .await?;
+        if is_rewrapped {
+            repos::kek::update(&kek).await?;
+            job.rewrapped_keys += 1;
+        }
+
+        job.kek_cursor = Some(kek.get_id().partial_identifier().to_string().into());
+    }
+
+    Ok(true)
+}
+
+async fn rewrap_users(job: &mut RewrapJob<'_>) -> OperationResult<bool> {
+    let users = repos::user::read_batch_after(job.user_cursor.as_deref(), BATCH_SIZE).await?;
+    if users.is_empty() {
+        return Ok(false);
+    }
+
+    for mut user in users {
+        // Users registered before per-user keys get theirs here, and their values are moved
+        // under it
+        let kek_id = keys::user_kek_id(&mut user).await?;
+        if let Some(email) = user.email.as_mut() {
+            if email.rewrap(&kek_id).await? {
+                repos::user::update_email(&user).await?;
+                job.rewrapped_values += 1;
+            }
+        }
+
+        job.user_cursor = Some(user.get_id().partial_identifier().to_string().into());
+    }
+
+    Ok(true)
+}
+
+async fn rewrap_files(job: &mut RewrapJob<'_>) -> OperationResult<bool> {
+    let files = repos::file::read_batch_after(job.file_cursor.as_deref(), BATCH_SIZE).await?;
+    if files.is_empty() {
+        return Ok(false);
+    }
+
+    for file in files {
+        let user = repos::user::read(file.user_id.split(':').last().unwrap()).await?;
+        let mut user = match user {
+            Some(u) => u,
+            None => {
+                tracing::warn!(
+                    "Skipped re-wrapping {}, whose user doesn't exist",
+                    file.location()
+                );
+                job.file_cursor = Some(file.get_id().partial_identifier().to_string().into());
+                continue;
+            }
+        };
+
+        let kek_id = keys::user_kek_id(&mut user).await?;
+        if FileSystem::get().rewrap(&file.location(), &kek_id).await? {
+            job.rewrapped_files += 1;
+        }
+
+        job.file_cursor = Some(file.get_id().partial_identifier().to_string().into());
+    }
+
+    Ok(true)
+}
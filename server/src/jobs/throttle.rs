@@ -0,0 +1,13 @@
+use crate::config::Settings;
+use crate::repos;
+use chrono::{Duration, Utc};
+use singleton::unsync::Singleton as UnsyncSingleton;
+
+pub async fn run() {
+    let window_start =
+        Utc::now() - Duration::minutes(Settings::get().security.login.failure_window_minutes);
+
+    if let Err(e) = repos::login_throttle::delete_stale(window_start).await {
+        tracing::error!("Failed to delete stale login throttles: {:?}", e);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::config::Settings;
+use crate::fs::FileSystem;
+use crate::models::fs::File;
+use crate::repos;
+use chrono::{Duration, Utc};
+use shared::error::EmptyResult;
+use singleton::unsync::Singleton as UnsyncSingleton;
+
+pub async fn run() {
+    let added_before = Utc::now() - Duration::hours(Settings::get().fs.stale_upload_hours);
+
+    let files = match repos::file::read_stale_uploads(added_before).await {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::error!("Failed to read stale uploads: {:?}", e);
+            return;
+        }
+    };
+
+    for file in files {
+        if let Err(e) = delete(&file).await {
+            tracing::error!(
+                "Failed to delete the stale upload {}: {:?}",
+                file.location(),
+                e
+            );
+        }
+    }
+}
+
+async fn delete(file: &File<'_>) -> EmptyResult {
+    let file_system = FileSystem::get();
+
+    // The upload may have been stored in full before the record could be updated, in which
+    // case there is nothing left to abort
+    if let Some(multipart_id) = file.multipart_id.as_ref() {
+        if let Err(e) = file_system.abort(&file.location(), multipart_id).await {
+            tracing::warn!("Failed to abort the upload to {}: {:?}", file.location(), e);
+        }
+    }
+    file_system.delete(&file.location()).await?;
+
+    repos::file::delete(file.get_id().partial_identifier()).await
+}
@@ -0,0 +1,348 @@
+use crate::kms::cipher::{Algorithm, Cipher};
+use blahaj::{Share, Sharks};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use scrypt::Params;
+use secret_vault_value::SecretValue;
+use shared::error::{EmptyResult, OperationResult};
+
+// Master key share, version 1
+//
+// | magic "PDMS" | version: u8 | threshold: u8 | scrypt log_n: u8 | scrypt r: u32 BE |
+// | scrypt p: u32 BE | master key ID length: u16 BE | master key ID | key check value: 16 bytes |
+// | salt: 16 bytes | nonce: 24 bytes | encrypted share |
+//
+// Everything before the encrypted share is authenticated as associated data. The key check value
+// lets a recovery tell a correct key apart from the garbage that mismatched shares combine into.
+const SHARE_MAGIC: &[u8; 4] = b"PDMS";
+const SHARE_VERSION: u8 = 1;
+const FIXED_HEADER_SIZE: usize = 17;
+const CHECK_VALUE_SIZE: usize = 16;
+const SALT_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+
+#[cfg(not(test))]
+const SCRYPT_LOG_N: u8 = 17;
+// Shares record their scrypt cost, so tests can seal them cheaply and still parse them as usual
+#[cfg(test)]
+const SCRYPT_LOG_N: u8 = 10;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+pub struct MasterKeyShare {
+    pub master_key_id: String,
+    pub threshold: u8,
+    check_value: Vec<u8>,
+    params: Params,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    encrypted_share: Vec<u8>,
+}
+
+impl MasterKeyShare {
+    /// Splits `key` into one share per passphrase, any `threshold` of which can rebuild it.
+    pub fn split(
+        master_key_id: &str,
+        key: &SecretValue,
+        threshold: u8,
+        passphrases: &[SecretValue],
+    ) -> OperationResult<Vec<MasterKeyShare>> {
+        Self::verify_threshold(threshold, passphrases.len())?;
+
+        let check_value = Self::check_value(master_key_id, key)?;
+        let shares = Sharks(threshold).dealer(key.ref_sensitive_value());
+
+        shares
+            .zip(passphrases)
+            .map(|(share, passphrase)| {
+                Self::seal(
+                    master_key_id,
+                    threshold,
+                    &check_value,
+                    &SecretValue::from(Vec::from(&share)),
+                    passphrase,
+                )
+            })
+            .collect()
+    }
+
+    pub fn verify_threshold(threshold: u8, shares: usize) -> EmptyResult {
+        if threshold < 2 || shares < threshold as usize || shares > 255 {
+            return Err(anyhow::Error::msg("invalid_master_key_backup__threshold").into());
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the key from unsealed shares of the same master key.
+    pub fn combine(shares: &[(MasterKeyShare, SecretValue)]) -> OperationResult<SecretValue> {
+        let (first, _) = match shares.first() {
+            Some(s) => s,
+            None => return Err(anyhow::Error::msg("invalid_master_key_backup__shares").into()),
+        };
+        if shares.iter().any(|(s, _)| {
+            s.master_key_id != first.master_key_id
+                || s.threshold != first.threshold
+                || s.check_value != first.check_value
+        }) {
+            return Err(anyhow::Error::msg("invalid_master_key_backup__mixed_shares").into());
+        }
+
+        let mut unsealed = Vec::with_capacity(shares.len());
+        for (share, passphrase) in shares {
+            let bytes = share.unseal(passphrase)?;
+            let share = Share::try_from(&bytes.ref_sensitive_value()[..])
+                .map_err(|_| anyhow::Error::msg("invalid_master_key_backup__share"))?;
+            unsealed.push(share);
+        }
+
+        let key = Sharks(first.threshold)
+            .recover(&unsealed)
+            .map_err(|_| anyhow::Error::msg("invalid_master_key_backup__not_enough_shares"))?;
+        let key = SecretValue::from(key);
+
+        if Self::check_value(&first.master_key_id, &key)? != first.check_value {
+            return Err(anyhow::Error::msg("invalid_master_key_backup__check_value").into());
+        }
+
+        Ok(key)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        bytes.extend_from_slice(&self.encrypted_share);
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> OperationResult<MasterKeyShare> {
+        if bytes.len() < FIXED_HEADER_SIZE || &bytes[..4] != SHARE_MAGIC {
+            return Err(anyhow::Error::msg("invalid_master_key_backup__format").into());
+        }
+        if bytes[4] != SHARE_VERSION {
+            return Err(anyhow::Error::msg("invalid_master_key_backup__version").into());
+        }
+
+        let threshold = bytes[5];
+        let params = Params::new(
+            bytes[6],
+            u32::from_be_bytes(bytes[7..11].try_into()?),
+            u32::from_be_bytes(bytes[11..15].try_into()?),
+        )?;
+        let id_size = u16::from_be_bytes(bytes[15..17].try_into()?) as usize;
+
+        let nonce_size = Algorithm::XChaCha20Poly1305.nonce_size();
+        let header_size = FIXED_HEADER_SIZE + id_size + CHECK_VALUE_SIZE + SALT_SIZE + nonce_size;
+        if bytes.len() <= header_size {
+            return Err(anyhow::Error::msg("invalid_master_key_backup__format").into());
+        }
+
+        let mut offset = FIXED_HEADER_SIZE;
+        let master_key_id = String::from_utf8(bytes[offset..offset + id_size].to_vec())?;
+        offset += id_size;
+        let check_value = bytes[offset..offset + CHECK_VALUE_SIZE].to_vec();
+        offset += CHECK_VALUE_SIZE;
+        let salt = bytes[offset..offset + SALT_SIZE].to_vec();
+        offset += SALT_SIZE;
+        let nonce = bytes[offset..offset + nonce_size].to_vec();
+
+        Ok(MasterKeyShare {
+            master_key_id,
+            threshold,
+            check_value,
+            params,
+            salt,
+            nonce,
+            encrypted_share: bytes[header_size..].to_vec(),
+        })
+    }
+
+    fn seal(
+        master_key_id: &str,
+        threshold: u8,
+        check_value: &[u8],
+        share: &SecretValue,
+        passphrase: &SecretValue,
+    ) -> OperationResult<MasterKeyShare> {
+        let mut salt = vec![0_u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = vec![0_u8; Algorithm::XChaCha20Poly1305.nonce_size()];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut sealed = MasterKeyShare {
+            master_key_id: master_key_id.to_string(),
+            threshold,
+            check_value: check_value.to_vec(),
+            params: Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?,
+            salt,
+            nonce,
+            encrypted_share: Vec::new(),
+        };
+
+        let wrapping_key = sealed.derive_key(passphrase)?;
+        sealed.encrypted_share = Cipher::new(Algorithm::XChaCha20Poly1305, &wrapping_key)?
+            .encrypt(&sealed.nonce, share.ref_sensitive_value(), &sealed.header())?;
+
+        Ok(sealed)
+    }
+
+    fn unseal(&self, passphrase: &SecretValue) -> OperationResult<SecretValue> {
+        let wrapping_key = self.derive_key(passphrase)?;
+        let share = Cipher::new(Algorithm::XChaCha20Poly1305, &wrapping_key)?
+            .decrypt(&self.nonce, &self.encrypted_share, &self.header())
+            .map_err(|_| anyhow::Error::msg("invalid_master_key_backup__passphrase"))?;
+
+        Ok(SecretValue::from(share))
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(SHARE_MAGIC);
+        header.push(SHARE_VERSION);
+        header.push(self.threshold);
+        header.push(self.params.log_n());
+        header.extend_from_slice(&self.params.r().to_be_bytes());
+        header.extend_from_slice(&self.params.p().to_be_bytes());
+        header.extend_from_slice(&(self.master_key_id.len() as u16).to_be_bytes());
+        header.extend_from_slice(self.master_key_id.as_bytes());
+        header.extend_from_slice(&self.check_value);
+        header.extend_from_slice(&self.salt);
+        header.extend_from_slice(&self.nonce);
+        header
+    }
+
+    fn derive_key(&self, passphrase: &SecretValue) -> OperationResult<SecretValue> {
+        let mut key = vec![0_u8; KEY_SIZE];
+        scrypt::scrypt(
+            passphrase.ref_sensitive_value(),
+            &self.salt,
+            &self.params,
+            &mut key,
+        )?;
+
+        Ok(SecretValue::from(key))
+    }
+
+    /// The tag of an empty message sealed with the key under a zero nonce.
+    fn check_value(master_key_id: &str, key: &SecretValue) -> OperationResult<Vec<u8>> {
+        let nonce = vec![0_u8; Algorithm::XChaCha20Poly1305.nonce_size()];
+
+        Cipher::new(Algorithm::XChaCha20Poly1305, key)?.encrypt(
+            &nonce,
+            &[],
+            master_key_id.as_bytes(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_KEY_ID: &str = "master_key:test";
+
+    fn passphrase(index: usize) -> SecretValue {
+        SecretValue::from(format!("passphrase {}", index))
+    }
+
+    fn generate_key() -> SecretValue {
+        let mut key = vec![0_u8; KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+        SecretValue::from(key)
+    }
+
+    /// Splits `key` and returns the encoded shares, as they are written to files.
+    fn split(key: &SecretValue, threshold: u8, shares: usize) -> Vec<Vec<u8>> {
+        let passphrases: Vec<SecretValue> = (0..shares).map(passphrase).collect();
+
+        MasterKeyShare::split(MASTER_KEY_ID, key, threshold, &passphrases)
+            .unwrap()
+            .iter()
+            .map(|s| s.to_bytes())
+            .collect()
+    }
+
+    fn combine(shares: &[Vec<u8>], indices: &[usize]) -> OperationResult<SecretValue> {
+        let shares: Vec<(MasterKeyShare, SecretValue)> = indices
+            .iter()
+            .map(|&i| (MasterKeyShare::parse(&shares[i]).unwrap(), passphrase(i)))
+            .collect();
+
+        MasterKeyShare::combine(&shares)
+    }
+
+    #[test]
+    fn any_threshold_of_shares_rebuild_the_key() {
+        let key = generate_key();
+        let shares = split(&key, 3, 5);
+
+        for indices in [[0, 1, 2], [0, 2, 4], [4, 3, 1]] {
+            let recovered = combine(&shares, &indices).unwrap();
+            assert_eq!(recovered.ref_sensitive_value(), key.ref_sensitive_value());
+        }
+
+        let recovered = combine(&shares, &[0, 1, 2, 3, 4]).unwrap();
+        assert_eq!(recovered.ref_sensitive_value(), key.ref_sensitive_value());
+    }
+
+    #[test]
+    fn fewer_shares_than_the_threshold_fail() {
+        let key = generate_key();
+        let shares = split(&key, 3, 5);
+
+        assert!(combine(&shares, &[0, 1]).is_err());
+        assert!(combine(&shares, &[]).is_err());
+    }
+
+    #[test]
+    fn a_repeated_share_does_not_count_twice() {
+        let key = generate_key();
+        let shares = split(&key, 3, 5);
+
+        assert!(combine(&shares, &[0, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn a_wrong_passphrase_fails() {
+        let key = generate_key();
+        let shares = split(&key, 2, 3);
+
+        let shares = vec![
+            (MasterKeyShare::parse(&shares[0]).unwrap(), passphrase(0)),
+            (MasterKeyShare::parse(&shares[1]).unwrap(), passphrase(2)),
+        ];
+        assert!(MasterKeyShare::combine(&shares).is_err());
+    }
+
+    #[test]
+    fn shares_of_different_keys_do_not_mix() {
+        let first = split(&generate_key(), 2, 3);
+        let second = split(&generate_key(), 2, 3);
+
+        let shares = vec![
+            (MasterKeyShare::parse(&first[0]).unwrap(), passphrase(0)),
+            (MasterKeyShare::parse(&second[1]).unwrap(), passphrase(1)),
+        ];
+        assert!(MasterKeyShare::combine(&shares).is_err());
+    }
+
+    #[test]
+    fn tampered_headers_fail() {
+        let key = generate_key();
+        let mut shares = split(&key, 2, 3);
+
+        // The threshold is authenticated along with the rest of the header
+        shares[0][5] = 3;
+        shares[1][5] = 3;
+        shares[2][5] = 3;
+        assert!(combine(&shares, &[0, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn thresholds_are_checked() {
+        assert!(MasterKeyShare::verify_threshold(2, 3).is_ok());
+        assert!(MasterKeyShare::verify_threshold(3, 3).is_ok());
+        assert!(MasterKeyShare::verify_threshold(1, 3).is_err());
+        assert!(MasterKeyShare::verify_threshold(4, 3).is_err());
+        assert!(MasterKeyShare::verify_threshold(2, 256).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use secret_vault_value::SecretValue;
+use serde::{Deserialize, Serialize};
+use shared::error::{EmptyResult, OperationResult};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Algorithm {
+    /// The cipher used before records were tagged with one, so untagged records fall back to it.
+    #[default]
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+    #[serde(rename = "aes-256-gcm-siv")]
+    Aes256GcmSiv,
+}
+
+impl Algorithm {
+    pub fn nonce_size(&self) -> usize {
+        match self {
+            Algorithm::ChaCha20Poly1305 | Algorithm::XChaCha20Poly1305 => 24,
+            Algorithm::Aes256GcmSiv => 12,
+        }
+    }
+
+    /// Whether the cipher can authenticate associated data alongside the ciphertext.
+    pub fn supports_associated_data(&self) -> bool {
+        !matches!(self, Algorithm::ChaCha20Poly1305)
+    }
+}
+
+enum Inner {
+    ChaCha20Poly1305(SecretValue),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    Aes256GcmSiv(Aes256GcmSiv),
+}
+
+pub struct Cipher {
+    algorithm: Algorithm,
+    inner: Inner,
+}
+
+impl Cipher {
+    pub fn new(algorithm: Algorithm, key: &SecretValue) -> OperationResult<Self> {
+        let inner = match algorithm {
+            Algorithm::ChaCha20Poly1305 => Inner::ChaCha20Poly1305(key.clone()),
+            Algorithm::XChaCha20Poly1305 => Inner::XChaCha20Poly1305(
+                XChaCha20Poly1305::new_from_slice(key.ref_sensitive_value())
+                    .map_err(|_| anyhow::Error::msg("invalid_cipher__key"))?,
+            ),
+            Algorithm::Aes256GcmSiv => Inner::Aes256GcmSiv(
+                Aes256GcmSiv::new_from_slice(key.ref_sensitive_value())
+                    .map_err(|_| anyhow::Error::msg("invalid_cipher__key"))?,
+            ),
+        };
+
+        Ok(Self { algorithm, inner })
+    }
+
+    pub fn encrypt(&self, nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> OperationResult<Vec<u8>> {
+        self.verify_parameters(nonce, aad)?;
+
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let ciphertext = match &self.inner {
+            Inner::ChaCha20Poly1305(key) => {
+                let plaintext = SecretValue::from(plaintext.to_vec());
+                return Ok(ChaCha20Poly1305::encrypt(&plaintext, key, nonce)?);
+            }
+            Inner::XChaCha20Poly1305(cipher) => cipher.encrypt(XNonce::from_slice(nonce), payload),
+            Inner::Aes256GcmSiv(cipher) => {
+                cipher.encrypt(aes_gcm_siv::Nonce::from_slice(nonce), payload)
+            }
+        };
+
+        ciphertext.map_err(|_| anyhow::Error::msg("invalid_cipher__encryption").into())
+    }
+
+    pub fn decrypt(&self, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> OperationResult<Vec<u8>> {
+        self.verify_parameters(nonce, aad)?;
+
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        let plaintext = match &self.inner {
+            Inner::ChaCha20Poly1305(key) => {
+                let plaintext = ChaCha20Poly1305::decrypt(ciphertext, key, nonce)?;
+                return Ok(plaintext.ref_sensitive_value().to_vec());
+            }
+            Inner::XChaCha20Poly1305(cipher) => cipher.decrypt(XNonce::from_slice(nonce), payload),
+            Inner::Aes256GcmSiv(cipher) => {
+                cipher.decrypt(aes_gcm_siv::Nonce::from_slice(nonce), payload)
+            }
+        };
+
+        plaintext.map_err(|_| anyhow::Error::msg("invalid_cipher__decryption").into())
+    }
+
+    fn verify_parameters(&self, nonce: &[u8], aad: &[u8]) -> EmptyResult {
+        if nonce.len() != self.algorithm.nonce_size() {
+            return Err(anyhow::Error::msg("invalid_cipher__nonce").into());
+        }
+        if !aad.is_empty() && !self.algorithm.supports_associated_data() {
+            return Err(anyhow::Error::msg("invalid_cipher__associated_data").into());
+        }
+
+        Ok(())
+    }
+}
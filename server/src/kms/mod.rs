@@ -1,16 +1,32 @@
 use crate::config::Settings;
-use crate::models::crypto::{Dek, Mk};
+use crate::hsm::Hsm;
+use crate::kms::backup::MasterKeyShare;
+use crate::kms::cipher::{Algorithm, Cipher};
+use crate::models::crypto::{Dek, Kek, Mk};
 use crate::repos;
-use chrono::Utc;
-use crypto::chacha20poly1305::ChaCha20Poly1305;
-use crypto::hsm::HsmProvider;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
 use secret_vault_value::SecretValue;
+use sha2::Sha256;
 use shared::error::{EmptyResult, OperationResult};
-use singleton::{unsync::Singleton as UnsyncSingleton, OnceCell, Singleton, SingletonInit};
+use singleton::{
+    sync::Singleton as SyncSingleton, unsync::Singleton as UnsyncSingleton, OnceCell, Singleton,
+    SingletonInit,
+};
 use std::borrow::Cow;
+use std::sync::RwLock;
+
+pub mod backup;
+pub mod cipher;
 
 const ENCRYPTION_KEY_SIZE: u32 = 32;
-const ENCRYPTION_NONCE_SIZE: u32 = 24;
+const TOKEN_KEY_ID: &str = "token_hmac";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The unwrapped token key. It is kept outside of the KMS so that hashing and verifying tokens,
+/// which every request does, doesn't wait on the KMS lock.
+static TOKEN_KEY: RwLock<Option<SecretValue>> = RwLock::new(None);
 
 #[derive(Default, Singleton)]
 #[singleton(use_once_cell = false)]
@@ -20,96 +36,336 @@ pub struct KeyManagementSystem {
 
 impl KeyManagementSystem {
     pub async fn init_kms(&mut self) -> EmptyResult {
-        self.rotate().await
+        self.rotate().await?;
+        self.load_token_key().await
+    }
+
+    /// Unwraps the token key, creating it on first start, and replaces the cached one.
+    async fn load_token_key(&self) -> EmptyResult {
+        let token_key = match self.load_kek(TOKEN_KEY_ID).await {
+            Ok(kek) => kek,
+            Err(e) => {
+                if e.to_string() != "key_encryption_key_not_found" {
+                    return Err(e);
+                }
+
+                let kek = self.generate_kek().await?;
+                let mut kek = repos::kek::create_with_id(TOKEN_KEY_ID, kek).await?;
+                self.decrypt_kek(&mut kek).await?;
+                kek
+            }
+        };
+        *TOKEN_KEY
+            .write()
+            .map_err(|_| anyhow::Error::msg("token_key_poisoned"))? = token_key.decoded_key;
+
+        Ok(())
     }
 
-    pub async fn rotate(&mut self) -> EmptyResult {
+    /// Replaces the current master key once it expires. Returns `true` if a new key was created.
+    pub async fn rotate(&mut self) -> OperationResult<bool> {
         let result = self.load_master_key(None).await;
 
-        match result {
-            Ok(mut mk) => {
+        let previous = match result {
+            Ok(mk) => {
                 if mk.expires_on > Utc::now() {
                     self.current_master_key = Cow::Owned(mk);
-                    return Ok(());
+                    return Ok(false);
                 }
 
-                mk.is_active = false;
-                repos::mk::update(&mk).await?;
+                Some(mk)
             }
             Err(e) => {
                 if e.to_string() != "master_key_not_found" {
                     return Err(e);
                 }
+
+                None
             }
-        }
+        };
 
-        let key_material =
-            SecretValue::from(HsmProvider::lock().await.generate_random_bytes(32).await?);
-        let wrapped_key_material = HsmProvider::lock()
+        let key_material = SecretValue::from(Hsm::lock().await.generate_random_bytes(32).await?);
+        let wrapped_key_material = Hsm::lock()
             .await
-            .encrypt_envelope(
-                key_material.clone(),
-                Settings::get().hsm.gcp.as_ref().unwrap().key.as_ref(),
-            )
+            .encrypt_envelope(key_material.clone())
             .await?;
 
-        let master_key = Mk::new(wrapped_key_material);
-        let mut master_key: Mk = repos::mk::create(master_key).await?;
+        let expires_on = Utc::now() + Duration::days(Settings::get().kms.rotation_period_days);
+        let master_key = Mk::new(wrapped_key_material, expires_on);
+        let mut master_key: Mk = match previous.as_ref() {
+            Some(previous) => repos::mk::replace(previous, master_key).await?,
+            None => repos::mk::create(master_key).await?,
+        };
 
         master_key.key = Default::default();
         master_key.decoded_key = Some(key_material);
 
         self.current_master_key = Cow::Owned(master_key);
 
-        Ok(())
+        // Not on the first start, where the token key doesn't exist yet
+        if previous.is_some() {
+            self.load_token_key().await?;
+        }
+
+        Ok(true)
     }
 
-    pub async fn generate_dek<'a>(&self) -> OperationResult<Dek<'a>> {
+    pub fn current_master_key_id(&self) -> String {
+        self.current_master_key.get_id().as_string()
+    }
+
+    /// Creates a key encryption key wrapped under the current master key. It still has to be
+    /// stored before DEKs can be wrapped with it.
+    pub async fn generate_kek<'a>(&self) -> OperationResult<Kek<'a>> {
         let key = SecretValue::from(
-            HsmProvider::lock()
+            Hsm::lock()
                 .await
                 .generate_random_bytes(ENCRYPTION_KEY_SIZE)
                 .await?,
         );
-        let nonce = HsmProvider::lock()
-            .await
-            .generate_random_bytes(ENCRYPTION_NONCE_SIZE)
+        let (algorithm, wrapping_nonce, wrapped_key_material) = self
+            .wrap_key(&key, self.current_master_key.decoded_key.as_ref().unwrap())
             .await?;
-        let wrapping_nonce = HsmProvider::lock()
+
+        Ok(Kek::new(
+            key,
+            self.current_master_key_id(),
+            algorithm,
+            wrapping_nonce,
+            wrapped_key_material,
+        ))
+    }
+
+    /// Creates a DEK wrapped by the given key encryption key, with a nonce sized for `algorithm`.
+    pub async fn generate_dek<'a>(
+        &self,
+        kek_id: &str,
+        algorithm: Algorithm,
+    ) -> OperationResult<Dek<'a>> {
+        let kek = self.load_kek(kek_id.split(':').last().unwrap()).await?;
+
+        let key = SecretValue::from(
+            Hsm::lock()
+                .await
+                .generate_random_bytes(ENCRYPTION_KEY_SIZE)
+                .await?,
+        );
+        let nonce = Hsm::lock()
             .await
-            .generate_random_bytes(ENCRYPTION_NONCE_SIZE)
+            .generate_random_bytes(algorithm.nonce_size() as u32)
+            .await?;
+        let (wrapping_algorithm, wrapping_nonce, wrapped_key_material) = self
+            .wrap_key(&key, kek.decoded_key.as_ref().unwrap())
             .await?;
-        let wrapped_key_material = ChaCha20Poly1305::encrypt(
-            &key,
-            &self.current_master_key.decoded_key.clone().unwrap(),
-            &wrapping_nonce,
-        )?;
 
         Ok(Dek::new(
             key,
             nonce,
-            self.current_master_key.get_id().as_string(),
+            kek.get_id().as_string(),
+            wrapping_algorithm,
             wrapping_nonce,
             wrapped_key_material,
         ))
     }
 
     pub async fn decrypt_dek(&self, dek: &'_ mut Dek<'_>) -> EmptyResult {
-        let master_key = self
-            .load_master_key(Some(dek.master_key_id.as_ref().split(':').last().unwrap()))
-            .await?;
+        let (table, id) = match dek.wrapping_key_id.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(anyhow::Error::msg("invalid_dek__wrapping_key").into()),
+        };
 
-        let key = ChaCha20Poly1305::decrypt(
-            &dek.key,
-            master_key.decoded_key.as_ref().unwrap(),
+        let wrapping_key = match table {
+            "key_encryption_key" => self.load_kek(id).await?.decoded_key,
+            "master_key" => self.load_master_key(Some(id)).await?.decoded_key,
+            _ => return Err(anyhow::Error::msg("invalid_dek__wrapping_key").into()),
+        };
+
+        let key = Cipher::new(dek.wrapping_algorithm, wrapping_key.as_ref().unwrap())?.decrypt(
             &dek.wrapping_nonce,
+            &dek.key,
+            &[],
+        )?;
+
+        dek.decoded_key = SecretValue::from(key);
+
+        Ok(())
+    }
+
+    /// Moves `dek`, which predates per-user keys and is wrapped by a master key, under the key
+    /// encryption key `kek_id`. Returns `false` if it already is wrapped by a key encryption key,
+    /// which gets re-wrapped on its own instead.
+    pub async fn rewrap_dek(&self, dek: &'_ mut Dek<'_>, kek_id: &str) -> OperationResult<bool> {
+        if !dek.wrapping_key_id.starts_with("master_key:") {
+            return Ok(false);
+        }
+
+        self.decrypt_dek(dek).await?;
+        let kek = self.load_kek(kek_id.split(':').last().unwrap()).await?;
+
+        let (algorithm, wrapping_nonce, wrapped_key_material) = self
+            .wrap_key(&dek.decoded_key, kek.decoded_key.as_ref().unwrap())
+            .await?;
+
+        dek.key = wrapped_key_material.into();
+        dek.wrapping_algorithm = algorithm;
+        dek.wrapping_nonce = wrapping_nonce.into();
+        dek.wrapping_key_id = kek.get_id().as_string().into();
+
+        Ok(true)
+    }
+
+    /// Re-wraps `kek` under the current master key. Returns `false` if it already was.
+    pub async fn rewrap_kek(&self, kek: &'_ mut Kek<'_>) -> OperationResult<bool> {
+        let master_key_id = self.current_master_key_id();
+        if kek.master_key_id == master_key_id {
+            return Ok(false);
+        }
+
+        self.decrypt_kek(kek).await?;
+
+        let (algorithm, wrapping_nonce, wrapped_key_material) = self
+            .wrap_key(
+                kek.decoded_key.as_ref().unwrap(),
+                self.current_master_key.decoded_key.as_ref().unwrap(),
+            )
+            .await?;
+
+        kek.key = wrapped_key_material.into();
+        kek.algorithm = algorithm;
+        kek.wrapping_nonce = wrapping_nonce.into();
+        kek.master_key_id = master_key_id.into();
+
+        Ok(true)
+    }
+
+    /// Wraps `key` with the configured algorithm. Returns the algorithm, the nonce and the
+    /// wrapped key.
+    async fn wrap_key(
+        &self,
+        key: &SecretValue,
+        wrapping_key: &SecretValue,
+    ) -> OperationResult<(Algorithm, Vec<u8>, Vec<u8>)> {
+        let algorithm = Settings::get().kms.algorithm;
+        let nonce = Hsm::lock()
+            .await
+            .generate_random_bytes(algorithm.nonce_size() as u32)
+            .await?;
+        let wrapped_key = Cipher::new(algorithm, wrapping_key)?.encrypt(
+            &nonce,
+            key.ref_sensitive_value(),
+            &[],
         )?;
 
-        dek.decoded_key = key;
+        Ok((algorithm, nonce, wrapped_key))
+    }
+
+    async fn load_kek<'a>(&self, id: &str) -> OperationResult<Kek<'a>> {
+        let kek = repos::kek::read(id).await?;
+
+        let mut kek = match kek {
+            Some(k) => Ok(k),
+            None => Err(anyhow::Error::msg("key_encryption_key_not_found")),
+        }?;
+
+        self.decrypt_kek(&mut kek).await?;
+
+        Ok(kek)
+    }
+
+    async fn decrypt_kek(&self, kek: &mut Kek<'_>) -> EmptyResult {
+        let master_key = if kek.master_key_id == self.current_master_key_id() {
+            self.current_master_key.decoded_key.clone()
+        } else {
+            self.load_master_key(Some(kek.master_key_id.split(':').last().unwrap()))
+                .await?
+                .decoded_key
+        };
+
+        let key = Cipher::new(kek.algorithm, master_key.as_ref().unwrap())?.decrypt(
+            &kek.wrapping_nonce,
+            &kek.key,
+            &[],
+        )?;
+
+        kek.decoded_key = Some(SecretValue::from(key));
 
         Ok(())
     }
 
+    /// Returns the keyed hash that is stored in place of a session or refresh token.
+    pub fn hash_token(token: &[u8]) -> OperationResult<Vec<u8>> {
+        let mut mac = Self::token_mac()?;
+        mac.update(token);
+
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+
+    /// Compares the hash of `token` with `hash` in constant time.
+    pub fn verify_token(token: &[u8], hash: &[u8]) -> OperationResult<bool> {
+        let mut mac = Self::token_mac()?;
+        mac.update(token);
+
+        Ok(mac.verify_slice(hash).is_ok())
+    }
+
+    fn token_mac() -> OperationResult<HmacSha256> {
+        let token_key = TOKEN_KEY
+            .read()
+            .map_err(|_| anyhow::Error::msg("token_key_poisoned"))?;
+        let key = match token_key.as_ref() {
+            Some(k) => k,
+            None => return Err(anyhow::Error::msg("token_key_not_loaded").into()),
+        };
+
+        HmacSha256::new_from_slice(key.ref_sensitive_value())
+            .map_err(|_| anyhow::Error::msg("invalid_token_key").into())
+    }
+
+    /// Splits a master key into Shamir shares, one sealed to each custodian passphrase.
+    /// Defaults to the current master key.
+    pub async fn export_master_key(
+        &self,
+        id: Option<&str>,
+        threshold: u8,
+        passphrases: &[SecretValue],
+    ) -> OperationResult<Vec<MasterKeyShare>> {
+        let master_key = self
+            .load_master_key(id.map(|id| id.split(':').last().unwrap()))
+            .await?;
+
+        MasterKeyShare::split(
+            &master_key.get_id().as_string(),
+            master_key.decoded_key.as_ref().unwrap(),
+            threshold,
+            passphrases,
+        )
+    }
+
+    /// Rebuilds a master key from its shares and wraps it under the configured HSM key, which
+    /// replaces the one it was wrapped under before. Returns the ID of the recovered key.
+    pub async fn recover_master_key(
+        &self,
+        shares: &[(MasterKeyShare, SecretValue)],
+    ) -> OperationResult<String> {
+        let key_material = MasterKeyShare::combine(shares)?;
+        let master_key_id = shares[0].0.master_key_id.clone();
+
+        let master_key = repos::mk::read(master_key_id.split(':').last().unwrap()).await?;
+        let mut master_key = match master_key {
+            Some(m) => Ok(m),
+            None => Err(anyhow::Error::msg("master_key_not_found")),
+        }?;
+
+        master_key.key = Hsm::lock()
+            .await
+            .encrypt_envelope(key_material)
+            .await?
+            .into();
+        repos::mk::update(&master_key).await?;
+
+        Ok(master_key_id)
+    }
+
     async fn load_master_key<'a>(&self, id: Option<&'a str>) -> OperationResult<Mk<'a>> {
         let master_key = match id {
             Some(id) => repos::mk::read(id).await,
@@ -127,13 +383,7 @@ impl KeyManagementSystem {
     }
 
     async fn decrypt_master_key<'a>(&self, master_key: &mut Mk<'a>) -> EmptyResult {
-        let decrypted_key = HsmProvider::lock()
-            .await
-            .decrypt_envelope(
-                &master_key.key,
-                Settings::get().hsm.gcp.as_ref().unwrap().key.as_ref(),
-            )
-            .await?;
+        let decrypted_key = Hsm::lock().await.decrypt_envelope(&master_key.key).await?;
 
         master_key.decoded_key = Some(decrypted_key);
 
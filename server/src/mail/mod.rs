@@ -0,0 +1,71 @@
+use crate::config::{MailSettings, Settings};
+use crate::mail::smtp::SmtpMailer;
+use crate::mail::spool::SpoolMailer;
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::Message;
+use shared::error::{EmptyResult, OperationResult};
+use singleton::{unsync::Singleton as UnsyncSingleton, Singleton, SingletonInit};
+
+mod smtp;
+mod spool;
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: Message) -> EmptyResult;
+}
+
+#[derive(Singleton)]
+#[singleton(sync = false)]
+pub struct Mail {
+    mailer: Box<dyn Mailer>,
+}
+
+impl Mail {
+    pub fn new(settings: &MailSettings) -> OperationResult<Self> {
+        let mailer: Box<dyn Mailer> = match settings.provider.as_ref() {
+            "smtp" => {
+                let smtp = match settings.smtp.as_ref() {
+                    Some(s) => s,
+                    None => panic!("Missing [mail.smtp] settings for the smtp mail provider"),
+                };
+
+                Box::new(SmtpMailer::new(smtp)?)
+            }
+            "spool" => {
+                let spool = match settings.spool.as_ref() {
+                    Some(s) => s,
+                    None => panic!("Missing [mail.spool] settings for the spool mail provider"),
+                };
+
+                Box::new(SpoolMailer::new(spool)?)
+            }
+            val => {
+                panic!("Unknown mail provider: {}", val);
+            }
+        };
+
+        Ok(Self { mailer })
+    }
+
+    /// Sends a plain text mail from the configured address.
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> EmptyResult {
+        let message = Message::builder()
+            .from(Settings::get().mail.from.parse()?)
+            .to(to.parse()?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?;
+
+        self.mailer.send(message).await
+    }
+}
+
+impl SingletonInit<Mail> for Mail {
+    fn init() -> Mail {
+        match Mail::new(&Settings::get().mail) {
+            Ok(m) => m,
+            Err(e) => panic!("Failed to set up the mail provider: {:?}", e),
+        }
+    }
+}
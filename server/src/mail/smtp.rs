@@ -0,0 +1,53 @@
+use crate::config::SmtpMailSettings;
+use crate::mail::Mailer;
+use async_trait::async_trait;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use shared::error::{EmptyResult, OperationResult};
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(settings: &SmtpMailSettings) -> OperationResult<Self> {
+        let mut builder = match settings.security.as_ref() {
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?,
+            // Only meant for a sink on the same machine, since the password would go in the clear
+            "none" => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(settings.host.as_ref())
+            }
+            val => {
+                panic!("Unknown SMTP security: {}", val);
+            }
+        }
+        .port(settings.port);
+
+        if let Some(username) = settings.username.as_ref() {
+            let password_env = settings.password_env.as_ref().ok_or_else(|| {
+                anyhow::Error::msg("mail.smtp.password_env must be set along with the username")
+            })?;
+            let password = std::env::var(password_env.as_ref()).map_err(|_| {
+                anyhow::format_err!(
+                    "The {} environment variable must hold the SMTP password",
+                    password_env
+                )
+            })?;
+
+            builder = builder.credentials(Credentials::new(username.to_string(), password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: Message) -> EmptyResult {
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
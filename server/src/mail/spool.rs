@@ -0,0 +1,30 @@
+use crate::config::SpoolMailSettings;
+use crate::mail::Mailer;
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncTransport, Message, Tokio1Executor};
+use shared::error::{EmptyResult, OperationResult};
+
+/// Writes every mail to a file of its own in the spool directory rather than sending it, for
+/// development and tests. A local SMTP sink like MailHog goes through the smtp provider instead.
+pub struct SpoolMailer {
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl SpoolMailer {
+    pub fn new(settings: &SpoolMailSettings) -> OperationResult<Self> {
+        let dir = std::env::current_dir()?.join(settings.dir.as_ref());
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            transport: AsyncFileTransport::new(dir),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SpoolMailer {
+    async fn send(&self, message: Message) -> EmptyResult {
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
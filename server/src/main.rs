@@ -1,41 +1,124 @@
 #![forbid(unsafe_code)]
 
 use crate::config::Settings;
-use ::crypto::hsm::HsmProvider;
 use ::shared::error::EmptyResult;
+use clap::{Parser, Subcommand};
+use protobuf::pandorica_admin::admin_service_server::AdminServiceServer;
 use protobuf::pandorica_auth::auth_service_server::AuthServiceServer;
+use protobuf::pandorica_file::file_service_server::FileServiceServer;
 use protobuf::pandorica_user::user_service_server::UserServiceServer;
 use protobuf::FILE_DESCRIPTOR_SET;
 use singleton::{sync::Singleton, unsync::Singleton as UnsyncSingleton};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use surrealdb::engine::remote::ws::{Client, Ws, Wss};
 use surrealdb::opt::auth::Root;
 use surrealdb::Surreal;
 use tonic::transport::Server;
 use tracing_subscriber::fmt::format::FmtSpan;
 
+use crate::fs::FileSystem;
+use crate::handlers::admin::AdminService;
 use crate::handlers::auth::AuthService;
+use crate::handlers::file::FileService;
 use crate::handlers::user::UserService;
+use crate::helpers::auth_guard::AuthGuard;
+use crate::helpers::breach_filter::BreachFilter;
+use crate::helpers::opaque::Opaque;
+use crate::hsm::Hsm;
 use crate::kms::KeyManagementSystem;
+use crate::mail::Mail;
 
+mod commands;
 mod config;
 mod fs;
 mod handlers;
 mod helpers;
+mod hsm;
+mod jobs;
 mod kms;
+mod mail;
 mod models;
 mod repos;
 mod validators;
 
 static DB: Surreal<Client> = Surreal::init();
 
+/// The Pandorica server. Without a command, it serves the gRPC API.
+#[derive(Parser)]
+#[clap(name = "Pandorica", version = "0.1.0", author = "Omnilium")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Split a master key into Shamir shares, each sealed to a custodian passphrase
+    ExportMasterKey {
+        /// The master key to export [Default: the current one]
+        #[arg(long)]
+        id: Option<String>,
+        /// The number of shares needed to recover the key
+        #[arg(long)]
+        threshold: u8,
+        /// The number of shares, one per custodian
+        #[arg(long)]
+        shares: u8,
+        /// The directory to write the share files to
+        #[arg(long, default_value_t = String::from("."))]
+        out_dir: String,
+    },
+    /// Rebuild a master key from its shares and wrap it under the configured HSM key
+    RecoverMasterKey {
+        /// The share files
+        #[arg(required = true)]
+        shares: Vec<String>,
+    },
+    /// Lift the login backoff or lockout of a username or a client address
+    Unlock {
+        #[arg(long)]
+        username: Option<String>,
+        #[arg(long)]
+        client: Option<IpAddr>,
+    },
+    /// Give a user a role, one of user, auditor or admin
+    SetRole {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        role: String,
+    },
+    /// Build the breached password filter from an HIBP Pwned Passwords SHA-1 download
+    BuildBreachFilter {
+        /// The download, with one `HASH:COUNT` line per password
+        input: String,
+        /// The filter file to create
+        output: String,
+        /// The share of unbreached passwords the filter rejects anyway
+        #[arg(long, default_value_t = 0.001)]
+        false_positive_rate: f64,
+    },
+}
+
 #[tokio::main]
 async fn main() -> EmptyResult {
+    let args = Args::parse();
+
     tracing_subscriber::fmt()
         .with_env_filter(format!("pandorica={}", Settings::get().log_level))
         .with_span_events(FmtSpan::CLOSE)
         .init();
 
+    // Building the filter only works on files, so it doesn't wait for the database or the HSM
+    if let Some(Command::BuildBreachFilter {
+        input,
+        output,
+        false_positive_rate,
+    }) = &args.command
+    {
+        return commands::breach_filter::build(input, output, *false_positive_rate);
+    }
+
     let result = match Settings::get().db.proto.as_ref() {
         "ws" => {
             DB.connect::<Ws>(Settings::get().db.addr.clone().into_owned())
@@ -78,16 +161,51 @@ async fn main() -> EmptyResult {
     }
 
     {
-        let mut hsm = HsmProvider::lock().await;
-        hsm.init_provider(&Settings::get().hsm).await?;
+        let mut hsm = Hsm::lock().await;
+        hsm.init_hsm(&Settings::get().hsm).await?;
     }
+
+    // Recovery has to run before the KMS loads the current master key, which it may not be
+    // able to decrypt yet
+    match args.command {
+        Some(Command::ExportMasterKey {
+            id,
+            threshold,
+            shares,
+            out_dir,
+        }) => return commands::master_key::export(id, threshold, shares, &out_dir).await,
+        Some(Command::RecoverMasterKey { shares }) => {
+            return commands::master_key::recover(&shares).await
+        }
+        Some(Command::Unlock { username, client }) => {
+            return commands::throttle::unlock(username, client).await
+        }
+        Some(Command::SetRole { username, role }) => {
+            return commands::role::set(&username, &role).await
+        }
+        Some(Command::BuildBreachFilter { .. }) | None => {}
+    }
+
     {
         let mut kms = KeyManagementSystem::lock().await;
         kms.init_kms().await?;
     }
+    {
+        let mut opaque = Opaque::lock().await;
+        opaque.init_opaque().await?;
+    }
+    jobs::start();
+
+    // Set up front, so that a broken filter, mail provider or file store stops the start-up
+    // rather than a registration or an upload
+    BreachFilter::get();
+    Mail::get();
+    FileSystem::get();
 
     // Setup the services
+    let admin_service = AdminService::default();
     let auth_service = AuthService::default();
+    let file_service = FileService::default();
     let user_service = UserService::default();
 
     // Setup reflection
@@ -104,8 +222,22 @@ async fn main() -> EmptyResult {
 
     Server::builder()
         .add_service(reflection_service)
-        .add_service(AuthServiceServer::new(auth_service))
-        .add_service(UserServiceServer::new(user_service))
+        .add_service(AuthGuard::new(
+            AdminServiceServer::new(admin_service),
+            AdminService::access,
+        ))
+        .add_service(AuthGuard::new(
+            AuthServiceServer::new(auth_service),
+            AuthService::access,
+        ))
+        .add_service(AuthGuard::new(
+            FileServiceServer::new(file_service),
+            FileService::access,
+        ))
+        .add_service(AuthGuard::new(
+            UserServiceServer::new(user_service),
+            UserService::access,
+        ))
         .serve(addr)
         .await?;
 
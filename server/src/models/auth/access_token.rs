@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use protobuf::pandorica_common;
+use secret_vault_value::SecretValue;
+use serde::{Deserialize, Serialize};
+use shared::error::OperationResult;
+use std::borrow::Cow;
+
+use crate::models::auth::Token;
+
+/// What a personal access token may be used for. Anything not covered by a scope, like managing
+/// the account or its credentials, needs a session.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "files:read")]
+    FilesRead,
+    #[serde(rename = "files:write")]
+    FilesWrite,
+    #[serde(rename = "account:read")]
+    AccountRead,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::FilesRead, Scope::FilesWrite, Scope::AccountRead];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::FilesRead => "files:read",
+            Scope::FilesWrite => "files:write",
+            Scope::AccountRead => "account:read",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Scope> {
+        Self::ALL.into_iter().find(|s| s.as_str() == value)
+    }
+}
+
+/// A named, expiring token for automation that can only do what its scopes allow. Like session
+/// tokens, only a keyed hash of it is stored.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AccessToken<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub user_id: Cow<'a, str>,
+    pub name: Cow<'a, str>,
+    pub scopes: Vec<Scope>,
+    pub token_selector: Cow<'a, str>,
+    pub token_hash: Cow<'a, [u8]>,
+    #[serde(skip)]
+    pub token: Option<SecretValue>,
+    pub added_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+    pub last_used_on: Option<DateTime<Utc>>,
+    pub is_revoked: bool,
+}
+
+impl<'a> IntoKey for AccessToken<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> AccessToken<'a> {
+    /// Sets personal access tokens apart from session tokens, which is also what makes them
+    /// easy to spot when one leaks.
+    pub const PREFIX: &'static str = "pat_";
+
+    /// Creates an access token. The token is only available until it is stored.
+    pub fn new(
+        user_id: String,
+        name: String,
+        scopes: Vec<Scope>,
+        expires_on: DateTime<Utc>,
+    ) -> OperationResult<AccessToken<'a>> {
+        let token = Token::generate()?;
+        let value = format!("{}{}", Self::PREFIX, token.value.as_sensitive_str());
+
+        Ok(Self {
+            id: Identifier::default(),
+            user_id: user_id.into(),
+            name: name.into(),
+            scopes,
+            token_selector: token.selector.into(),
+            token_hash: token.hash.into(),
+            token: Some(SecretValue::from(value)),
+            added_on: Utc::now(),
+            expires_on,
+            last_used_on: None,
+            is_revoked: false,
+        })
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+
+    pub fn verify(&self) -> bool {
+        !self.is_revoked && self.expires_on > Utc::now()
+    }
+}
+
+impl From<AccessToken<'_>> for pandorica_common::AccessToken {
+    fn from(value: AccessToken<'_>) -> Self {
+        pandorica_common::AccessToken {
+            id: value.get_id().as_string(),
+            name: value.name.into(),
+            scopes: value.scopes.iter().map(|s| s.as_str().into()).collect(),
+            added_on: value.added_on.timestamp_micros(),
+            expires_on: value.expires_on.timestamp_micros(),
+            last_used_on: value.last_used_on.map(|l| l.timestamp_micros()),
+        }
+    }
+}
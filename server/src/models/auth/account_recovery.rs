@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::helpers::recovery;
+
+/// An account recovery between its two messages. Its ID is what the client signs with the
+/// recovery key, so that a signature can't be used twice.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AccountRecovery<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub user_id: Cow<'a, str>,
+    pub added_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+}
+
+impl<'a> IntoKey for AccountRecovery<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> AccountRecovery<'a> {
+    pub fn new(user_id: String) -> Self {
+        Self {
+            id: Identifier::default(),
+            user_id: user_id.into(),
+            added_on: Utc::now(),
+            expires_on: Utc::now() + recovery::recovery_timeout(),
+        }
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+
+    pub fn verify(&self) -> bool {
+        self.expires_on > Utc::now()
+    }
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use secret_vault_value::SecretValue;
+use serde::{Deserialize, Serialize};
+use shared::error::OperationResult;
+use std::borrow::Cow;
+
+use crate::models::auth::Token;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTokenPurpose {
+    Verification,
+    PasswordReset,
+}
+
+/// A single-use token mailed to a user, which proves that they can read the mail sent to their
+/// address. Only a keyed hash of it is stored.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EmailToken<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub user_id: Cow<'a, str>,
+    pub purpose: EmailTokenPurpose,
+    pub token_selector: Cow<'a, str>,
+    pub token_hash: Cow<'a, [u8]>,
+    #[serde(skip)]
+    pub token: Option<SecretValue>,
+    pub added_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+    pub used_on: Option<DateTime<Utc>>,
+}
+
+impl<'a> IntoKey for EmailToken<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> EmailToken<'a> {
+    /// Creates an email token. The token is only available until it is stored.
+    pub fn new(
+        user_id: String,
+        purpose: EmailTokenPurpose,
+        expires_on: DateTime<Utc>,
+    ) -> OperationResult<EmailToken<'a>> {
+        let token = Token::generate()?;
+
+        Ok(Self {
+            id: Identifier::default(),
+            user_id: user_id.into(),
+            purpose,
+            token_selector: token.selector.into(),
+            token_hash: token.hash.into(),
+            token: Some(token.value),
+            added_on: Utc::now(),
+            expires_on,
+            used_on: None,
+        })
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+
+    pub fn verify(&self) -> bool {
+        self.used_on.is_none() && self.expires_on > Utc::now()
+    }
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Failed logins counted against a username or a client address. Records exist for usernames
+/// that don't, so a lockout says nothing about whether an account does.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LoginThrottle<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub key: Cow<'a, str>,
+    pub failures: u32,
+    pub last_failure_on: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl<'a> IntoKey for LoginThrottle<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> LoginThrottle<'a> {
+    #[allow(dead_code)]
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+}
@@ -1,7 +1,21 @@
+pub use access_token::{AccessToken, Scope};
+pub use account_recovery::AccountRecovery;
+pub use email_token::{EmailToken, EmailTokenPurpose};
+pub use login_throttle::LoginThrottle;
+pub use opaque_login::OpaqueLogin;
 pub use password::Password;
-pub use session::Session;
-pub use user::User;
+pub use refresh_token::RefreshToken;
+pub use session::{ClientInfo, Session};
+pub use token::Token;
+pub use user::{Role, User};
 
+mod access_token;
+mod account_recovery;
+mod email_token;
+mod login_throttle;
+mod opaque_login;
 mod password;
+mod refresh_token;
 mod session;
+mod token;
 mod user;
@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use secret_vault_value::SecretValue;
+use serde::{Deserialize, Serialize};
+use shared::error::OperationResult;
+use std::borrow::Cow;
+
+use crate::helpers::opaque;
+use crate::models::crypto::EncryptedValue;
+
+/// An OPAQUE login between its two messages. It is used up by the second one.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OpaqueLogin<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub username: Cow<'a, str>,
+    /// Set when a user who is logged in proves their password again. Such a login ends in a
+    /// proof for the RPCs that ask for the password, rather than in a session.
+    pub user_id: Option<Cow<'a, str>>,
+    /// The server's side of the key exchange, which holds what the client has to prove.
+    pub state: EncryptedValue<'a>,
+    pub added_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+    /// When a re-authentication was finished. Its ID is the proof from then on.
+    pub verified_on: Option<DateTime<Utc>>,
+}
+
+impl<'a> IntoKey for OpaqueLogin<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> OpaqueLogin<'a> {
+    pub async fn new(
+        username: String,
+        user_id: Option<String>,
+        state: SecretValue,
+    ) -> OperationResult<OpaqueLogin<'a>> {
+        Ok(Self {
+            id: Identifier::default(),
+            username: username.into(),
+            user_id: user_id.map(|u| u.into()),
+            state: EncryptedValue::new(state, opaque::SERVER_SETUP_ID).await?,
+            added_on: Utc::now(),
+            expires_on: Utc::now() + opaque::login_timeout(),
+            verified_on: None,
+        })
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+
+    pub fn verify(&self) -> bool {
+        self.expires_on > Utc::now()
+    }
+}
@@ -1,12 +1,18 @@
 use std::borrow::Cow;
 
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier};
 use chrono::{DateTime, Utc};
 use crypto::argon2id::Argon2id;
 use foreign::IntoKey;
 use identifier::Identifier;
+use rand::rngs::OsRng;
 use secret_vault_value::SecretValue;
 use serde::{Deserialize, Serialize};
 use shared::error::OperationResult;
+use singleton::unsync::Singleton as UnsyncSingleton;
+
+use crate::config::Settings;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Password<'a> {
@@ -28,7 +34,7 @@ impl<'a> IntoKey for Password<'a> {
 
 impl<'a> Password<'a> {
     pub fn new(plaintext: SecretValue, user_id: String) -> OperationResult<Self> {
-        let hash = Argon2id::generate_hash(&plaintext)?;
+        let hash = Self::hash(&plaintext)?;
 
         Ok(Password {
             id: Identifier::default(),
@@ -45,6 +51,56 @@ impl<'a> Password<'a> {
     }
 
     pub fn verify(&self, hashed: &Password<'_>) -> OperationResult<bool> {
-        Argon2id::verify_hash(self.plaintext.as_ref().unwrap(), &hashed.hash)
+        let plaintext = self.plaintext.as_ref().unwrap();
+
+        // Hashes written before the costs became configurable aren't PHC strings, so they are
+        // left to the hasher that produced them
+        let hash = match hashed.parse_hash() {
+            Some(h) => h,
+            None => return Argon2id::verify_hash(plaintext, &hashed.hash),
+        };
+
+        Ok(Argon2::default()
+            .verify_password(plaintext.ref_sensitive_value(), &hash)
+            .is_ok())
+    }
+
+    /// Whether the hash was made with other Argon2id parameters than the configured ones.
+    pub fn needs_rehash(&self) -> bool {
+        let hash = match self.parse_hash() {
+            Some(h) => h,
+            None => return true,
+        };
+        let params = match Params::try_from(&hash) {
+            Ok(p) => p,
+            Err(_) => return true,
+        };
+
+        let settings = &Settings::get().security.password;
+        hash.algorithm != argon2::Algorithm::Argon2id.ident()
+            || params.m_cost() != settings.argon2_memory_kib
+            || params.t_cost() != settings.argon2_iterations
+            || params.p_cost() != settings.argon2_parallelism
+    }
+
+    fn parse_hash(&self) -> Option<PasswordHash<'_>> {
+        let hash = std::str::from_utf8(&self.hash).ok()?;
+        PasswordHash::new(hash).ok()
+    }
+
+    fn hash(plaintext: &SecretValue) -> OperationResult<Vec<u8>> {
+        let settings = &Settings::get().security.password;
+        let params = Params::new(
+            settings.argon2_memory_kib,
+            settings.argon2_iterations,
+            settings.argon2_parallelism,
+            None,
+        )?;
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password(plaintext.ref_sensitive_value(), &salt)?;
+
+        Ok(hash.to_string().into_bytes())
     }
 }
@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use secret_vault_value::SecretValue;
+use serde::{Deserialize, Serialize};
+use shared::error::OperationResult;
+use std::borrow::Cow;
+
+use crate::models::auth::Token;
+
+/// A single-use token that trades itself for a new session and a new refresh token. Every token
+/// descending from one login shares a family, which is revoked as a whole if a used token comes
+/// back.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RefreshToken<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub user_id: Cow<'a, str>,
+    pub family_id: Cow<'a, str>,
+    pub token_selector: Cow<'a, str>,
+    pub token_hash: Cow<'a, [u8]>,
+    #[serde(skip)]
+    pub token: Option<SecretValue>,
+    pub added_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+    pub used_on: Option<DateTime<Utc>>,
+    pub is_revoked: bool,
+}
+
+impl<'a> IntoKey for RefreshToken<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> RefreshToken<'a> {
+    pub fn new(
+        user_id: String,
+        family_id: String,
+        expires_on: DateTime<Utc>,
+    ) -> OperationResult<RefreshToken<'a>> {
+        let token = Token::generate()?;
+
+        Ok(Self {
+            id: Identifier::default(),
+            user_id: user_id.into(),
+            family_id: family_id.into(),
+            token_selector: token.selector.into(),
+            token_hash: token.hash.into(),
+            token: Some(token.value),
+            added_on: Utc::now(),
+            expires_on,
+            used_on: None,
+            is_revoked: false,
+        })
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+
+    pub fn verify(&self) -> bool {
+        !self.is_revoked && self.used_on.is_none() && self.expires_on > Utc::now()
+    }
+}
@@ -2,17 +2,54 @@ use chrono::{DateTime, Duration, Utc};
 use foreign::IntoKey;
 use identifier::Identifier;
 use protobuf::pandorica_common;
+use secret_vault_value::SecretValue;
 use serde::{Deserialize, Serialize};
+use shared::error::OperationResult;
+use singleton::unsync::Singleton as UnsyncSingleton;
 use std::borrow::Cow;
 
+use crate::config::Settings;
+use crate::models::auth::Token;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Session<'a> {
     #[serde(skip_serializing_if = "Identifier::is_none")]
     id: Identifier,
     pub user_id: Cow<'a, str>,
+    #[serde(default)]
+    pub family_id: Cow<'a, str>,
+    /// Looks the session up by the first half of its token. Sessions created before tokens
+    /// were introduced have none, so they can no longer be used.
+    #[serde(default)]
+    pub token_selector: Cow<'a, str>,
+    /// A keyed hash of the second half of the token, which is never stored.
+    #[serde(default)]
+    pub token_hash: Cow<'a, [u8]>,
+    #[serde(skip)]
+    pub token: Option<SecretValue>,
     pub added_on: DateTime<Utc>,
     pub last_used_on: DateTime<Utc>,
     pub expires_on: DateTime<Utc>,
+    #[serde(default)]
+    pub absolute_expires_on: DateTime<Utc>,
+    /// The password was accepted, but the second factor still has to be. Such a session is only
+    /// good for `AuthService::verify_mfa`.
+    #[serde(default)]
+    pub is_mfa_pending: bool,
+    #[serde(default)]
+    pub mfa_attempts: u32,
+    #[serde(default)]
+    pub client: ClientInfo<'a>,
+}
+
+/// What the client said about itself when the session was created, so that users can tell
+/// their sessions apart. None of it is verified.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ClientInfo<'a> {
+    pub address: Option<Cow<'a, str>>,
+    pub user_agent: Option<Cow<'a, str>>,
+    pub version: Option<Cow<'a, str>>,
+    pub device_name: Option<Cow<'a, str>>,
 }
 
 impl<'a> IntoKey for Session<'a> {
@@ -22,14 +59,34 @@ impl<'a> IntoKey for Session<'a> {
 }
 
 impl<'a> Session<'a> {
-    pub fn new(user_id: String) -> Self {
-        Self {
+    /// Creates a session with a fresh token. The token is only available until the session is
+    /// stored.
+    pub fn new(
+        user_id: String,
+        family_id: String,
+        absolute_expires_on: DateTime<Utc>,
+        client: ClientInfo<'a>,
+    ) -> OperationResult<Session<'a>> {
+        let token = Token::generate()?;
+
+        let mut session = Self {
             id: Identifier::default(),
             user_id: user_id.into(),
+            family_id: family_id.into(),
+            token_selector: token.selector.into(),
+            token_hash: token.hash.into(),
+            token: Some(token.value),
             added_on: Utc::now(),
             last_used_on: Utc::now(),
-            expires_on: Utc::now() + Duration::hours(8),
-        }
+            expires_on: Utc::now(),
+            absolute_expires_on,
+            is_mfa_pending: false,
+            mfa_attempts: 0,
+            client,
+        };
+        session.touch();
+
+        Ok(session)
     }
 
     pub fn get_id(&self) -> &Identifier {
@@ -39,6 +96,15 @@ impl<'a> Session<'a> {
     pub fn verify(&self) -> bool {
         self.expires_on > Utc::now()
     }
+
+    /// Marks the session as used and pushes its expiry out by the idle timeout, up to the
+    /// absolute timeout.
+    pub fn touch(&mut self) {
+        let idle_timeout = Duration::minutes(Settings::get().session.idle_timeout_minutes);
+
+        self.last_used_on = Utc::now();
+        self.expires_on = std::cmp::min(Utc::now() + idle_timeout, self.absolute_expires_on);
+    }
 }
 
 impl From<Session<'_>> for pandorica_common::Session {
@@ -49,6 +115,10 @@ impl From<Session<'_>> for pandorica_common::Session {
             added_on: value.added_on.timestamp_micros(),
             last_used_on: value.last_used_on.timestamp_micros(),
             expires_on: value.expires_on.timestamp_micros(),
+            client_address: value.client.address.map(|a| a.into()),
+            user_agent: value.client.user_agent.map(|a| a.into()),
+            client_version: value.client.version.map(|v| v.into()),
+            device_name: value.client.device_name.map(|d| d.into()),
         }
     }
 }
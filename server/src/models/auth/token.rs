@@ -0,0 +1,62 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use secret_vault_value::SecretValue;
+use shared::error::OperationResult;
+
+use crate::kms::KeyManagementSystem;
+
+const SELECTOR_SIZE: usize = 16;
+const VERIFIER_SIZE: usize = 32;
+
+/// An opaque bearer token in the form `<selector>.<verifier>`. The selector finds the record it
+/// belongs to, and only a keyed hash of the verifier is stored.
+pub struct Token {
+    pub selector: String,
+    pub hash: Vec<u8>,
+    pub value: SecretValue,
+}
+
+impl Token {
+    pub fn generate() -> OperationResult<Token> {
+        let mut selector = vec![0_u8; SELECTOR_SIZE];
+        OsRng.fill_bytes(&mut selector);
+        let mut verifier = vec![0_u8; VERIFIER_SIZE];
+        OsRng.fill_bytes(&mut verifier);
+
+        let hash = KeyManagementSystem::hash_token(&verifier)?;
+        let selector = URL_SAFE_NO_PAD.encode(selector);
+        let value = format!("{}.{}", selector, URL_SAFE_NO_PAD.encode(verifier));
+
+        Ok(Token {
+            selector,
+            hash,
+            value: SecretValue::from(value),
+        })
+    }
+
+    /// Splits a token into its selector and verifier.
+    pub fn parse(token: &str) -> Option<(&str, Vec<u8>)> {
+        let (selector, verifier) = token.split_once('.')?;
+        if selector.is_empty() {
+            return None;
+        }
+
+        let verifier = URL_SAFE_NO_PAD.decode(verifier).ok()?;
+        Some((selector, verifier))
+    }
+
+    /// Compares the hash of `verifier` with `hash` in constant time.
+    pub fn verify(verifier: &[u8], hash: &[u8]) -> OperationResult<bool> {
+        KeyManagementSystem::verify_token(verifier, hash)
+    }
+
+    /// A random ID shared by every session and refresh token that descends from one login.
+    pub fn generate_family_id() -> String {
+        let mut family_id = vec![0_u8; SELECTOR_SIZE];
+        OsRng.fill_bytes(&mut family_id);
+
+        URL_SAFE_NO_PAD.encode(family_id)
+    }
+}
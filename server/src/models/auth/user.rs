@@ -7,7 +7,7 @@ use secret_vault_value::SecretValue;
 use serde::{Deserialize, Serialize};
 use shared::error::OperationResult;
 
-use crate::models::crypto::EncryptedValue;
+use crate::models::crypto::{EncryptedValue, Keyring, RecoveryKey};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct User<'a> {
@@ -15,22 +15,82 @@ pub struct User<'a> {
     id: Identifier,
     pub username: Cow<'a, str>,
     pub email: Option<EncryptedValue<'a>>,
+    /// Set once the user follows the verification mail. Password resets are only mailed to
+    /// verified addresses.
+    #[serde(default)]
+    pub is_email_verified: bool,
+    pub kek_id: Option<Cow<'a, str>>,
     pub added_on: DateTime<Utc>,
     pub last_seen_on: DateTime<Utc>,
     pub passwords: Vec<Cow<'a, str>>,
     pub sessions: Vec<Cow<'a, str>>,
     pub is_active: bool,
+    /// Set at enrollment, but only asked for at login once `is_totp_enabled` is confirmed.
+    #[serde(default)]
+    pub totp_secret: Option<EncryptedValue<'a>>,
+    #[serde(default)]
+    pub is_totp_enabled: bool,
+    /// The time step of the last accepted TOTP code, which can't be used again.
+    #[serde(default)]
+    pub totp_last_step: i64,
+    /// Keyed hashes of the unused recovery codes.
+    #[serde(default)]
+    pub recovery_codes: Vec<Cow<'a, [u8]>>,
+    #[serde(default)]
+    pub role: Role,
+    /// Set by an admin. Until the user changes their password, their sessions can do nothing
+    /// else.
+    #[serde(default)]
+    pub is_password_reset_required: bool,
+    /// What the user registered with OPAQUE, which stands in for their password from then on.
+    /// Accounts from before OPAQUE have none until their client moves them over.
+    #[serde(default)]
+    pub opaque_registration: Option<Cow<'a, [u8]>>,
+    /// Set once the user's client starts encrypting files end to end.
+    #[serde(default)]
+    pub keyring: Option<Keyring<'a>>,
+    /// Lets the user set a new password and get their keyring back without the old password.
+    #[serde(default)]
+    pub recovery_key: Option<RecoveryKey<'a>>,
+}
+
+/// Each role can do everything the ones before it can.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    /// Can look at users and the state of the keys, but change nothing.
+    Auditor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Auditor => "auditor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        [Role::User, Role::Auditor, Role::Admin]
+            .into_iter()
+            .find(|r| r.as_str() == value)
+    }
 }
 
 impl<'a> User<'a> {
     pub async fn new(
         username: String,
         email: Option<String>,
-        password_id: String,
+        kek_id: String,
+        password_id: Option<String>,
         session_id: String,
     ) -> OperationResult<User<'a>> {
         let email = match email {
-            Some(e) => Some(EncryptedValue::new(SecretValue::from(e)).await?),
+            Some(e) => Some(EncryptedValue::new(SecretValue::from(e), &kek_id).await?),
             None => None,
         };
 
@@ -38,11 +98,22 @@ impl<'a> User<'a> {
             id: Identifier::default(),
             username: username.into(),
             email,
+            is_email_verified: false,
+            kek_id: Some(kek_id.into()),
             added_on: Utc::now(),
             last_seen_on: Utc::now(),
-            passwords: vec![password_id.into()],
+            passwords: password_id.into_iter().map(|p| p.into()).collect(),
             sessions: vec![session_id.into()],
             is_active: true,
+            totp_secret: None,
+            is_totp_enabled: false,
+            totp_last_step: 0,
+            recovery_codes: Vec::new(),
+            role: Role::default(),
+            is_password_reset_required: false,
+            opaque_registration: None,
+            keyring: None,
+            recovery_key: None,
         })
     }
 
@@ -62,6 +133,9 @@ impl From<User<'_>> for pandorica_common::User {
             added_on: value.added_on.timestamp_micros(),
             last_seen_on: value.last_seen_on.timestamp_micros(),
             is_active: value.is_active,
+            role: value.role.as_str().into(),
+            is_password_reset_required: value.is_password_reset_required,
+            is_email_verified: value.is_email_verified,
         }
     }
 }
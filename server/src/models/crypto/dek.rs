@@ -1,6 +1,7 @@
 use secret_vault_value::SecretValue;
 use std::borrow::Cow;
 
+use crate::kms::cipher::Algorithm;
 use serde::{Deserialize, Serialize};
 use serde_binary::binary_stream::Endian;
 use shared::error::OperationResult;
@@ -10,23 +11,36 @@ pub struct Dek<'a> {
     pub key: Cow<'a, [u8]>,
     pub nonce: Cow<'a, [u8]>,
     pub wrapping_nonce: Cow<'a, [u8]>,
-    pub master_key_id: Cow<'a, str>,
+    /// Either a `key_encryption_key` or, for DEKs created before per-user keys, a `master_key`.
+    pub wrapping_key_id: Cow<'a, str>,
+    pub wrapping_algorithm: Algorithm,
     #[serde(skip)]
     pub decoded_key: SecretValue,
 }
 
+/// The encoding of DEKs written before the wrapping algorithm was recorded.
+#[derive(Deserialize)]
+struct UntaggedDek<'a> {
+    key: Cow<'a, [u8]>,
+    nonce: Cow<'a, [u8]>,
+    wrapping_nonce: Cow<'a, [u8]>,
+    wrapping_key_id: Cow<'a, str>,
+}
+
 impl<'a> Dek<'a> {
     pub fn new(
         key: SecretValue,
         nonce: Vec<u8>,
-        master_key_id: String,
+        wrapping_key_id: String,
+        wrapping_algorithm: Algorithm,
         wrapping_nonce: Vec<u8>,
         wrapped_key: Vec<u8>,
     ) -> Self {
         Self {
             decoded_key: key,
             nonce: nonce.into(),
-            master_key_id: master_key_id.into(),
+            wrapping_key_id: wrapping_key_id.into(),
+            wrapping_algorithm,
             wrapping_nonce: wrapping_nonce.into(),
             key: wrapped_key.into(),
         }
@@ -37,6 +51,18 @@ impl<'a> Dek<'a> {
     }
 
     pub fn from_bytes(encoded: &[u8]) -> OperationResult<Self> {
-        serde_binary::from_slice(encoded, Endian::Big).map_err(|e| e.into())
+        if let Ok(dek) = serde_binary::from_slice(encoded, Endian::Big) {
+            return Ok(dek);
+        }
+
+        let dek: UntaggedDek = serde_binary::from_slice(encoded, Endian::Big)?;
+        Ok(Self {
+            key: Cow::Owned(dek.key.into_owned()),
+            nonce: Cow::Owned(dek.nonce.into_owned()),
+            wrapping_nonce: Cow::Owned(dek.wrapping_nonce.into_owned()),
+            wrapping_key_id: Cow::Owned(dek.wrapping_key_id.into_owned()),
+            wrapping_algorithm: Algorithm::default(),
+            decoded_key: SecretValue::default(),
+        })
     }
 }
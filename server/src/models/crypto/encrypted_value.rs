@@ -1,17 +1,20 @@
 use std::borrow::Cow;
 
+use crate::config::Settings;
+use crate::kms::cipher::{Algorithm, Cipher};
 use crate::kms::KeyManagementSystem;
 use crate::models::crypto::Dek;
-use crypto::chacha20poly1305::ChaCha20Poly1305;
 use secret_vault_value::SecretValue;
 use serde::{Deserialize, Serialize};
 use shared::error::{EmptyResult, OperationResult};
-use singleton::sync::Singleton;
+use singleton::{sync::Singleton, unsync::Singleton as UnsyncSingleton};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct EncryptedValue<'a> {
     value: Cow<'a, [u8]>,
     dek: Cow<'a, [u8]>,
+    #[serde(default)]
+    algorithm: Algorithm,
     #[serde(skip)]
     is_decoded: bool,
     #[serde(skip)]
@@ -21,18 +24,24 @@ pub struct EncryptedValue<'a> {
 }
 
 impl<'a> EncryptedValue<'a> {
-    pub async fn new(value: SecretValue) -> OperationResult<EncryptedValue<'a>> {
+    pub async fn new(value: SecretValue, kek_id: &str) -> OperationResult<EncryptedValue<'a>> {
+        let algorithm = Settings::get().kms.algorithm;
         let dek: Dek;
         {
             let kms = KeyManagementSystem::lock().await;
-            dek = kms.generate_dek().await?;
+            dek = kms.generate_dek(kek_id, algorithm).await?;
         }
 
-        let encrypted_value = ChaCha20Poly1305::encrypt(&value, &dek.decoded_key, &dek.nonce)?;
+        let encrypted_value = Cipher::new(algorithm, &dek.decoded_key)?.encrypt(
+            &dek.nonce,
+            value.ref_sensitive_value(),
+            &[],
+        )?;
 
         Ok(EncryptedValue {
             value: encrypted_value.into(),
             dek: dek.to_bytes()?.into(),
+            algorithm,
             is_decoded: true,
             decoded_value: value,
             decoded_dek: dek,
@@ -46,17 +55,34 @@ impl<'a> EncryptedValue<'a> {
             kms.decrypt_dek(&mut self.decoded_dek).await?;
         }
 
-        self.decoded_value = ChaCha20Poly1305::decrypt(
-            &self.value,
-            &self.decoded_dek.decoded_key,
+        let value = Cipher::new(self.algorithm, &self.decoded_dek.decoded_key)?.decrypt(
             &self.decoded_dek.nonce,
+            &self.value,
+            &[],
         )?;
+        self.decoded_value = SecretValue::from(value);
 
         self.is_decoded = true;
 
         Ok(())
     }
 
+    /// Moves a DEK that predates per-user keys under the key encryption key `kek_id`. The value
+    /// itself is left untouched.
+    pub async fn rewrap(&mut self, kek_id: &str) -> OperationResult<bool> {
+        let mut dek = Dek::from_bytes(&self.dek)?;
+        {
+            let kms = KeyManagementSystem::lock().await;
+            if !kms.rewrap_dek(&mut dek, kek_id).await? {
+                return Ok(false);
+            }
+        }
+
+        self.dek = dek.to_bytes()?.into();
+
+        Ok(true)
+    }
+
     pub fn value(&self) -> Option<&SecretValue> {
         if !self.is_decoded {
             return None;
@@ -0,0 +1,47 @@
+use std::borrow::Cow;
+
+use crate::kms::cipher::Algorithm;
+use chrono::{DateTime, Utc};
+use identifier::Identifier;
+use secret_vault_value::SecretValue;
+use serde::{Deserialize, Serialize};
+
+/// A per-user key encryption key. It is wrapped by a master key and wraps every DEK of the user
+/// it belongs to, so deleting it makes all of that user's data unrecoverable.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Kek<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub added_on: DateTime<Utc>,
+    pub master_key_id: Cow<'a, str>,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    pub wrapping_nonce: Cow<'a, [u8]>,
+    pub key: Cow<'a, [u8]>,
+    #[serde(skip)]
+    pub decoded_key: Option<SecretValue>,
+}
+
+impl<'a> Kek<'a> {
+    pub fn new(
+        key: SecretValue,
+        master_key_id: String,
+        algorithm: Algorithm,
+        wrapping_nonce: Vec<u8>,
+        wrapped_key: Vec<u8>,
+    ) -> Self {
+        Kek {
+            id: Identifier::default(),
+            added_on: Utc::now(),
+            master_key_id: master_key_id.into(),
+            algorithm,
+            wrapping_nonce: wrapping_nonce.into(),
+            key: wrapped_key.into(),
+            decoded_key: Some(key),
+        }
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+}
@@ -0,0 +1,58 @@
+use protobuf::pandorica_common;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// A user's X25519 and Ed25519 key pairs for end-to-end encryption. The private keys are
+/// sealed by the client with a key it derives from the password with scrypt, so the server only
+/// ever stores them encrypted, and can do nothing with them.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Keyring<'a> {
+    /// What file DEKs are wrapped to.
+    pub public_key: Cow<'a, [u8]>,
+    /// What the wrapped DEKs are signed with, so that nobody else can wrap one to `public_key`.
+    pub signing_public_key: Cow<'a, [u8]>,
+    pub salt: Cow<'a, [u8]>,
+    pub scrypt_log_n: u8,
+    pub scrypt_r: u32,
+    pub scrypt_p: u32,
+    pub nonce: Cow<'a, [u8]>,
+    pub encrypted_private_keys: Cow<'a, [u8]>,
+}
+
+impl<'a> Keyring<'a> {
+    /// Whether both hold the same key pairs, which may only be sealed again, never replaced, as
+    /// long as there are files wrapped to them.
+    pub fn has_same_keys(&self, other: &Keyring<'_>) -> bool {
+        self.public_key == other.public_key && self.signing_public_key == other.signing_public_key
+    }
+}
+
+impl From<pandorica_common::Keyring> for Keyring<'_> {
+    fn from(value: pandorica_common::Keyring) -> Self {
+        Keyring {
+            public_key: value.public_key.into(),
+            signing_public_key: value.signing_public_key.into(),
+            salt: value.salt.into(),
+            scrypt_log_n: value.scrypt_log_n as u8,
+            scrypt_r: value.scrypt_r,
+            scrypt_p: value.scrypt_p,
+            nonce: value.nonce.into(),
+            encrypted_private_keys: value.encrypted_private_keys.into(),
+        }
+    }
+}
+
+impl From<Keyring<'_>> for pandorica_common::Keyring {
+    fn from(value: Keyring<'_>) -> Self {
+        pandorica_common::Keyring {
+            public_key: value.public_key.into(),
+            signing_public_key: value.signing_public_key.into(),
+            salt: value.salt.into(),
+            scrypt_log_n: value.scrypt_log_n.into(),
+            scrypt_r: value.scrypt_r,
+            scrypt_p: value.scrypt_p,
+            nonce: value.nonce.into(),
+            encrypted_private_keys: value.encrypted_private_keys.into(),
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use std::borrow::Cow;
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Utc};
 use identifier::Identifier;
+use protobuf::pandorica_admin;
 use secret_vault_value::SecretValue;
 use serde::{Deserialize, Serialize};
 
@@ -18,11 +19,11 @@ pub struct Mk<'a> {
 }
 
 impl<'a> Mk<'a> {
-    pub fn new(key: Vec<u8>) -> Self {
+    pub fn new(key: Vec<u8>, expires_on: DateTime<Utc>) -> Self {
         Mk {
             id: Identifier::default(),
             added_on: Utc::now(),
-            expires_on: Utc::now() + Duration::days(90),
+            expires_on,
             is_active: true,
             key: key.into(),
             decoded_key: None,
@@ -33,3 +34,14 @@ impl<'a> Mk<'a> {
         &self.id
     }
 }
+
+impl From<Mk<'_>> for pandorica_admin::MasterKey {
+    fn from(value: Mk<'_>) -> Self {
+        pandorica_admin::MasterKey {
+            id: value.get_id().as_string(),
+            added_on: value.added_on.timestamp_micros(),
+            expires_on: value.expires_on.timestamp_micros(),
+            is_active: value.is_active,
+        }
+    }
+}
@@ -1,7 +1,17 @@
 pub use crate::models::crypto::dek::Dek;
 pub use crate::models::crypto::encrypted_value::EncryptedValue;
+pub use crate::models::crypto::kek::Kek;
+pub use crate::models::crypto::keyring::Keyring;
 pub use crate::models::crypto::mk::Mk;
+pub use crate::models::crypto::recovery_key::RecoveryKey;
+pub use crate::models::crypto::rewrap_job::RewrapJob;
+pub use crate::models::crypto::server_secret::ServerSecret;
 
 mod dek;
 mod encrypted_value;
+mod kek;
+mod keyring;
 mod mk;
+mod recovery_key;
+mod rewrap_job;
+mod server_secret;
@@ -0,0 +1,33 @@
+use protobuf::pandorica_common;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// A second copy of a user's keyring private keys, sealed by the client with a key derived from
+/// the recovery key it showed the user once. The Ed25519 key derived from the same recovery key
+/// is how the user proves they have it, so the server never learns the recovery key itself.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecoveryKey<'a> {
+    pub verifying_key: Cow<'a, [u8]>,
+    pub nonce: Cow<'a, [u8]>,
+    pub encrypted_private_keys: Cow<'a, [u8]>,
+}
+
+impl From<pandorica_common::RecoveryKey> for RecoveryKey<'_> {
+    fn from(value: pandorica_common::RecoveryKey) -> Self {
+        RecoveryKey {
+            verifying_key: value.verifying_key.into(),
+            nonce: value.nonce.into(),
+            encrypted_private_keys: value.encrypted_private_keys.into(),
+        }
+    }
+}
+
+impl From<RecoveryKey<'_>> for pandorica_common::RecoveryKey {
+    fn from(value: RecoveryKey<'_>) -> Self {
+        pandorica_common::RecoveryKey {
+            verifying_key: value.verifying_key.into(),
+            nonce: value.nonce.into(),
+            encrypted_private_keys: value.encrypted_private_keys.into(),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RewrapJob<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub master_key_id: Cow<'a, str>,
+    pub started_on: DateTime<Utc>,
+    pub completed_on: Option<DateTime<Utc>>,
+    pub kek_cursor: Option<Cow<'a, str>>,
+    pub user_cursor: Option<Cow<'a, str>>,
+    pub file_cursor: Option<Cow<'a, str>>,
+    pub rewrapped_keys: u64,
+    pub rewrapped_values: u64,
+    pub rewrapped_files: u64,
+}
+
+impl<'a> IntoKey for RewrapJob<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> RewrapJob<'a> {
+    pub fn new(master_key_id: String) -> Self {
+        Self {
+            id: Identifier::default(),
+            master_key_id: master_key_id.into(),
+            started_on: Utc::now(),
+            completed_on: None,
+            kek_cursor: None,
+            user_cursor: None,
+            file_cursor: None,
+            rewrapped_keys: 0,
+            rewrapped_values: 0,
+            rewrapped_files: 0,
+        }
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+
+    /// Points a job that is still pending to `master_key_id` and starts it over. Everything it
+    /// re-wrapped so far is under a retired key again, so its progress is reset as well.
+    pub fn restart(&mut self, master_key_id: String) {
+        *self = Self {
+            id: self.id.clone(),
+            ..Self::new(master_key_id)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_resets_progress() {
+        let mut job = RewrapJob::new("master_key:old".to_string());
+        job.kek_cursor = Some("key_encryption_key:a".into());
+        job.user_cursor = Some("user:b".into());
+        job.file_cursor = Some("file:c".into());
+        job.rewrapped_keys = 3;
+        job.rewrapped_values = 5;
+        job.rewrapped_files = 7;
+
+        job.restart("master_key:new".to_string());
+
+        assert_eq!(job.master_key_id, "master_key:new");
+        assert!(job.completed_on.is_none());
+        assert!(job.kek_cursor.is_none());
+        assert!(job.user_cursor.is_none());
+        assert!(job.file_cursor.is_none());
+        assert_eq!(job.rewrapped_keys, 0);
+        assert_eq!(job.rewrapped_values, 0);
+        assert_eq!(job.rewrapped_files, 0);
+    }
+}
@@ -0,0 +1,28 @@
+use foreign::IntoKey;
+use identifier::Identifier;
+use serde::{Deserialize, Serialize};
+
+use crate::models::crypto::EncryptedValue;
+
+/// A secret that belongs to the server itself rather than to a user, stored under a fixed ID.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ServerSecret<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub value: EncryptedValue<'a>,
+}
+
+impl<'a> IntoKey for ServerSecret<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> ServerSecret<'a> {
+    pub fn new(value: EncryptedValue<'a>) -> Self {
+        Self {
+            id: Identifier::default(),
+            value,
+        }
+    }
+}
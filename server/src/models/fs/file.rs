@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use foreign::IntoKey;
+use identifier::Identifier;
+use protobuf::pandorica_file;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct File<'a> {
+    #[serde(skip_serializing_if = "Identifier::is_none")]
+    id: Identifier,
+    pub user_id: Cow<'a, str>,
+    pub name: Cow<'a, str>,
+    pub size: u64,
+    pub added_on: DateTime<Utc>,
+    /// Set for a file the client encrypted end to end, with a DEK that only the user's keyring
+    /// can unwrap. The server encrypts what it gets all the same.
+    #[serde(default)]
+    pub wrapped_dek: Option<Cow<'a, [u8]>>,
+    /// Set until the upload finishes. The content may not be stored yet, or only in part.
+    #[serde(default)]
+    pub is_uploading: bool,
+    /// The multipart upload the content is being written to, so that an upload that never
+    /// finishes can be aborted.
+    #[serde(default)]
+    pub multipart_id: Option<Cow<'a, str>>,
+}
+
+impl<'a> IntoKey for File<'a> {
+    fn get_key(&self) -> String {
+        self.id.as_string()
+    }
+}
+
+impl<'a> File<'a> {
+    pub fn new(user_id: String, name: String) -> Self {
+        Self {
+            id: Identifier::default(),
+            user_id: user_id.into(),
+            name: name.into(),
+            size: 0,
+            added_on: Utc::now(),
+            wrapped_dek: None,
+            is_uploading: true,
+            multipart_id: None,
+        }
+    }
+
+    pub fn get_id(&self) -> &Identifier {
+        &self.id
+    }
+
+    pub fn location(&self) -> String {
+        format!(
+            "{}/{}",
+            self.user_id.split(':').last().unwrap(),
+            self.get_id().partial_identifier()
+        )
+    }
+}
+
+impl From<File<'_>> for pandorica_file::File {
+    fn from(value: File<'_>) -> Self {
+        pandorica_file::File {
+            id: value.get_id().as_string(),
+            name: value.name.into(),
+            size: value.size,
+            added_on: value.added_on.timestamp_micros(),
+            wrapped_dek: value.wrapped_dek.map(|w| w.into()),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub use file::File;
+
+mod file;
@@ -0,0 +1,90 @@
+use shared::error::{EmptyResult, OperationResult};
+
+use crate::models::auth::AccessToken;
+use crate::DB;
+
+pub async fn create(access_token: AccessToken<'_>) -> OperationResult<AccessToken> {
+    let access_token: AccessToken = DB.create("access_token").content(access_token).await?;
+    Ok(access_token)
+}
+
+pub async fn read(id: &str) -> OperationResult<Option<AccessToken>> {
+    let access_token: Option<AccessToken> = DB.select(("access_token", id)).await?;
+    Ok(access_token)
+}
+
+pub async fn read_by_token_selector<'a>(
+    selector: &str,
+) -> OperationResult<Option<AccessToken<'a>>> {
+    let access_token: Option<AccessToken> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM access_token
+    WHERE token_selector = $token_selector
+    "#,
+        )
+        .bind(("token_selector", selector))
+        .await?
+        .take(0)?;
+
+    Ok(access_token)
+}
+
+pub async fn read_all_by_user_id(user_id: &str) -> OperationResult<Vec<AccessToken>> {
+    let access_tokens: Vec<AccessToken> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM access_token
+    WHERE user_id = $user_id
+    ORDER BY added_on
+    "#,
+        )
+        .bind(("user_id", user_id))
+        .await?
+        .take(0)?;
+
+    Ok(access_tokens)
+}
+
+pub async fn update(access_token: &AccessToken<'_>) -> EmptyResult {
+    if access_token.get_id().is_none() {
+        return Err(anyhow::format_err!("Access token ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE access_token
+    SET last_used_on = $last_used_on,
+        is_revoked = $is_revoked
+    WHERE id = $id
+    "#,
+    )
+    .bind(("last_used_on", access_token.last_used_on))
+    .bind(("is_revoked", access_token.is_revoked))
+    .bind(("id", access_token.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes `last_used_on` alone, so that it can't undo a revocation made in the meantime.
+pub async fn update_last_used_on(access_token: &AccessToken<'_>) -> EmptyResult {
+    if access_token.get_id().is_none() {
+        return Err(anyhow::format_err!("Access token ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE access_token
+    SET last_used_on = $last_used_on
+    WHERE id = $id
+    "#,
+    )
+    .bind(("last_used_on", access_token.last_used_on))
+    .bind(("id", access_token.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
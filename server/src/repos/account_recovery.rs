@@ -0,0 +1,25 @@
+use shared::error::OperationResult;
+
+use crate::models::auth::AccountRecovery;
+use crate::DB;
+
+pub async fn create(recovery: AccountRecovery<'_>) -> OperationResult<AccountRecovery> {
+    let recovery: AccountRecovery = DB.create("account_recovery").content(recovery).await?;
+    Ok(recovery)
+}
+
+/// Deletes the recovery and returns it, so that each one can only be finished once.
+pub async fn take<'a>(id: &str) -> OperationResult<Option<AccountRecovery<'a>>> {
+    let recovery: Option<AccountRecovery> = DB
+        .query(
+            r#"
+    DELETE type::thing("account_recovery", $id)
+    RETURN BEFORE
+    "#,
+        )
+        .bind(("id", id))
+        .await?
+        .take(0)?;
+
+    Ok(recovery)
+}
@@ -0,0 +1,84 @@
+use shared::error::{EmptyResult, OperationResult};
+
+use crate::models::auth::{EmailToken, EmailTokenPurpose};
+use crate::DB;
+
+pub async fn create(email_token: EmailToken<'_>) -> OperationResult<EmailToken> {
+    let email_token: EmailToken = DB.create("email_token").content(email_token).await?;
+    Ok(email_token)
+}
+
+pub async fn read_by_token_selector<'a>(selector: &str) -> OperationResult<Option<EmailToken<'a>>> {
+    let email_token: Option<EmailToken> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM email_token
+    WHERE token_selector = $token_selector
+    "#,
+        )
+        .bind(("token_selector", selector))
+        .await?
+        .take(0)?;
+
+    Ok(email_token)
+}
+
+pub async fn read_latest_by_user_id<'a>(
+    user_id: &str,
+    purpose: EmailTokenPurpose,
+) -> OperationResult<Option<EmailToken<'a>>> {
+    let email_token: Option<EmailToken> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM email_token
+    WHERE user_id = $user_id AND purpose = $purpose
+    ORDER BY added_on DESC
+    LIMIT 1
+    "#,
+        )
+        .bind(("user_id", user_id))
+        .bind(("purpose", purpose))
+        .await?
+        .take(0)?;
+
+    Ok(email_token)
+}
+
+/// Marks the token as used, unless it already was. Returns whether it was this call that used it.
+pub async fn mark_used(email_token: &EmailToken<'_>) -> OperationResult<bool> {
+    if email_token.get_id().is_none() {
+        return Err(anyhow::format_err!("Email token ID is required").into());
+    }
+
+    let updated: Vec<EmailToken> = DB
+        .query(
+            r#"
+    UPDATE email_token
+    SET used_on = time::now()
+    WHERE id = $id AND used_on = NONE
+    "#,
+        )
+        .bind(("id", email_token.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Uses up every token of the user for `purpose`, so that only the one sent last works.
+pub async fn revoke_all_by_user_id(user_id: &str, purpose: EmailTokenPurpose) -> EmptyResult {
+    DB.query(
+        r#"
+    UPDATE email_token
+    SET used_on = time::now()
+    WHERE user_id = $user_id AND purpose = $purpose AND used_on = NONE
+    "#,
+    )
+    .bind(("user_id", user_id))
+    .bind(("purpose", purpose))
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use shared::error::{EmptyResult, OperationResult};
+
+use crate::models::fs::File;
+use crate::DB;
+
+pub async fn create(file: File<'_>) -> OperationResult<File> {
+    let file: File = DB.create("file").content(file).await?;
+    Ok(file)
+}
+
+pub async fn read(id: &str) -> OperationResult<Option<File>> {
+    let file: Option<File> = DB.select(("file", id)).await?;
+    Ok(file)
+}
+
+pub async fn read_all_by_user_id(user_id: &str) -> OperationResult<Vec<File>> {
+    let files: Vec<File> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM file
+    WHERE user_id = $user_id
+    "#,
+        )
+        .bind(("user_id", user_id))
+        .await?
+        .take(0)?;
+
+    Ok(files)
+}
+
+/// Pages through the files whose upload has finished.
+pub async fn read_batch_after<'a>(
+    cursor: Option<&str>,
+    limit: u32,
+) -> OperationResult<Vec<File<'a>>> {
+    let files: Vec<File> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM file
+    WHERE ($cursor = NONE OR id > type::thing("file", $cursor))
+        AND is_uploading != true
+    ORDER BY id
+    LIMIT $limit
+    "#,
+        )
+        .bind(("cursor", cursor))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(files)
+}
+
+/// Reads the files whose upload started before `added_before` and still hasn't finished.
+pub async fn read_stale_uploads<'a>(added_before: DateTime<Utc>) -> OperationResult<Vec<File<'a>>> {
+    let files: Vec<File> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM file
+    WHERE is_uploading = true
+        AND added_on < $added_before
+    "#,
+        )
+        .bind(("added_before", added_before))
+        .await?
+        .take(0)?;
+
+    Ok(files)
+}
+
+pub async fn update(file: &File<'_>) -> EmptyResult {
+    if file.get_id().is_none() {
+        return Err(anyhow::format_err!("File ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE file
+    SET name = $name,
+        size = $size,
+        wrapped_dek = $wrapped_dek,
+        is_uploading = $is_uploading,
+        multipart_id = $multipart_id
+    WHERE id = $id
+    "#,
+    )
+    .bind(("name", &file.name))
+    .bind(("size", file.size))
+    .bind(("wrapped_dek", &file.wrapped_dek))
+    .bind(("is_uploading", file.is_uploading))
+    .bind(("multipart_id", &file.multipart_id))
+    .bind(("id", file.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete(id: &str) -> EmptyResult {
+    DB.delete(("file", id)).await?;
+    Ok(())
+}
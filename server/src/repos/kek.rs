@@ -0,0 +1,71 @@
+use crate::models::crypto::Kek;
+use crate::DB;
+use shared::error::{EmptyResult, OperationResult};
+
+pub async fn create(kek: Kek<'_>) -> OperationResult<Kek> {
+    let kek: Kek = DB.create("key_encryption_key").content(kek).await?;
+    Ok(kek)
+}
+
+/// Creates a key encryption key under a fixed ID, for keys that belong to the server itself.
+pub async fn create_with_id<'a>(id: &str, kek: Kek<'_>) -> OperationResult<Kek<'a>> {
+    let kek: Kek = DB.create(("key_encryption_key", id)).content(kek).await?;
+    Ok(kek)
+}
+
+pub async fn read<'a>(id: &str) -> OperationResult<Option<Kek<'a>>> {
+    let kek: Option<Kek> = DB.select(("key_encryption_key", id)).await?;
+    Ok(kek)
+}
+
+pub async fn read_batch_after<'a>(
+    cursor: Option<&str>,
+    limit: u32,
+) -> OperationResult<Vec<Kek<'a>>> {
+    let keks: Vec<Kek> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM key_encryption_key
+    WHERE $cursor = NONE OR id > type::thing("key_encryption_key", $cursor)
+    ORDER BY id
+    LIMIT $limit
+    "#,
+        )
+        .bind(("cursor", cursor))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(keks)
+}
+
+pub async fn delete(id: &str) -> EmptyResult {
+    DB.delete(("key_encryption_key", id)).await?;
+    Ok(())
+}
+
+pub async fn update(kek: &Kek<'_>) -> EmptyResult {
+    if kek.get_id().is_none() {
+        return Err(anyhow::format_err!("Key encryption key ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE key_encryption_key
+    SET master_key_id = $master_key_id,
+        algorithm = $algorithm,
+        wrapping_nonce = $wrapping_nonce,
+        key = $key
+    WHERE id = $id
+    "#,
+    )
+    .bind(("master_key_id", &kek.master_key_id))
+    .bind(("algorithm", kek.algorithm))
+    .bind(("wrapping_nonce", &kek.wrapping_nonce))
+    .bind(("key", &kek.key))
+    .bind(("id", kek.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
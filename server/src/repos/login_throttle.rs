@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use shared::error::{EmptyResult, OperationResult};
+
+use crate::config::LoginSettings;
+use crate::models::auth::LoginThrottle;
+use crate::DB;
+
+/// Counts a failed login against `key` and sets how long the next one has to wait by `settings`,
+/// unless `key` is already waiting. Returns whether it was counted. Failures from before
+/// `window_start` are forgotten first.
+///
+/// It is a single statement, so that concurrent attempts are all counted and each one sees the
+/// wait set by the one before it. The fields are set in order, so the wait is computed from the
+/// new failure count.
+pub async fn record_attempt(
+    key: &str,
+    window_start: DateTime<Utc>,
+    lockout_after_failures: u32,
+    settings: &LoginSettings,
+) -> OperationResult<bool> {
+    let throttle: Option<LoginThrottle> = DB
+        .query(
+            r#"
+    UPDATE type::thing("login_throttle", $key)
+    SET key = $key,
+        failures = IF last_failure_on = NONE OR last_failure_on < $window_start
+            THEN 1
+            ELSE failures + 1
+        END,
+        last_failure_on = time::now(),
+        locked_until = IF failures >= $lockout_after_failures
+            THEN time::now() + duration::from::mins($lockout_minutes)
+        ELSE IF failures >= $backoff_after_failures
+            THEN time::now() + duration::from::secs(math::min([
+                $backoff_base_seconds
+                    * math::pow(2, math::min([failures - $backoff_after_failures, 32])),
+                $backoff_max_seconds
+            ]))
+        ELSE
+            NONE
+        END
+    WHERE locked_until = NONE OR locked_until <= time::now()
+    RETURN AFTER
+    "#,
+        )
+        .bind(("key", key))
+        .bind(("window_start", window_start))
+        .bind(("lockout_after_failures", lockout_after_failures))
+        .bind(("lockout_minutes", settings.lockout_minutes))
+        .bind(("backoff_after_failures", settings.backoff_after_failures))
+        .bind(("backoff_base_seconds", settings.backoff_base_seconds))
+        .bind(("backoff_max_seconds", settings.backoff_max_seconds))
+        .await?
+        .take(0)?;
+
+    Ok(throttle.is_some())
+}
+
+/// Takes back one failure counted against `key`.
+pub async fn forgive(key: &str) -> EmptyResult {
+    DB.query(
+        r#"
+    UPDATE type::thing("login_throttle", $key)
+    SET failures = failures - 1
+    WHERE failures > 0
+    "#,
+    )
+    .bind(("key", key))
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the records whose failures are all from before `window_start` and that aren't
+/// waiting anymore, most of which are for usernames that don't exist.
+pub async fn delete_stale(window_start: DateTime<Utc>) -> EmptyResult {
+    DB.query(
+        r#"
+    DELETE login_throttle
+    WHERE last_failure_on < $window_start
+        AND (locked_until = NONE OR locked_until <= time::now())
+    "#,
+    )
+    .bind(("window_start", window_start))
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_by_key(key: &str) -> EmptyResult {
+    DB.query(
+        r#"
+    DELETE type::thing("login_throttle", $key)
+    "#,
+    )
+    .bind(("key", key))
+    .await?;
+
+    Ok(())
+}
@@ -1,13 +1,62 @@
-use crate::models::crypto::Mk;
+use crate::models::crypto::{Mk, RewrapJob};
+use crate::repos::rewrap_job;
 use crate::{EmptyResult, DB};
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
 use shared::error::OperationResult;
 use surrealdb::opt::PatchOp;
 
+const ID_SIZE: usize = 20;
+
 pub async fn create(mk: Mk<'_>) -> OperationResult<Mk> {
     let mk: Mk = DB.create("master_key").content(mk).await?;
     Ok(mk)
 }
 
+/// Stores `mk` as the active master key in place of `previous`, along with the job that
+/// re-wraps everything under `previous`. A job that is still pending is restarted instead.
+///
+/// The new key and the job are written first and `previous` is deactivated last, in one
+/// transaction, so that the server is never left without an active key or with a new one that
+/// nothing gets re-wrapped to. Their IDs are picked here, since the job has to name the key.
+pub async fn replace<'a>(previous: &Mk<'_>, mk: Mk<'_>) -> OperationResult<Mk<'a>> {
+    if previous.get_id().is_none() {
+        return Err(anyhow::format_err!("Master key ID is required").into());
+    }
+
+    let id = generate_id();
+    let master_key_id = format!("master_key:{}", id);
+    let (job_id, job) = match rewrap_job::read_pending().await? {
+        Some(mut job) => {
+            job.restart(master_key_id);
+            let job_id = job.get_id().as_string();
+            (job_id.split(':').last().unwrap().to_string(), job)
+        }
+        None => (generate_id(), RewrapJob::new(master_key_id)),
+    };
+
+    let mk: Option<Mk> = DB
+        .query(
+            r#"
+    BEGIN TRANSACTION;
+    CREATE type::thing("master_key", $id) CONTENT $master_key;
+    UPDATE type::thing("rewrap_job", $job_id) CONTENT $job;
+    UPDATE type::thing("master_key", $previous_id) SET is_active = false;
+    COMMIT TRANSACTION;
+    "#,
+        )
+        .bind(("id", &id))
+        .bind(("master_key", mk))
+        .bind(("job_id", job_id))
+        .bind(("job", job))
+        .bind(("previous_id", previous.get_id().partial_identifier()))
+        .await?
+        .take(0)?;
+
+    mk.ok_or_else(|| anyhow::Error::msg("master_key_not_found").into())
+}
+
 pub async fn read<'a>(id: &str) -> OperationResult<Option<Mk<'a>>> {
     let mk: Option<Mk> = DB.select(("master_key", id)).await?;
     Ok(mk)
@@ -29,6 +78,22 @@ pub async fn read_current<'a>() -> OperationResult<Option<Mk<'a>>> {
     Ok(mk)
 }
 
+pub async fn read_all<'a>() -> OperationResult<Vec<Mk<'a>>> {
+    let mut result = DB
+        .query(
+            r#"
+            SELECT *
+            FROM master_key
+            ORDER BY added_on
+        "#,
+        )
+        .await?;
+
+    let mks: Vec<Mk> = result.take(0)?;
+
+    Ok(mks)
+}
+
 pub async fn update(mk: &Mk<'_>) -> EmptyResult {
     if mk.get_id().is_none() {
         return Err(anyhow::format_err!("Password ID is required").into());
@@ -37,6 +102,7 @@ pub async fn update(mk: &Mk<'_>) -> EmptyResult {
     DB.update(("master_key", mk.get_id().partial_identifier()))
         .patch(PatchOp::replace("/expires_on", mk.expires_on))
         .patch(PatchOp::replace("/is_active", mk.is_active))
+        .patch(PatchOp::replace("/key", &mk.key))
         .await?;
 
     Ok(())
@@ -47,3 +113,11 @@ pub async fn delete(id: &str) -> EmptyResult {
     DB.delete(("master_key", id)).await?;
     Ok(())
 }
+
+fn generate_id() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(ID_SIZE)
+        .map(char::from)
+        .collect()
+}
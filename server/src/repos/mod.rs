@@ -1,4 +1,14 @@
+pub mod access_token;
+pub mod account_recovery;
+pub mod email_token;
+pub mod file;
+pub mod kek;
+pub mod login_throttle;
 pub mod mk;
+pub mod opaque_login;
 pub mod password;
+pub mod refresh_token;
+pub mod rewrap_job;
+pub mod server_secret;
 pub mod session;
 pub mod user;
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use shared::error::OperationResult;
+
+use crate::models::auth::OpaqueLogin;
+use crate::DB;
+
+pub async fn create(login: OpaqueLogin<'_>) -> OperationResult<OpaqueLogin> {
+    let login: OpaqueLogin = DB.create("opaque_login").content(login).await?;
+    Ok(login)
+}
+
+pub async fn read<'a>(id: &str) -> OperationResult<Option<OpaqueLogin<'a>>> {
+    let login: Option<OpaqueLogin> = DB.select(("opaque_login", id)).await?;
+    Ok(login)
+}
+
+/// Deletes the login and returns it, so that each one can only be finished once, even by
+/// concurrent requests.
+pub async fn take<'a>(id: &str) -> OperationResult<Option<OpaqueLogin<'a>>> {
+    let login: Option<OpaqueLogin> = DB
+        .query(
+            r#"
+    DELETE type::thing("opaque_login", $id)
+    RETURN BEFORE
+    "#,
+        )
+        .bind(("id", id))
+        .await?
+        .take(0)?;
+
+    Ok(login)
+}
+
+/// Marks a re-authentication as finished. Returns `false` if it already was.
+pub async fn mark_verified(
+    login: &OpaqueLogin<'_>,
+    expires_on: DateTime<Utc>,
+) -> OperationResult<bool> {
+    if login.get_id().is_none() {
+        return Err(anyhow::format_err!("OPAQUE login ID is required").into());
+    }
+
+    let updated: Vec<OpaqueLogin> = DB
+        .query(
+            r#"
+    UPDATE opaque_login
+    SET verified_on = time::now(),
+        expires_on = $expires_on
+    WHERE id = $id AND verified_on = NONE
+    "#,
+        )
+        .bind(("expires_on", expires_on))
+        .bind(("id", login.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Uses up the proof from a finished re-authentication of the user. Returns `false` if there
+/// is no such proof, or it expired.
+pub async fn take_proof(id: &str, user_id: &str) -> OperationResult<bool> {
+    let taken: Vec<OpaqueLogin> = DB
+        .query(
+            r#"
+    DELETE opaque_login
+    WHERE id = type::thing("opaque_login", $id)
+        AND user_id = $user_id
+        AND verified_on != NONE
+        AND expires_on > time::now()
+    RETURN BEFORE
+    "#,
+        )
+        .bind(("id", id))
+        .bind(("user_id", user_id))
+        .await?
+        .take(0)?;
+
+    Ok(!taken.is_empty())
+}
@@ -31,7 +31,26 @@ pub async fn read_active_by_user_id(user_id: &str) -> OperationResult<Option<Pas
     Ok(password)
 }
 
-#[allow(dead_code)]
+/// Returns the user's `limit` most recent passwords, the active one included.
+pub async fn read_recent_by_user_id(user_id: &str, limit: u32) -> OperationResult<Vec<Password>> {
+    let passwords: Vec<Password> = DB
+        .query(
+            r#"
+        SELECT *
+        FROM password
+        WHERE user_id = $user_id
+        ORDER BY added_on DESC
+        LIMIT $limit
+    "#,
+        )
+        .bind(("user_id", user_id))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(passwords)
+}
+
 pub async fn update(password: &Password<'_>) -> EmptyResult {
     if password.get_id().is_none() {
         return Err(anyhow::format_err!("Password ID is required").into());
@@ -41,11 +60,13 @@ pub async fn update(password: &Password<'_>) -> EmptyResult {
         r#"
         UPDATE password
         SET user_id = $user_id,
+            hash = $hash,
             is_active = $is_active
         WHERE id = $id
     "#,
     )
     .bind(("user_id", &password.user_id))
+    .bind(("hash", &password.hash))
     .bind(("is_active", password.is_active))
     .bind(("id", password.get_id().full_identifier()))
     .await?;
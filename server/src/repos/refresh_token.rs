@@ -0,0 +1,103 @@
+use shared::error::{EmptyResult, OperationResult};
+
+use crate::models::auth::{RefreshToken, Session};
+use crate::DB;
+
+pub async fn create(refresh_token: RefreshToken<'_>) -> OperationResult<RefreshToken> {
+    let refresh_token: RefreshToken = DB.create("refresh_token").content(refresh_token).await?;
+    Ok(refresh_token)
+}
+
+pub async fn read_by_token_selector(selector: &str) -> OperationResult<Option<RefreshToken>> {
+    let refresh_token: Option<RefreshToken> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM refresh_token
+    WHERE token_selector = $token_selector
+    "#,
+        )
+        .bind(("token_selector", selector))
+        .await?
+        .take(0)?;
+
+    Ok(refresh_token)
+}
+
+/// Marks the refresh token as used. Returns `false` if it already was, which makes concurrent
+/// uses of the same token count as reuse.
+pub async fn mark_used(refresh_token: &RefreshToken<'_>) -> OperationResult<bool> {
+    if refresh_token.get_id().is_none() {
+        return Err(anyhow::format_err!("Refresh token ID is required").into());
+    }
+
+    let updated: Vec<RefreshToken> = DB
+        .query(
+            r#"
+    UPDATE refresh_token
+    SET used_on = time::now()
+    WHERE id = $id AND used_on = NONE
+    "#,
+        )
+        .bind(("id", refresh_token.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Revokes every refresh token and expires every session that descends from the same login.
+pub async fn revoke_family(family_id: &str) -> EmptyResult {
+    DB.query(
+        r#"
+    BEGIN TRANSACTION;
+    UPDATE refresh_token SET is_revoked = true WHERE family_id = $family_id;
+    UPDATE session SET expires_on = time::now() WHERE family_id = $family_id AND expires_on > time::now();
+    COMMIT TRANSACTION;
+    "#,
+    )
+    .bind(("family_id", family_id))
+    .await?;
+
+    Ok(())
+}
+
+/// Revokes every refresh token and expires every session of the user.
+pub async fn revoke_all_by_user_id(user_id: &str) -> EmptyResult {
+    DB.query(
+        r#"
+    BEGIN TRANSACTION;
+    UPDATE refresh_token SET is_revoked = true WHERE user_id = $user_id;
+    UPDATE session SET expires_on = time::now() WHERE user_id = $user_id AND expires_on > time::now();
+    COMMIT TRANSACTION;
+    "#,
+    )
+    .bind(("user_id", user_id))
+    .await?;
+
+    Ok(())
+}
+
+/// Revokes every refresh token and expires every session of the user, except `session` and
+/// the ones that descend from the same login. Sessions from before refresh tokens have no
+/// family, so only `session` itself is kept for them.
+pub async fn revoke_all_by_user_id_except(session: &Session<'_>) -> EmptyResult {
+    if session.get_id().is_none() {
+        return Err(anyhow::format_err!("Session ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    BEGIN TRANSACTION;
+    UPDATE refresh_token SET is_revoked = true WHERE user_id = $user_id AND ($family_id = "" OR family_id != $family_id);
+    UPDATE session SET expires_on = time::now() WHERE user_id = $user_id AND id != $session_id AND ($family_id = "" OR family_id != $family_id) AND expires_on > time::now();
+    COMMIT TRANSACTION;
+    "#,
+    )
+    .bind(("user_id", &session.user_id))
+    .bind(("family_id", &session.family_id))
+    .bind(("session_id", session.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use shared::error::{EmptyResult, OperationResult};
+
+use crate::models::crypto::RewrapJob;
+use crate::DB;
+
+pub async fn read_pending<'a>() -> OperationResult<Option<RewrapJob<'a>>> {
+    let job: Option<RewrapJob> = DB
+        .query(
+            r#"
+        SELECT *
+        FROM rewrap_job
+        WHERE completed_on = NONE
+    "#,
+        )
+        .await?
+        .take(0)?;
+
+    Ok(job)
+}
+
+pub async fn update(job: &RewrapJob<'_>) -> EmptyResult {
+    if job.get_id().is_none() {
+        return Err(anyhow::format_err!("Rewrap job ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE rewrap_job
+    SET master_key_id = $master_key_id,
+        started_on = $started_on,
+        completed_on = $completed_on,
+        kek_cursor = $kek_cursor,
+        user_cursor = $user_cursor,
+        file_cursor = $file_cursor,
+        rewrapped_keys = $rewrapped_keys,
+        rewrapped_values = $rewrapped_values,
+        rewrapped_files = $rewrapped_files
+    WHERE id = $id
+    "#,
+    )
+    .bind(("master_key_id", &job.master_key_id))
+    .bind(("started_on", job.started_on))
+    .bind(("completed_on", job.completed_on))
+    .bind(("kek_cursor", &job.kek_cursor))
+    .bind(("user_cursor", &job.user_cursor))
+    .bind(("file_cursor", &job.file_cursor))
+    .bind(("rewrapped_keys", job.rewrapped_keys))
+    .bind(("rewrapped_values", job.rewrapped_values))
+    .bind(("rewrapped_files", job.rewrapped_files))
+    .bind(("id", job.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use crate::models::crypto::ServerSecret;
+use crate::DB;
+use shared::error::OperationResult;
+
+pub async fn create_with_id<'a>(
+    id: &str,
+    secret: ServerSecret<'_>,
+) -> OperationResult<ServerSecret<'a>> {
+    let secret: ServerSecret = DB.create(("server_secret", id)).content(secret).await?;
+    Ok(secret)
+}
+
+pub async fn read<'a>(id: &str) -> OperationResult<Option<ServerSecret<'a>>> {
+    let secret: Option<ServerSecret> = DB.select(("server_secret", id)).await?;
+    Ok(secret)
+}
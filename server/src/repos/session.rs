@@ -13,6 +13,22 @@ pub async fn read(id: &str) -> OperationResult<Option<Session>> {
     Ok(session)
 }
 
+pub async fn read_by_token_selector<'a>(selector: &str) -> OperationResult<Option<Session<'a>>> {
+    let session: Option<Session> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM session
+    WHERE token_selector = $token_selector
+    "#,
+        )
+        .bind(("token_selector", selector))
+        .await?
+        .take(0)?;
+
+    Ok(session)
+}
+
 pub async fn read_all_by_user_id(user_id: &str) -> OperationResult<Vec<Session>> {
     let sessions: Vec<Session> = DB
         .query(
@@ -39,13 +55,66 @@ pub async fn update(session: &Session<'_>) -> EmptyResult {
     UPDATE session
     SET user_id = $user_id,
         last_used_on = $last_used_on,
-        expires_on = $expires_on
+        expires_on = $expires_on,
+        mfa_attempts = $mfa_attempts
     WHERE id = $id
     "#,
     )
     .bind(("user_id", &session.user_id))
     .bind(("last_used_on", session.last_used_on))
     .bind(("expires_on", session.expires_on))
+    .bind(("mfa_attempts", session.mfa_attempts))
+    .bind(("id", session.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Counts an attempt at the second factor, and expires the session with the attempt that
+/// reaches `max_attempts`. Returns the new count. It is a single statement, so that concurrent
+/// attempts are all counted.
+pub async fn record_mfa_attempt(session: &Session<'_>, max_attempts: u32) -> OperationResult<u32> {
+    if session.get_id().is_none() {
+        return Err(anyhow::format_err!("Session ID is required").into());
+    }
+
+    let updated: Option<Session> = DB
+        .query(
+            r#"
+    UPDATE session
+    SET mfa_attempts += 1,
+        expires_on = IF mfa_attempts >= $max_attempts THEN time::now() ELSE expires_on END
+    WHERE id = $id
+    RETURN AFTER
+    "#,
+        )
+        .bind(("max_attempts", max_attempts))
+        .bind(("id", session.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    updated
+        .map(|s| s.mfa_attempts)
+        .ok_or_else(|| anyhow::Error::msg("session_not_found").into())
+}
+
+/// Writes the activity timestamps alone, and only while the session is still valid, so that it
+/// can't bring back a session that was revoked in the meantime.
+pub async fn touch(session: &Session<'_>) -> EmptyResult {
+    if session.get_id().is_none() {
+        return Err(anyhow::format_err!("Session ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE session
+    SET last_used_on = $last_used_on,
+        expires_on = $expires_on
+    WHERE id = $id AND expires_on > time::now()
+    "#,
+    )
+    .bind(("last_used_on", session.last_used_on))
+    .bind(("expires_on", session.expires_on))
     .bind(("id", session.get_id().full_identifier()))
     .await?;
 
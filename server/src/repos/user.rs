@@ -1,4 +1,4 @@
-use crate::models::auth::User;
+use crate::models::auth::{Session, User};
 use crate::DB;
 use shared::error::{EmptyResult, OperationResult};
 
@@ -8,7 +8,7 @@ pub async fn create(user: User<'_>) -> OperationResult<User> {
 }
 
 #[allow(dead_code)]
-pub async fn read(id: &str) -> OperationResult<Option<User>> {
+pub async fn read<'a>(id: &str) -> OperationResult<Option<User<'a>>> {
     let user: Option<User> = DB.select(("user", id)).await?;
     Ok(user)
 }
@@ -29,7 +29,127 @@ pub async fn read_by_username(username: &str) -> OperationResult<Option<User>> {
     Ok(user)
 }
 
-pub async fn update(user: &User<'_>) -> EmptyResult {
+pub async fn read_batch_after<'a>(
+    cursor: Option<&str>,
+    limit: u32,
+) -> OperationResult<Vec<User<'a>>> {
+    let users: Vec<User> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM user
+    WHERE $cursor = NONE OR id > type::thing("user", $cursor)
+    ORDER BY id
+    LIMIT $limit
+    "#,
+        )
+        .bind(("cursor", cursor))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(users)
+}
+
+/// Pages through the users whose username contains `query`, ignoring case.
+pub async fn search<'a>(
+    query: Option<&str>,
+    cursor: Option<&str>,
+    limit: u32,
+) -> OperationResult<Vec<User<'a>>> {
+    let users: Vec<User> = DB
+        .query(
+            r#"
+    SELECT *
+    FROM user
+    WHERE ($query = NONE OR string::contains(string::lowercase(username), $query))
+        AND ($cursor = NONE OR id > type::thing("user", $cursor))
+    ORDER BY id
+    LIMIT $limit
+    "#,
+        )
+        .bind(("query", query.map(|q| q.to_lowercase())))
+        .bind(("cursor", cursor))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(users)
+}
+
+/// Sets the key encryption key of a user that has none yet. Returns `false` if they got one in
+/// the meantime.
+pub async fn set_kek_id(user: &User<'_>, kek_id: &str) -> OperationResult<bool> {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    let updated: Vec<User> = DB
+        .query(
+            r#"
+    UPDATE user
+    SET kek_id = $kek_id
+    WHERE id = $id AND kek_id = NONE
+    "#,
+        )
+        .bind(("kek_id", kek_id))
+        .bind(("id", user.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Marks the TOTP time step `step` as used, unless it or a later one already is. Returns whether
+/// it was this call that used it, so that a code can't be accepted twice by concurrent requests.
+pub async fn use_totp_step(user: &User<'_>, step: i64) -> OperationResult<bool> {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    let updated: Vec<User> = DB
+        .query(
+            r#"
+    UPDATE user
+    SET totp_last_step = $step
+    WHERE id = $id AND totp_last_step < $step
+    "#,
+        )
+        .bind(("step", step))
+        .bind(("id", user.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Removes the recovery code with `hash`, unless it is gone already. Returns whether it was this
+/// call that removed it.
+pub async fn use_recovery_code(user: &User<'_>, hash: &[u8]) -> OperationResult<bool> {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    let updated: Vec<User> = DB
+        .query(
+            r#"
+    UPDATE user
+    SET recovery_codes -= $hash
+    WHERE id = $id AND recovery_codes CONTAINS $hash
+    "#,
+        )
+        .bind(("hash", hash))
+        .bind(("id", user.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Lists `session` among the user's sessions and writes `last_seen_on`. The list is rebuilt from
+/// the sessions that are still valid, so that it drops the ones that expired and the ones that
+/// `session` replaces in its family, instead of growing with every login and refresh.
+pub async fn add_session(user: &User<'_>, session: &Session<'_>) -> EmptyResult {
     if user.get_id().is_none() {
         return Err(anyhow::format_err!("User ID is required").into());
     }
@@ -37,16 +157,254 @@ pub async fn update(user: &User<'_>) -> EmptyResult {
     DB.query(
         r#"
     UPDATE user
-    SET passwords = $passwords,
-        sessions = $sessions,
-        last_seen_on = $last_seen_on,
-        is_active = $is_active
-    WHERE id = $id
+    SET sessions = (
+            SELECT VALUE <string> id
+            FROM session
+            WHERE user_id = $user_id
+                AND is_mfa_pending = false
+                AND expires_on > time::now()
+                AND (family_id != $family_id OR id = $session_id)
+        ),
+        last_seen_on = $last_seen_on
+    WHERE id = $user_id
     "#,
     )
-    .bind(("passwords", &user.passwords))
-    .bind(("sessions", &user.sessions))
+    .bind(("family_id", &session.family_id))
+    .bind(("session_id", session.get_id().full_identifier()))
     .bind(("last_seen_on", user.last_seen_on))
+    .bind(("user_id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Stores a new TOTP secret with its recovery codes, unless TOTP is already enabled. Returns
+/// whether it was stored.
+pub async fn enroll_totp(user: &User<'_>) -> OperationResult<bool> {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    let updated: Vec<User> = DB
+        .query(
+            r#"
+    UPDATE user
+    SET totp_secret = $totp_secret,
+        totp_last_step = 0,
+        recovery_codes = $recovery_codes
+    WHERE id = $id AND is_totp_enabled = false
+    "#,
+        )
+        .bind(("totp_secret", &user.totp_secret))
+        .bind(("recovery_codes", &user.recovery_codes))
+        .bind(("id", user.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Enables TOTP with the code from time step `step`, which is used up like in `use_totp_step`.
+/// Returns whether it was this call that enabled it.
+pub async fn enable_totp(user: &User<'_>, step: i64) -> OperationResult<bool> {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    let updated: Vec<User> = DB
+        .query(
+            r#"
+    UPDATE user
+    SET is_totp_enabled = true,
+        totp_last_step = $step
+    WHERE id = $id AND is_totp_enabled = false AND totp_last_step < $step
+    "#,
+        )
+        .bind(("step", step))
+        .bind(("id", user.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Turns TOTP off and forgets the secret and the recovery codes.
+pub async fn disable_totp(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET totp_secret = NONE,
+        is_totp_enabled = false,
+        totp_last_step = 0,
+        recovery_codes = []
+    WHERE id = $id
+    "#,
+    )
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Adds a new password to the user's history. Changing the password is what an admin asks for
+/// with `is_password_reset_required`, so that is cleared as well.
+pub async fn add_password(user: &User<'_>, password_id: &str) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET passwords += $password_id,
+        is_password_reset_required = false
+    WHERE id = $id
+    "#,
+    )
+    .bind(("password_id", password_id))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes `opaque_registration`, and `keyring` along with it if it is set, since the keyring is
+/// sealed with the password the registration is for. `is_password_change` clears
+/// `is_password_reset_required`.
+pub async fn update_opaque_registration(user: &User<'_>, is_password_change: bool) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET opaque_registration = $opaque_registration,
+        keyring = IF $keyring = NONE THEN keyring ELSE $keyring END,
+        is_password_reset_required = IF $is_password_change
+            THEN false
+            ELSE is_password_reset_required
+            END
+    WHERE id = $id
+    "#,
+    )
+    .bind(("opaque_registration", &user.opaque_registration))
+    .bind(("keyring", &user.keyring))
+    .bind(("is_password_change", is_password_change))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes everything an account recovery replaces: the OPAQUE registration, the keyring sealed
+/// for it and the recovery key. Clears `is_password_reset_required`.
+pub async fn update_recovered_credentials(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET opaque_registration = $opaque_registration,
+        keyring = $keyring,
+        recovery_key = $recovery_key,
+        is_password_reset_required = false
+    WHERE id = $id
+    "#,
+    )
+    .bind(("opaque_registration", &user.opaque_registration))
+    .bind(("keyring", &user.keyring))
+    .bind(("recovery_key", &user.recovery_key))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Sets the keyring, and the recovery key that goes with it, of a user that has none yet.
+/// Returns `false` if they got one in the meantime.
+pub async fn set_keyring(user: &User<'_>) -> OperationResult<bool> {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    let updated: Vec<User> = DB
+        .query(
+            r#"
+    UPDATE user
+    SET keyring = $keyring,
+        recovery_key = $recovery_key
+    WHERE id = $id AND keyring = NONE
+    "#,
+        )
+        .bind(("keyring", &user.keyring))
+        .bind(("recovery_key", &user.recovery_key))
+        .bind(("id", user.get_id().full_identifier()))
+        .await?
+        .take(0)?;
+
+    Ok(!updated.is_empty())
+}
+
+/// Writes `recovery_key` alone.
+pub async fn update_recovery_key(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET recovery_key = $recovery_key
+    WHERE id = $id
+    "#,
+    )
+    .bind(("recovery_key", &user.recovery_key))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes `is_email_verified` alone.
+pub async fn update_is_email_verified(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET is_email_verified = $is_email_verified
+    WHERE id = $id
+    "#,
+    )
+    .bind(("is_email_verified", user.is_email_verified))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes `is_active` alone.
+pub async fn update_is_active(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET is_active = $is_active
+    WHERE id = $id
+    "#,
+    )
     .bind(("is_active", user.is_active))
     .bind(("id", user.get_id().full_identifier()))
     .await?;
@@ -54,9 +412,116 @@ pub async fn update(user: &User<'_>) -> EmptyResult {
     Ok(())
 }
 
-#[allow(dead_code)]
-pub async fn delete(id: &str) -> EmptyResult {
-    // TODO: Also delete all passwords associated with this user
-    DB.delete(("user", id)).await?;
+/// Writes `role` alone.
+pub async fn update_role(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET role = $role
+    WHERE id = $id
+    "#,
+    )
+    .bind(("role", user.role))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes `is_password_reset_required` alone.
+pub async fn update_is_password_reset_required(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET is_password_reset_required = $is_password_reset_required
+    WHERE id = $id
+    "#,
+    )
+    .bind((
+        "is_password_reset_required",
+        user.is_password_reset_required,
+    ))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes `email` alone, so that a background job can't undo a change made to the user in the
+/// meantime.
+pub async fn update_email(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET email = $email
+    WHERE id = $id
+    "#,
+    )
+    .bind(("email", &user.email))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Writes `last_seen_on` alone, so that it can't undo a change made to the user in the meantime.
+pub async fn update_last_seen_on(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    UPDATE user
+    SET last_seen_on = $last_seen_on
+    WHERE id = $id
+    "#,
+    )
+    .bind(("last_seen_on", user.last_seen_on))
+    .bind(("id", user.get_id().full_identifier()))
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the user with everything that belongs to them. Destroying the key encryption key
+/// makes any copy of their encrypted values and files left behind unrecoverable.
+pub async fn delete(user: &User<'_>) -> EmptyResult {
+    if user.get_id().is_none() {
+        return Err(anyhow::format_err!("User ID is required").into());
+    }
+
+    DB.query(
+        r#"
+    BEGIN TRANSACTION;
+    DELETE key_encryption_key WHERE id = $kek_id;
+    DELETE password WHERE user_id = $user_id;
+    DELETE session WHERE user_id = $user_id;
+    DELETE refresh_token WHERE user_id = $user_id;
+    DELETE access_token WHERE user_id = $user_id;
+    DELETE opaque_login WHERE user_id = $user_id;
+    DELETE account_recovery WHERE user_id = $user_id;
+    DELETE email_token WHERE user_id = $user_id;
+    DELETE file WHERE user_id = $user_id;
+    DELETE user WHERE id = $user_id;
+    COMMIT TRANSACTION;
+    "#,
+    )
+    .bind(("kek_id", &user.kek_id))
+    .bind(("user_id", user.get_id().full_identifier()))
+    .await?;
+
     Ok(())
 }
@@ -0,0 +1,28 @@
+use shared::error::ValidationResult;
+use singleton::unsync::Singleton as UnsyncSingleton;
+
+use crate::config::Settings;
+use crate::models::auth::Scope;
+
+pub fn access_token(name: &str, scopes: &[String], lifetime_days: u32) -> ValidationResult {
+    let mut errors = Vec::new();
+
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        errors.push("invalid_access_token__name_length".to_string());
+    }
+
+    if scopes.is_empty() {
+        errors.push("invalid_access_token__scopes_empty".to_string());
+    }
+
+    if scopes.iter().any(|s| Scope::parse(s).is_none()) {
+        errors.push("invalid_access_token__scope_unknown".to_string());
+    }
+
+    if lifetime_days == 0 || lifetime_days > Settings::get().access_token.max_lifetime_days {
+        errors.push("invalid_access_token__lifetime".to_string());
+    }
+
+    ValidationResult(errors)
+}
@@ -0,0 +1,27 @@
+use shared::error::ValidationResult;
+
+pub fn file_name(name: &str) -> ValidationResult {
+    let mut errors = Vec::new();
+
+    if name.is_empty() || name.len() > 255 {
+        errors.push("invalid_file__name_length".to_string());
+    }
+
+    if name.contains(['/', '\\']) || name.chars().any(char::is_control) {
+        errors.push("invalid_file__name_characters".to_string());
+    }
+
+    ValidationResult(errors)
+}
+
+/// A DEK the client wrapped to the user's public key. Its layout is up to the client, so only
+/// its size is checked.
+pub fn wrapped_dek(wrapped_dek: &[u8]) -> ValidationResult {
+    let mut errors = Vec::new();
+
+    if wrapped_dek.is_empty() || wrapped_dek.len() > 1024 {
+        errors.push("invalid_file__wrapped_dek_length".to_string());
+    }
+
+    ValidationResult(errors)
+}
@@ -0,0 +1,59 @@
+use protobuf::pandorica_common;
+use shared::error::ValidationResult;
+
+/// X25519 and Ed25519 keys alike.
+const KEY_LENGTH: usize = 32;
+/// Both private keys, sealed with XChaCha20-Poly1305.
+const ENCRYPTED_PRIVATE_KEYS_LENGTH: usize = 2 * KEY_LENGTH + 16;
+
+pub fn keyring(keyring: &pandorica_common::Keyring) -> ValidationResult {
+    let mut errors = Vec::new();
+
+    if keyring.public_key.len() != KEY_LENGTH || keyring.signing_public_key.len() != KEY_LENGTH {
+        errors.push("invalid_keyring__public_key_length".to_string());
+    }
+
+    if keyring.salt.len() < 16 || keyring.salt.len() > 64 {
+        errors.push("invalid_keyring__salt_length".to_string());
+    }
+
+    // Anything weaker makes the password easy to guess from the keyring, and anything much
+    // stronger makes every client unlocking it run out of memory
+    if keyring.scrypt_log_n < 15
+        || keyring.scrypt_log_n > 20
+        || keyring.scrypt_r == 0
+        || keyring.scrypt_r > 32
+        || keyring.scrypt_p == 0
+        || keyring.scrypt_p > 16
+    {
+        errors.push("invalid_keyring__scrypt_params".to_string());
+    }
+
+    if keyring.nonce.len() != 24 {
+        errors.push("invalid_keyring__nonce_length".to_string());
+    }
+
+    if keyring.encrypted_private_keys.len() != ENCRYPTED_PRIVATE_KEYS_LENGTH {
+        errors.push("invalid_keyring__private_keys_length".to_string());
+    }
+
+    ValidationResult(errors)
+}
+
+pub fn recovery_key(recovery_key: &pandorica_common::RecoveryKey) -> ValidationResult {
+    let mut errors = Vec::new();
+
+    if recovery_key.verifying_key.len() != KEY_LENGTH {
+        errors.push("invalid_recovery_key__verifying_key_length".to_string());
+    }
+
+    if recovery_key.nonce.len() != 24 {
+        errors.push("invalid_recovery_key__nonce_length".to_string());
+    }
+
+    if recovery_key.encrypted_private_keys.len() != ENCRYPTED_PRIVATE_KEYS_LENGTH {
+        errors.push("invalid_recovery_key__private_keys_length".to_string());
+    }
+
+    ValidationResult(errors)
+}
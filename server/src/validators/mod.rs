@@ -1,8 +1,17 @@
+pub use access_token::access_token;
 pub use email::email;
+pub use file::file_name;
+pub use file::wrapped_dek;
+pub use keyring::keyring;
+pub use keyring::recovery_key;
+pub use password::breached_password;
 pub use password::password;
 pub use username::username_duplicate;
 pub use username::username_format;
 
+mod access_token;
 mod email;
+mod file;
+mod keyring;
 mod password;
 mod username;
@@ -1,6 +1,10 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
-use shared::error::ValidationResult;
+use shared::error::{OperationResult, ValidationResult};
+use singleton::unsync::Singleton as UnsyncSingleton;
+
+use crate::config::Settings;
+use crate::helpers::breach_filter::BreachFilter;
 
 static PASSWORD_UPPER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"[A-Z]+").unwrap());
 static PASSWORD_LOWER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"[a-z]+").unwrap());
@@ -8,28 +12,77 @@ static PASSWORD_DIGIT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"[0-9]+").un
 static PASSWORD_SPECIAL_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"[ !"$%&'()*+,-./:;<=>?@\[\\\]^_`{|}~]+"#).unwrap());
 
-pub fn password(password: &str) -> ValidationResult {
+/// Checks a new password against the configured policy. `user_inputs`, such as the username,
+/// make the strength estimate treat passwords derived from them as weak.
+pub fn password(password: &str, user_inputs: &[&str]) -> ValidationResult {
+    let policy = &Settings::get().security.password;
     let mut errors: Vec<String> = Vec::new();
 
-    if password.len() < 8_usize {
+    let length = password.chars().count();
+    if length < policy.min_length || length > policy.max_length {
         errors.push("invalid_password__length".to_string());
     }
 
-    if !PASSWORD_UPPER_REGEX.is_match(password) {
+    if policy.require_uppercase && !PASSWORD_UPPER_REGEX.is_match(password) {
         errors.push("invalid_password__uppercase".to_string());
     }
 
-    if !PASSWORD_LOWER_REGEX.is_match(password) {
+    if policy.require_lowercase && !PASSWORD_LOWER_REGEX.is_match(password) {
         errors.push("invalid_password__lowercase".to_string());
     }
 
-    if !PASSWORD_DIGIT_REGEX.is_match(password) {
+    if policy.require_digit && !PASSWORD_DIGIT_REGEX.is_match(password) {
         errors.push("invalid_password__digit".to_string());
     }
 
-    if !PASSWORD_SPECIAL_REGEX.is_match(password) {
+    if policy.require_special && !PASSWORD_SPECIAL_REGEX.is_match(password) {
         errors.push("invalid_password__special".to_string());
     }
 
+    if let Some(min_score) = policy.min_strength_score {
+        errors.extend(strength(password, user_inputs, min_score));
+    }
+
     ValidationResult(errors)
 }
+
+fn strength(password: &str, user_inputs: &[&str], min_score: u8) -> Option<String> {
+    let score = zxcvbn::zxcvbn(password, user_inputs)
+        .map(|e| e.score())
+        .unwrap_or(0);
+    if score < min_score {
+        return Some("invalid_password__strength".to_string());
+    }
+
+    None
+}
+
+/// Rejects passwords that appear in the breach corpus the configured filter was built from. A
+/// filter that can't be read is an error of its own, rather than a verdict on the password.
+pub async fn breached_password(password: &str) -> OperationResult<ValidationResult> {
+    let mut errors: Vec<String> = Vec::new();
+
+    if BreachFilter::get().contains(password).await? {
+        errors.push("invalid_password__breached".to_string());
+    }
+
+    Ok(ValidationResult(errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weak_passwords_are_rejected() {
+        assert_eq!(
+            strength("Password1!", &[], 3),
+            Some("invalid_password__strength".to_string())
+        );
+        assert_eq!(strength("Password1!", &[], 0), None);
+        assert_eq!(
+            strength("correct horse battery staple vortex", &[], 4),
+            None
+        );
+    }
+}